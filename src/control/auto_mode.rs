@@ -0,0 +1,101 @@
+use crate::control::rail_system::components::Address;
+use crate::general::AddressType;
+use petgraph::graph::NodeIndex;
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Keeps the trains of a railroad circulating on their own, by driving every idle train to
+/// a random free station it is allowed to drive to.
+///
+/// A train is idle, while it stands without a route and without a running timetable.
+/// It departs again, after it was idle for the minimum dwell time.
+/// Started with [Railroad::start_auto_mode](crate::control::rail_system::railroad::Railroad::start_auto_mode)
+/// and run by the scheduler of the railroad.
+///
+/// # Usage
+///
+/// ```
+/// # use std::time::Duration;
+/// # use petgraph::graph::NodeIndex;
+/// # use locologic::control::auto_mode::AutoMode;
+/// # use locologic::control::rail_system::components::Address;
+/// let auto_mode = AutoMode::<u16>::new(Duration::from_secs(30))
+///     .allow(Address::new(1), vec![NodeIndex::new(4), NodeIndex::new(7)]);
+/// assert!(auto_mode.allows(Address::new(1), NodeIndex::new(4)));
+/// assert!(!auto_mode.allows(Address::new(1), NodeIndex::new(5)));
+/// assert!(auto_mode.allows(Address::new(2), NodeIndex::new(5)));
+/// ```
+#[derive(Debug, Clone)]
+pub struct AutoMode<TrainAddr: AddressType> {
+    /// The stations a train may drive to. Trains without an entry may drive to every station.
+    allowed: HashMap<Address<TrainAddr>, Vec<NodeIndex>>,
+    /// The time a train stays in a station at least
+    min_dwell: Duration,
+    /// The time every idle train is idle since
+    idle_since: HashMap<Address<TrainAddr>, Instant>,
+    /// The state of the generator picking the stations at random
+    random: u64,
+}
+
+impl<TrainAddr: AddressType> AutoMode<TrainAddr> {
+    pub fn new(min_dwell: Duration) -> Self {
+        AutoMode {
+            allowed: HashMap::new(),
+            min_dwell,
+            idle_since: HashMap::new(),
+            random: 0x9E37_79B9_7F4A_7C15,
+        }
+    }
+
+    /// Restricts the stations the train may drive to.
+    pub fn allow(mut self, train: Address<TrainAddr>, stations: Vec<NodeIndex>) -> Self {
+        self.allowed.insert(train, stations);
+        self
+    }
+
+    /// Seeds the generator picking the stations, so the trains drive the same way every time.
+    pub fn seed(mut self, seed: u64) -> Self {
+        // The generator never leaves a zero state.
+        self.random = seed.max(1);
+        self
+    }
+
+    pub fn min_dwell(&self) -> Duration {
+        self.min_dwell
+    }
+
+    /// Checks if the train may drive to the station.
+    pub fn allows(&self, train: Address<TrainAddr>, station: NodeIndex) -> bool {
+        self.allowed
+            .get(&train)
+            .is_none_or(|stations| stations.contains(&station))
+    }
+
+    /// Tracks how long the train is idle already.
+    /// Returns `true`, if it is idle for at least the minimum dwell time.
+    pub(crate) fn dwelled(&mut self, train: Address<TrainAddr>, idle: bool) -> bool {
+        if !idle {
+            self.idle_since.remove(&train);
+            return false;
+        }
+        let since = self.idle_since.entry(train).or_insert_with(Instant::now);
+        since.elapsed() >= self.min_dwell
+    }
+
+    /// Shuffles the stations, so they are tried in a random order.
+    pub(crate) fn shuffle(&mut self, stations: &mut [NodeIndex]) {
+        for i in (1..stations.len()).rev() {
+            let j = (self.next_random() % (i as u64 + 1)) as usize;
+            stations.swap(i, j);
+        }
+    }
+
+    /// Returns the next number of a xorshift generator.
+    fn next_random(&mut self) -> u64 {
+        self.random ^= self.random << 13;
+        self.random ^= self.random >> 7;
+        self.random ^= self.random << 17;
+        self.random
+    }
+}
@@ -0,0 +1,73 @@
+use crate::control::auto_mode::AutoMode;
+use crate::control::rail_system::components::Address;
+use crate::control::rail_system::railroad_test::{
+    create_station_fork_railroad, create_station_loop_railroad, start_simulation,
+};
+use std::collections::HashSet;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+
+#[tokio::test(start_paused = true)]
+pub async fn test_auto_mode_circulates() {
+    let (r, stations) = create_station_loop_railroad().await;
+    let tasks = start_simulation(&r).await;
+    let dwell = Duration::from_secs(2);
+    r.start_auto_mode(AutoMode::new(dwell).seed(7)).await;
+    assert!(r.auto_mode_running().await);
+
+    // Samples the train, counting how long it stood in every station.
+    let train = r.get_train(&Address::new(1)).unwrap();
+    let mut visited = HashSet::new();
+    let mut stays = vec![Duration::ZERO];
+    for _ in 0..300 {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let train = train.lock().await;
+        if train.route().is_none() {
+            *stays.last_mut().unwrap() += Duration::from_millis(100);
+            visited.insert(train.position());
+        } else if !stays.last().unwrap().is_zero() {
+            stays.push(Duration::ZERO);
+        }
+    }
+
+    assert_eq!(visited, HashSet::from(stations));
+    assert!(stays.len() > 3);
+    // The last stay may not be over yet.
+    assert!(stays[..stays.len() - 1].iter().all(|stay| *stay >= dwell));
+
+    // Stopped, the train stays, where it arrives next.
+    r.stop_auto_mode().await;
+    tokio::time::sleep(Duration::from_secs(5)).await;
+    let position = train.lock().await.position();
+    tokio::time::sleep(Duration::from_secs(5)).await;
+    assert_eq!(train.lock().await.position(), position);
+    assert_eq!(train.lock().await.route(), None);
+
+    tasks.iter().for_each(JoinHandle::abort);
+}
+
+#[tokio::test(start_paused = true)]
+pub async fn test_auto_mode_picks_free_stations() {
+    let (r, [_first, straight, curved]) = create_station_fork_railroad().await;
+    let tasks = start_simulation(&r).await;
+    r.start_auto_mode(
+        AutoMode::new(Duration::from_secs(1))
+            .allow(Address::new(1), vec![straight, curved])
+            .allow(Address::new(2), vec![]),
+    )
+    .await;
+
+    let first_train = r.get_train(&Address::new(1)).unwrap();
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    assert_eq!(first_train.lock().await.route(), None);
+
+    // The first station is taken by the second train.
+    tokio::time::sleep(Duration::from_secs(5)).await;
+    let train = first_train.lock().await;
+    assert_eq!(train.position(), curved);
+    let second_train = r.get_train(&Address::new(2)).unwrap().lock().await;
+    assert_eq!(second_train.position(), straight);
+    assert_eq!(second_train.route(), None);
+
+    tasks.iter().for_each(JoinHandle::abort);
+}
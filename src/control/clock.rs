@@ -0,0 +1,168 @@
+use serde::{Deserialize, Serialize};
+use std::fmt::{Display, Formatter};
+use std::time::Duration;
+use tokio::time::Instant;
+
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// A time of the model world, measured from midnight of its first day.
+///
+/// # Usage
+///
+/// ```
+/// # use locologic::control::clock::ModelTime;
+/// let time = ModelTime::new(1, 7, 42);
+/// assert_eq!((time.day(), time.hour(), time.minute()), (1, 7, 42));
+/// assert_eq!(time.to_string(), "07:42");
+/// ```
+#[derive(
+    Debug, Copy, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+pub struct ModelTime(Duration);
+
+impl ModelTime {
+    /// Creates the time of the given day. Hours beyond a day continue on the following days.
+    pub fn new(day: u32, hour: u32, minute: u32) -> Self {
+        let minutes = (day as u64 * 24 + hour as u64) * 60 + minute as u64;
+        ModelTime(Duration::from_secs(minutes * 60))
+    }
+
+    /// Creates the time on the first day.
+    pub fn at(hour: u32, minute: u32) -> Self {
+        ModelTime::new(0, hour, minute)
+    }
+
+    pub fn from_duration(since_midnight: Duration) -> Self {
+        ModelTime(since_midnight)
+    }
+
+    /// The time passed since midnight of the first day.
+    pub fn as_duration(&self) -> Duration {
+        self.0
+    }
+
+    pub fn day(&self) -> u32 {
+        (self.0.as_secs() / DAY.as_secs()) as u32
+    }
+
+    pub fn hour(&self) -> u32 {
+        (self.time_of_day().as_secs() / 3600) as u32
+    }
+
+    pub fn minute(&self) -> u32 {
+        (self.time_of_day().as_secs() / 60 % 60) as u32
+    }
+
+    /// The time passed since midnight of the current day.
+    pub fn time_of_day(&self) -> Duration {
+        Duration::new(self.0.as_secs() % DAY.as_secs(), self.0.subsec_nanos())
+    }
+
+    /// Returns the first time at or after this one with the time of day of `time`.
+    pub fn next(&self, time: ModelTime) -> ModelTime {
+        let midnight = self.0 - self.time_of_day();
+        let next = midnight + time.time_of_day();
+        if next < self.0 {
+            ModelTime(next + DAY)
+        } else {
+            ModelTime(next)
+        }
+    }
+}
+
+impl Display for ModelTime {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:02}:{:02}", self.hour(), self.minute())
+    }
+}
+
+/// The fast clock of a layout, running the model time faster than the real time.
+///
+/// A ratio of `6` runs the clock 1:6, so one real minute passes six model minutes.
+/// The clock of a [Railroad](crate::control::rail_system::railroad::Railroad) is set with
+/// [Railroad::set_clock](crate::control::rail_system::railroad::Railroad::set_clock).
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct Clock {
+    /// The model time, when the clock was set, resumed or changed its ratio last
+    time: ModelTime,
+    /// The real time the clock runs since, or `None`, if it is paused
+    running_since: Option<Instant>,
+    /// The model seconds passing per real second
+    ratio: u8,
+}
+
+impl Default for Clock {
+    /// A paused clock at midnight, that runs with the real time.
+    fn default() -> Self {
+        Clock::new(ModelTime::default(), 1)
+    }
+}
+
+impl Clock {
+    /// Creates a paused clock.
+    pub fn new(time: ModelTime, ratio: u8) -> Self {
+        Clock {
+            time,
+            running_since: None,
+            ratio,
+        }
+    }
+
+    /// Returns the current model time.
+    pub fn time(&self) -> ModelTime {
+        match self.running_since {
+            Some(since) => ModelTime(self.time.0 + since.elapsed() * self.ratio as u32),
+            None => self.time,
+        }
+    }
+
+    pub fn set_time(&mut self, time: ModelTime) {
+        self.time = time;
+        self.running_since = self.running_since.map(|_| Instant::now());
+    }
+
+    pub fn ratio(&self) -> u8 {
+        self.ratio
+    }
+
+    /// Sets the model seconds passing per real second, keeping the current model time.
+    pub fn set_ratio(&mut self, ratio: u8) {
+        self.set_time(self.time());
+        self.ratio = ratio;
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running_since.is_some()
+    }
+
+    pub fn pause(&mut self) {
+        self.time = self.time();
+        self.running_since = None;
+    }
+
+    pub fn resume(&mut self) {
+        if self.running_since.is_none() {
+            self.running_since = Some(Instant::now());
+        }
+    }
+
+    /// Sets the time and the ratio the clock runs with, pausing it for a ratio of `0`.
+    pub fn sync(&mut self, time: ModelTime, rate: u8) {
+        if rate == 0 {
+            self.pause();
+        } else {
+            self.set_ratio(rate);
+            self.resume();
+        }
+        self.set_time(time);
+    }
+
+    /// The ratio the clock runs with at the moment, `0` while it is paused.
+    pub fn rate(&self) -> u8 {
+        if self.is_running() {
+            self.ratio
+        } else {
+            0
+        }
+    }
+}
@@ -0,0 +1,97 @@
+use crate::control::clock::{Clock, ModelTime};
+use crate::control::messages::Message;
+use crate::control::rail_system::railroad::Railroad;
+use crate::control::rail_system::railroad_test::create_line_railroad;
+use crate::control::train::{Station, WaitingReasons, WaitingState};
+use std::time::Duration;
+
+#[test]
+pub fn test_model_time() {
+    let time = ModelTime::new(2, 25, 7);
+    assert_eq!((time.day(), time.hour(), time.minute()), (3, 1, 7));
+    assert_eq!(time.to_string(), "01:07");
+
+    let evening = ModelTime::at(23, 50);
+    assert_eq!(evening.next(ModelTime::at(23, 55)), ModelTime::at(23, 55));
+    assert_eq!(evening.next(ModelTime::at(23, 50)), evening);
+    assert_eq!(evening.next(ModelTime::at(7, 42)), ModelTime::new(1, 7, 42));
+}
+
+#[tokio::test(start_paused = true)]
+pub async fn test_fast_clock() {
+    let mut clock = Clock::new(ModelTime::at(7, 0), 6);
+    tokio::time::sleep(Duration::from_secs(60)).await;
+    assert_eq!(clock.time(), ModelTime::at(7, 0));
+    assert_eq!(clock.rate(), 0);
+
+    // One real minute passes six model minutes.
+    clock.resume();
+    tokio::time::sleep(Duration::from_secs(60)).await;
+    assert_eq!(clock.time(), ModelTime::at(7, 6));
+    assert_eq!(clock.rate(), 6);
+
+    clock.set_ratio(12);
+    tokio::time::sleep(Duration::from_secs(10)).await;
+    assert_eq!(clock.time(), ModelTime::at(7, 8));
+
+    clock.pause();
+    tokio::time::sleep(Duration::from_secs(60)).await;
+    assert_eq!(clock.time(), ModelTime::at(7, 8));
+
+    clock.sync(ModelTime::at(12, 0), 1);
+    assert!(clock.is_running());
+    tokio::time::sleep(Duration::from_secs(60)).await;
+    assert_eq!(clock.time(), ModelTime::at(12, 1));
+}
+
+#[tokio::test(start_paused = true)]
+pub async fn test_clock_ticks() {
+    let (r, ..) = create_line_railroad().await;
+    let mut messages = r.subscribe();
+
+    let mut clock = Clock::new(ModelTime::at(7, 40), 60);
+    clock.resume();
+    r.set_clock(clock).await;
+    assert_eq!(
+        messages.try_recv().unwrap(),
+        Message::ClockTick(ModelTime::at(7, 40), 60)
+    );
+
+    // Every real second passes one model minute.
+    let scheduler = Railroad::start_scheduler(r.clone(), Duration::from_millis(100));
+    tokio::time::sleep(Duration::from_millis(2050)).await;
+    let ticks: Vec<_> = std::iter::from_fn(|| messages.try_recv().ok())
+        .filter_map(|message| match message {
+            Message::ClockTick(time, 60) => Some(time.to_string()),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(ticks, vec!["07:40", "07:41", "07:42"]);
+
+    // Paused clocks do not tick, but the command station could set the clock.
+    clock.pause();
+    r.set_clock(clock).await;
+    Railroad::handle_feedback(r.clone(), Message::ClockSync(ModelTime::at(9, 0), 0)).await;
+    tokio::time::sleep(Duration::from_secs(2)).await;
+    let sent: Vec<_> = std::iter::from_fn(|| messages.try_recv().ok()).collect();
+    assert_eq!(sent.len(), 2);
+    assert!(matches!(sent[0], Message::ClockTick(time, 0) if time.to_string() == "07:42"));
+    assert_eq!(sent[1], Message::ClockSync(ModelTime::at(9, 0), 0));
+    assert_eq!(r.clock().await.time(), ModelTime::at(9, 0));
+
+    scheduler.abort();
+}
+
+#[test]
+pub fn test_wait_for_model_time() {
+    let station = Station::<u16>::new(Default::default())
+        .depart_when(WaitingReasons::ModelTime(ModelTime::at(7, 42)));
+
+    // The train waits over night for the next morning.
+    let mut state = WaitingState::new(Duration::ZERO);
+    state.model_since = ModelTime::at(23, 50);
+    state.model_time = ModelTime::new(1, 7, 41);
+    assert!(!station.could_depart(&state));
+    state.model_time = ModelTime::new(1, 7, 42);
+    assert!(station.could_depart(&state));
+}
@@ -0,0 +1,11 @@
+use std::time::Duration;
+use tokio::sync::broadcast::Receiver;
+use tokio::time::timeout;
+
+/// Receives the next message a connector sent, failing the test after a second without one.
+pub(crate) async fn next_feedback<T: Clone>(feedback: &mut Receiver<T>) -> T {
+    timeout(Duration::from_secs(1), feedback.recv())
+        .await
+        .unwrap()
+        .unwrap()
+}
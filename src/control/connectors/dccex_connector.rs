@@ -0,0 +1,194 @@
+use crate::control::rail_system::components::{Address, SLevel, Speed, SwDir, TrainDirection};
+use crate::control::rail_system::railroad::Railroad;
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::io::{
+    split, AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader, ReadHalf, WriteHalf,
+};
+use tokio::sync::broadcast::Receiver;
+use tokio::sync::Mutex;
+use tokio_serial::{Error, SerialPortBuilderExt, SerialStream};
+
+use super::RailroadConnector;
+
+type RailroadContainer = Arc<Mutex<Vec<Arc<Railroad<u8, u16, u16, u16, u16, u16>>>>>;
+type SendMessage = crate::control::messages::Message<u8, u16, u16, u16, u16>;
+
+/// The highest speed step a DCC-EX command station accepts.
+const MAX_SPEED: u8 = 126;
+
+/// Connects to a DCC-EX command station speaking its text protocol.
+///
+/// Any stream could be used as connection, so the connector can be tested without a
+/// command station. Use [DccExConnector::open] to connect over a serial port.
+///
+/// DCC-EX sets the speed and the direction of a train with one command,
+/// so the last of both is kept for every train.
+pub struct DccExConnector<T: AsyncRead + AsyncWrite> {
+    receiver: Receiver<SendMessage>,
+    writer: WriteHalf<T>,
+    railroads: RailroadContainer,
+    throttles: HashMap<Address, (Speed<u8>, TrainDirection)>,
+}
+
+impl DccExConnector<SerialStream> {
+    /// Opens the serial port of a DCC-EX command station.
+    pub fn open(
+        port_name: &str,
+        baud_rate: u32,
+        receiver: Receiver<SendMessage>,
+    ) -> Result<Self, Error> {
+        let port = tokio_serial::new(port_name, baud_rate).open_native_async()?;
+        Ok(DccExConnector::new(port, receiver))
+    }
+}
+
+impl<T: AsyncRead + AsyncWrite + Send + 'static> DccExConnector<T> {
+    pub fn new(stream: T, receiver: Receiver<SendMessage>) -> Self {
+        let (reader, writer) = split(stream);
+
+        let railroads: RailroadContainer = Arc::new(Mutex::new(vec![]));
+        tokio::spawn(DccExConnector::forward_feedback(reader, railroads.clone()));
+
+        DccExConnector {
+            receiver,
+            writer,
+            railroads,
+            throttles: HashMap::new(),
+        }
+    }
+
+    /// Handles all sensor, turnout and power reports of the command station
+    /// by every registered railroad, until the connection is closed.
+    async fn forward_feedback(reader: ReadHalf<T>, railroads: RailroadContainer) {
+        let mut reader = BufReader::new(reader);
+        let mut buffer = vec![];
+        loop {
+            buffer.clear();
+            match reader.read_until(b'>', &mut buffer).await {
+                Ok(0) | Err(_) => break,
+                Ok(_) => {}
+            }
+
+            let frame = String::from_utf8_lossy(&buffer);
+            let command = match frame.rfind('<') {
+                Some(start) => frame[start + 1..].trim_end_matches('>'),
+                None => continue,
+            };
+
+            if let Some(feedback) = DccExConnector::<T>::feedback(command) {
+                for railroad in railroads.lock().await.iter() {
+                    Railroad::handle_feedback(railroad.clone(), feedback).await;
+                }
+            }
+        }
+    }
+
+    /// Translates the content of a command station's `<...>` reply into the feedback message of
+    /// the rail system.
+    fn feedback(command: &str) -> Option<SendMessage> {
+        let mut chars = command.trim().chars();
+        let opcode = chars.next()?;
+        let args: Vec<&str> = chars.as_str().split_whitespace().collect();
+        let number = |index: usize| args.get(index)?.parse::<u16>().ok();
+
+        match opcode {
+            'Q' => Some(SendMessage::UpdateSensor(
+                Address::new(number(0)?),
+                SLevel::Occupied,
+            )),
+            'q' => Some(SendMessage::UpdateSensor(
+                Address::new(number(0)?),
+                SLevel::Free,
+            )),
+            'H' => Some(SendMessage::SwitchAck(
+                Address::new(number(0)?),
+                SwDir::from(number(1)? == 0),
+            )),
+            'p' => match args.first()?.chars().next()? {
+                '1' => Some(SendMessage::RailOnAck),
+                '0' => Some(SendMessage::RailOffAck),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// Translates a message of the rail system into a command of the DCC-EX protocol.
+    fn command(&mut self, message: SendMessage) -> Option<String> {
+        match message {
+            SendMessage::RailOn => Some("<1>".to_string()),
+            SendMessage::RailOff => Some("<0>".to_string()),
+            SendMessage::TrainSpeed(adr, speed) => {
+                let throttle = self
+                    .throttles
+                    .entry(adr)
+                    .or_insert((speed, TrainDirection::Forward));
+                throttle.0 = speed;
+                Some(DccExConnector::<T>::throttle(adr, *throttle))
+            }
+            SendMessage::TrainDirection(adr, direction) => {
+                let throttle = self
+                    .throttles
+                    .entry(adr)
+                    .or_insert((Speed::Stop, direction));
+                throttle.1 = direction;
+                Some(DccExConnector::<T>::throttle(adr, *throttle))
+            }
+            SendMessage::TrainFunction(adr, function, on) => {
+                Some(format!("<F {} {} {}>", adr.address(), function, on as u8))
+            }
+            SendMessage::TrainRemoved(adr) => {
+                self.throttles.remove(&adr);
+                None
+            }
+            SendMessage::Switch(adr, dir) => Some(format!(
+                "<T {} {}>",
+                adr.address(),
+                match dir {
+                    SwDir::Straight => 0,
+                    SwDir::Curved => 1,
+                }
+            )),
+            SendMessage::UpdateSignal(adr, aspect) => {
+                Some(format!("<A {} {}>", adr.address(), aspect.dcc_aspect()))
+            }
+            _ => None,
+        }
+    }
+
+    /// Creates the throttle command driving a train with the given speed and direction.
+    fn throttle(adr: Address, (speed, direction): (Speed<u8>, TrainDirection)) -> String {
+        let speed = match speed {
+            Speed::Stop => 0,
+            Speed::EmergencyStop => -1,
+            Speed::Drive(spd) => i16::from(spd.min(MAX_SPEED)),
+        };
+        let direction = match direction {
+            TrainDirection::Forward => 1,
+            TrainDirection::Backward => 0,
+        };
+        format!("<t {} {} {}>", adr.address(), speed, direction)
+    }
+}
+
+#[async_trait]
+impl<T: AsyncRead + AsyncWrite + Send + 'static> RailroadConnector<u8, u16, u16, u16, u16, u16>
+    for DccExConnector<T>
+{
+    async fn handle_message(&mut self, message: SendMessage) {
+        if let Some(command) = self.command(message) {
+            let _ = self.writer.write_all(command.as_bytes()).await;
+            let _ = self.writer.flush().await;
+        }
+    }
+
+    async fn reciever(&mut self) -> &mut Receiver<SendMessage> {
+        &mut self.receiver
+    }
+
+    async fn register_railroad(&mut self, railroad: Arc<Railroad<u8, u16, u16, u16, u16, u16>>) {
+        self.railroads.lock().await.push(railroad.clone());
+    }
+}
@@ -0,0 +1,97 @@
+use crate::control::connectors::connector_test::next_feedback;
+use crate::control::connectors::dccex_connector::DccExConnector;
+use crate::control::connectors::RailroadConnector;
+use crate::control::messages::Message;
+use crate::control::rail_system::components::{
+    Address, Aspect, SLevel, Speed, SwDir, TrainDirection,
+};
+use crate::control::rail_system::railroad_test::create_test_railroad;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{duplex, AsyncReadExt, AsyncWriteExt, DuplexStream};
+use tokio::time::timeout;
+
+async fn read_command(station: &mut DuplexStream) -> String {
+    let mut buffer = [0u8; 64];
+    let read = timeout(Duration::from_secs(1), station.read(&mut buffer))
+        .await
+        .unwrap()
+        .unwrap();
+    String::from_utf8_lossy(&buffer[..read]).to_string()
+}
+
+#[tokio::test]
+pub async fn test_dccex_commands() {
+    let (r, ..) = create_test_railroad().await;
+    let (stream, mut station) = duplex(256);
+    let mut connector = DccExConnector::new(stream, r.subscribe());
+
+    connector
+        .handle_message(Message::TrainSpeed(Address::new(3), Speed::Drive(200)))
+        .await;
+    assert_eq!(read_command(&mut station).await, "<t 3 126 1>");
+
+    connector
+        .handle_message(Message::TrainSpeed(Address::new(3), Speed::EmergencyStop))
+        .await;
+    assert_eq!(read_command(&mut station).await, "<t 3 -1 1>");
+
+    // Reversing keeps the last speed of the train.
+    connector
+        .handle_message(Message::TrainDirection(
+            Address::new(3),
+            TrainDirection::Backward,
+        ))
+        .await;
+    assert_eq!(read_command(&mut station).await, "<t 3 -1 0>");
+
+    connector
+        .handle_message(Message::TrainFunction(Address::new(3), 2, true))
+        .await;
+    assert_eq!(read_command(&mut station).await, "<F 3 2 1>");
+
+    connector
+        .handle_message(Message::Switch(Address::new(7), SwDir::Curved))
+        .await;
+    assert_eq!(read_command(&mut station).await, "<T 7 1>");
+
+    connector
+        .handle_message(Message::UpdateSignal(Address::new(9), Aspect::ExpectStop))
+        .await;
+    assert_eq!(read_command(&mut station).await, "<A 9 2>");
+
+    connector.handle_message(Message::RailOn).await;
+    assert_eq!(read_command(&mut station).await, "<1>");
+}
+
+#[tokio::test]
+pub async fn test_dccex_feedback() {
+    let (r, ..) = create_test_railroad().await;
+    let r = Arc::new(r);
+    let mut feedback = r.subscribe();
+    let (stream, mut station) = duplex(256);
+    let mut connector = DccExConnector::new(stream, r.subscribe());
+    connector.register_railroad(r.clone()).await;
+
+    station.write_all(b"<p1 MAIN>\n<Q 8>").await.unwrap();
+    assert_eq!(next_feedback(&mut feedback).await, Message::RailOnAck);
+    assert_eq!(
+        next_feedback(&mut feedback).await,
+        Message::UpdateSensor(Address::new(8), SLevel::Occupied)
+    );
+
+    station.write_all(b"<H 1 0><p0>").await.unwrap();
+    assert_eq!(
+        next_feedback(&mut feedback).await,
+        Message::SwitchAck(Address::new(1), SwDir::Straight)
+    );
+    // Feedback is handled in order, so the acknowledgement is applied by now.
+    assert_eq!(next_feedback(&mut feedback).await, Message::RailOffAck);
+    let state = r.state().await;
+    let switch = state
+        .switches
+        .iter()
+        .find(|switch| switch.address == Address::new(1))
+        .unwrap();
+    assert!(switch.updated);
+}
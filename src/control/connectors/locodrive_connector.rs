@@ -1,28 +1,216 @@
-use crate::control::rail_system::components::Address;
+use crate::control::clock::ModelTime;
+use crate::control::messages::ConnectionState;
+use crate::control::rail_system::components::{Address, SLevel, Speed, SwDir, TrainDirection};
 use crate::control::rail_system::railroad::Railroad;
 use async_trait::async_trait;
-use locodrive::args::{AddressArg, SlotArg, SpeedArg, SwitchArg, SwitchDirection};
+use locodrive::args::{
+    DirfArg, FastClock, FunctionArg, FunctionGroup, IdArg, SensorLevel, SlotArg, SnArg, SndArg,
+    SpeedArg, Stat1Arg, State, SwitchArg, SwitchDirection, TrkArg, WrSlDataStructure,
+};
+use locodrive::error::LocoDriveSendingError;
 use locodrive::loco_controller::{LocoDriveController, LocoDriveMessage};
 use locodrive::protocol::Message;
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
+use std::error;
+use std::fmt::{Display, Formatter};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
+use tokio::select;
 use tokio::sync::broadcast::error::RecvError;
 use tokio::sync::broadcast::{self, Receiver, Sender};
-use tokio::sync::Mutex;
-use tokio_serial::Error;
+use tokio::sync::{Mutex, Notify};
+use tokio::time::{timeout, MissedTickBehavior};
+use tokio_serial::{Error, FlowControl};
 
 use super::RailroadConnector;
 
-type RailroadContainer = Arc<Mutex<Vec<Arc<Railroad<u8, u16, u16, u16, u16, u16>>>>>;
+pub(crate) type RailroadContainer = Arc<Mutex<Vec<Arc<Railroad<u8, u16, u16, u16, u16, u16>>>>>;
 type SendMessage = crate::control::messages::Message<u8, u16, u16, u16, u16>;
+pub(crate) type SlotTable = Arc<Mutex<HashMap<Address, Slot>>>;
 
+/// The delay before the first try to reconnect to a lost serial port.
+pub const RECONNECT_DELAY: Duration = Duration::from_millis(500);
+/// The longest delay between two tries to reconnect to a lost serial port.
+pub const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(30);
+/// The interval the connection is checked in, while no message is sent.
+pub const HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+/// The time to wait for the command station to assign a slot to a train.
+pub const SLOT_TIMEOUT: Duration = Duration::from_secs(2);
+/// The control bit marking the data of the fast clock slot as valid.
+const CLOCK_VALID: u8 = 0x40;
+
+/// Describes why no slot could be assigned to a train.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SlotError {
+    /// The command station did not answer within [SLOT_TIMEOUT].
+    Timeout,
+    /// The command station has no free slot left.
+    NoFreeSlot,
+    /// The serial port is lost.
+    Disconnected,
+}
+
+impl Display for SlotError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SlotError::Timeout => write!(f, "the command station did not assign a slot in time"),
+            SlotError::NoFreeSlot => write!(f, "the command station has no free slot"),
+            SlotError::Disconnected => write!(f, "the LocoNet connection is lost"),
+        }
+    }
+}
+
+impl error::Error for SlotError {}
+
+/// A slot of the command station driven by this connector.
+#[derive(Debug, Copy, Clone)]
+pub(crate) struct Slot {
+    slot: SlotArg,
+    status: Stat1Arg,
+    /// The direction and the functions `F0` to `F4`, which are set by one message.
+    dirf: DirfArg,
+    /// The functions `F5` to `F8`
+    snd: SndArg,
+}
+
+impl Slot {
+    /// Returns the NULL-move marking the slot as in use by this connector.
+    pub(crate) fn take_over(&self) -> Message {
+        Message::MoveSlots(self.slot, self.slot)
+    }
+
+    /// Returns the message releasing the slot. Releasing marks the slot as common,
+    /// so it is still refreshed by the command station and could be dispatched to a throttle.
+    pub(crate) fn release(&self) -> Message {
+        Message::SlotStat1(
+            self.slot,
+            Stat1Arg::new(
+                self.status.s_purge(),
+                self.status.consist(),
+                State::Common,
+                self.status.decoder_type(),
+            ),
+        )
+    }
+}
+
+/// The last commands sent to the trains and switches, which are re-issued after reconnecting.
+#[derive(Debug, Default)]
+pub(crate) struct Issued {
+    /// The last direction of travel sent to every train
+    directions: HashMap<Address, TrainDirection>,
+    /// The last speed sent to every train
+    speeds: HashMap<Address, Speed<u8>>,
+    /// The functions switched on of every train
+    functions: HashMap<Address, BTreeSet<u8>>,
+    /// The last direction sent to every switch
+    switches: HashMap<Address, SwDir>,
+}
+
+impl Issued {
+    /// Remembers the command of a message. Removed trains are forgotten.
+    pub(crate) fn record(&mut self, message: SendMessage) {
+        match message {
+            SendMessage::TrainSpeed(adr, speed) => {
+                self.speeds.insert(adr, speed);
+            }
+            SendMessage::Switch(adr, dir) => {
+                self.switches.insert(adr, dir);
+            }
+            SendMessage::TrainDirection(adr, direction) => {
+                self.directions.insert(adr, direction);
+            }
+            SendMessage::TrainFunction(adr, function, on) => {
+                let functions = self.functions.entry(adr).or_default();
+                if on {
+                    functions.insert(function);
+                } else {
+                    functions.remove(&function);
+                }
+            }
+            SendMessage::TrainRemoved(adr) => {
+                self.directions.remove(&adr);
+                self.speeds.remove(&adr);
+                self.functions.remove(&adr);
+            }
+            _ => {}
+        }
+    }
+
+    /// Returns the messages re-issuing the last direction, speed and functions of every train
+    /// and the last direction of every switch.
+    /// The functions follow the direction, which is sent together with the functions `F0` to `F4`.
+    pub(crate) fn messages(&self) -> Vec<SendMessage> {
+        let directions = self
+            .directions
+            .iter()
+            .map(|(adr, direction)| SendMessage::TrainDirection(*adr, *direction));
+        let speeds = self
+            .speeds
+            .iter()
+            .map(|(adr, speed)| SendMessage::TrainSpeed(*adr, *speed));
+        let functions = self.functions.iter().flat_map(|(adr, functions)| {
+            functions
+                .iter()
+                .map(|function| SendMessage::TrainFunction(*adr, *function, true))
+        });
+        let switches = self
+            .switches
+            .iter()
+            .map(|(adr, dir)| SendMessage::Switch(*adr, *dir));
+        directions
+            .chain(speeds)
+            .chain(functions)
+            .chain(switches)
+            .collect()
+    }
+}
+
+/// The settings to open the serial port with.
+struct PortConfig {
+    port_name: String,
+    baud_rate: u32,
+    sending_timeout: u64,
+    flow_control: FlowControl,
+}
+
+/// Connects to a LocoNet over a serial port.
+///
+/// The connection is checked with every sent message and every [HEALTH_CHECK_INTERVAL].
+/// Changes of its health are handled by the registered railroads as
+/// [SendMessage::ConnectionState]. If the serial port is lost, the connector reconnects
+/// with an exponential backoff from [RECONNECT_DELAY] to [MAX_RECONNECT_DELAY] and re-issues
+/// the last direction, speed and functions of every train and the last direction of every switch
+/// afterwards.
+///
+/// Trains are driven by taking over the slot the command station assigns to them.
+/// The slots are released with [SendMessage::TrainRemoved]. If another throttle drives a train
+/// of this connector, the registered railroads handle [SendMessage::TrainTakenOver] and the slot
+/// is taken back with the next command for that train.
+///
+/// The functions `F0` to `F8` are switched in the slot, `F9` to `F28` with the extended function
+/// messages of Uhlenbrock command stations.
+///
+/// With [LocoDriveConnector::set_clock_sync] the fast clock of the railroads is written to the
+/// fast clock slot of the LocoNet with every [SendMessage::ClockTick], and clocks written to the
+/// slot by other devices are handled as [SendMessage::ClockSync].
 pub struct LocoDriveConnector {
     receiver: Receiver<SendMessage>,
     rail_controller: LocoDriveController,
     rail_messages: Sender<LocoDriveMessage>,
-    loco_receiver: Receiver<Message>,
-    slots: HashMap<Address, SlotArg>,
+    /// The slot and its status of every train driven by this connector
+    slots: SlotTable,
     railroads: RailroadContainer,
+    config: PortConfig,
+    state: ConnectionState,
+    /// Notified by the reader, when the serial port could not be read anymore
+    port_lost: Arc<Notify>,
+    /// Syncs the fast clock with the fast clock slot
+    clock_sync: Arc<AtomicBool>,
+    reconnect_delay: (Duration, Duration),
+    /// The last commands sent to the trains and switches
+    issued: Issued,
 }
 
 impl LocoDriveConnector {
@@ -30,62 +218,408 @@ impl LocoDriveConnector {
         port_name: &str,
         baud_rate: u32,
         sending_timeout: u64,
-        flow_control: tokio_serial::FlowControl,
+        flow_control: FlowControl,
         receiver: Receiver<SendMessage>,
-        loco_receiver: Receiver<Message>,
     ) -> Result<Self, Error> {
         let (rail_messages, _) = broadcast::channel(25);
 
-        let rail_controller = LocoDriveController::new(
-            port_name,
+        let config = PortConfig {
+            port_name: port_name.to_string(),
             baud_rate,
             sending_timeout,
             flow_control,
-            rail_messages.clone(),
-            true,
-        )
-        .await?;
+        };
+        let rail_controller = LocoDriveConnector::open(&config, &rail_messages).await?;
+
+        let railroads: RailroadContainer = Arc::new(Mutex::new(vec![]));
+        let slots: SlotTable = Arc::new(Mutex::new(HashMap::new()));
+        let port_lost = Arc::new(Notify::new());
+        let clock_sync = Arc::new(AtomicBool::new(false));
+        tokio::spawn(LocoDriveConnector::forward_feedback(
+            rail_messages.subscribe(),
+            railroads.clone(),
+            slots.clone(),
+            port_lost.clone(),
+            clock_sync.clone(),
+        ));
 
         Ok(LocoDriveConnector {
             receiver,
             rail_controller,
             rail_messages,
-            loco_receiver,
-            slots: HashMap::new(),
-            railroads: Arc::new(Mutex::new(vec![])),
+            slots,
+            railroads,
+            config,
+            state: ConnectionState::Connected,
+            port_lost,
+            clock_sync,
+            reconnect_delay: (RECONNECT_DELAY, MAX_RECONNECT_DELAY),
+            issued: Issued::default(),
         })
     }
 
-    pub async fn lookup_slot(&mut self, adr: Address) -> Option<SlotArg> {
-        if self.slots.contains_key(&adr) {
-            return Some(*self.slots.get(&adr).unwrap());
-        } else {
-            let _ = self
-                .rail_controller
-                .send_message(locodrive::protocol::Message::LocoAdr(AddressArg::new(
-                    adr.address(),
-                )))
-                .await;
-            loop {
-                match self.loco_receiver.recv().await {
-                    Ok(Message::SlRdData(slot, _, loco_adr, ..)) => {
-                        self.slots.insert(Address::new(loco_adr.address()), slot);
-                        if self.slots.contains_key(&adr) {
-                            return Some(*self.slots.get(&adr).unwrap());
+    /// Sets the first and the longest delay between two tries to reconnect.
+    pub fn set_reconnect_delay(&mut self, delay: Duration, max_delay: Duration) {
+        self.reconnect_delay = (delay, max_delay);
+    }
+
+    /// Syncs the fast clock of the registered railroads with the fast clock slot of the LocoNet.
+    /// Disabled by default.
+    pub fn set_clock_sync(&mut self, sync: bool) {
+        self.clock_sync.store(sync, Ordering::Relaxed);
+    }
+
+    /// Returns the last known health of the connection.
+    pub fn connection_state(&self) -> ConnectionState {
+        self.state
+    }
+
+    async fn open(
+        config: &PortConfig,
+        rail_messages: &Sender<LocoDriveMessage>,
+    ) -> Result<LocoDriveController, Error> {
+        LocoDriveController::new(
+            &config.port_name,
+            config.baud_rate,
+            config.sending_timeout,
+            config.flow_control,
+            rail_messages.clone(),
+            true,
+        )
+        .await
+    }
+
+    /// Handles all sensor, switch and power reports received from the LocoNet
+    /// by every registered railroad, until the LocoNet connection is closed.
+    /// Errors of the serial port are notified to `port_lost`.
+    /// The fast clock is only handled, while `clock_sync` is set.
+    async fn forward_feedback(
+        mut messages: Receiver<LocoDriveMessage>,
+        railroads: RailroadContainer,
+        slots: SlotTable,
+        port_lost: Arc<Notify>,
+        clock_sync: Arc<AtomicBool>,
+    ) {
+        loop {
+            match messages.recv().await {
+                // Only a waiting connector is notified, so no permit is left to trigger another
+                // reconnect after reconnecting. Errors while reconnecting are caught by sending.
+                Ok(LocoDriveMessage::SerialPortError(_err)) => port_lost.notify_waiters(),
+                Ok(message) => {
+                    let taken_over = LocoDriveConnector::update_slots(&message, &slots).await;
+                    let feedback = taken_over
+                        .map(SendMessage::TrainTakenOver)
+                        .into_iter()
+                        .chain(LocoDriveConnector::feedback(&message))
+                        .filter(|feedback| {
+                            !matches!(feedback, SendMessage::ClockSync(..))
+                                || clock_sync.load(Ordering::Relaxed)
+                        });
+                    for feedback in feedback {
+                        for railroad in railroads.lock().await.iter() {
+                            Railroad::handle_feedback(railroad.clone(), feedback).await;
                         }
                     }
-                    Err(RecvError::Closed) => break,
-                    Err(RecvError::Lagged(_)) => {
-                        let _ = self
-                            .rail_controller
-                            .send_message(Message::LocoAdr(adr.address_arg()))
-                            .await;
+                }
+                Err(RecvError::Closed) => break,
+                Err(RecvError::Lagged(_)) => continue,
+            }
+        }
+    }
+
+    /// Keeps the slot table in sync with the slot reports of the LocoNet.
+    /// Returns the train, whose slot was taken over by another throttle.
+    pub(crate) async fn update_slots(
+        message: &LocoDriveMessage,
+        slots: &SlotTable,
+    ) -> Option<Address> {
+        let mut slots = slots.lock().await;
+        match message {
+            LocoDriveMessage::Message(Message::SlRdData(
+                slot,
+                stat1,
+                loco_adr,
+                _,
+                dirf,
+                _,
+                _,
+                snd,
+                _,
+            )) => {
+                // Slots freed or reassigned by the command station are no longer ours.
+                slots.retain(|adr, owned| {
+                    owned.slot != *slot
+                        || adr.address() == loco_adr.address() && stat1.state() != State::Free
+                });
+                if let Some(owned) = slots.get_mut(&Address::new(loco_adr.address())) {
+                    owned.status = *stat1;
+                    owned.dirf = *dirf;
+                    owned.snd = *snd;
+                }
+                None
+            }
+            // Messages sent by this connector are not received,
+            // so these are sent by another throttle.
+            LocoDriveMessage::Message(
+                Message::LocoSpd(slot, _) | Message::LocoDirf(slot, _) | Message::LocoSnd(slot, _),
+            ) => {
+                let adr = *slots.iter().find(|(_, owned)| owned.slot == *slot)?.0;
+                slots.remove(&adr);
+                Some(adr)
+            }
+            _ => None,
+        }
+    }
+
+    /// Translates a LocoNet report into the feedback message of the rail system.
+    ///
+    /// Answers to [Message::SwState] report the straight direction with bit `0x20`
+    /// of their acknowledgement.
+    pub(crate) fn feedback(message: &LocoDriveMessage) -> Option<SendMessage> {
+        match message {
+            LocoDriveMessage::Message(Message::InputRep(input)) => Some(SendMessage::UpdateSensor(
+                Address::new(input.address()),
+                SLevel::from(input.sensor_level()),
+            )),
+            LocoDriveMessage::Message(Message::SwRep(SnArg::SwitchDirectionStatus(
+                adr,
+                straight,
+                curved,
+            ))) => match (straight, curved) {
+                (SensorLevel::High, SensorLevel::Low) => {
+                    Some(SendMessage::SwitchAck(Address::new(*adr), SwDir::Straight))
+                }
+                (SensorLevel::Low, SensorLevel::High) => {
+                    Some(SendMessage::SwitchAck(Address::new(*adr), SwDir::Curved))
+                }
+                _ => None,
+            },
+            LocoDriveMessage::Answer(Message::LongAck(_, ack), Message::SwState(switch))
+                if !ack.failed() =>
+            {
+                Some(SendMessage::SwitchAck(
+                    Address::new(switch.address()),
+                    SwDir::from(ack.ack1() & 0x20 == 0x20),
+                ))
+            }
+            LocoDriveMessage::Message(Message::WrSlData(WrSlDataStructure::DataTime(
+                clock,
+                ..,
+            ))) => Some(SendMessage::ClockSync(model_time(clock), clock.clk_rate())),
+            LocoDriveMessage::Message(Message::GpOn) => Some(SendMessage::RailOnAck),
+            LocoDriveMessage::Message(Message::GpOff) => Some(SendMessage::RailOffAck),
+            _ => None,
+        }
+    }
+
+    /// Publishes a changed connection state to all registered railroads.
+    async fn set_state(&mut self, state: ConnectionState) {
+        publish_state(&mut self.state, state, &self.railroads).await;
+    }
+
+    /// Sends a message to the LocoNet and updates the connection state by the result.
+    /// Returns `false`, if the serial port is lost.
+    async fn send(&mut self, message: Message) -> bool {
+        let state = connection_state(self.rail_controller.send_message(message).await);
+        self.set_state(state).await;
+        state != ConnectionState::Disconnected
+    }
+
+    /// Reopens the serial port until the last state of all trains and switches is re-issued.
+    async fn reconnect(&mut self) {
+        loop {
+            self.set_state(ConnectionState::Disconnected).await;
+
+            let (delay, max_delay) = self.reconnect_delay;
+            for delay in reconnect_delays(delay, max_delay) {
+                tokio::time::sleep(delay).await;
+                if let Ok(rail_controller) =
+                    LocoDriveConnector::open(&self.config, &self.rail_messages).await
+                {
+                    self.rail_controller = rail_controller;
+                    break;
+                }
+            }
+
+            // The command station may have assigned other slots in the meantime.
+            self.slots.lock().await.clear();
+            self.set_state(ConnectionState::Connected).await;
+            if self.reissue().await {
+                break;
+            }
+        }
+    }
+
+    /// Sends the last direction, speed and functions of every train and the last direction of
+    /// every switch again. Returns `false`, if the serial port is lost while doing so.
+    async fn reissue(&mut self) -> bool {
+        for message in self.issued.messages() {
+            if !self.issue(message).await {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Checks the connection and refreshes the slot table by requesting the data of all slots
+    /// of this connector, or the dispatch slot, if there are none.
+    async fn check_health(&mut self) {
+        let mut slots: Vec<SlotArg> = self
+            .slots
+            .lock()
+            .await
+            .values()
+            .map(|owned| owned.slot)
+            .collect();
+        if slots.is_empty() {
+            slots.push(SlotArg::new(0));
+        }
+
+        for slot in slots {
+            if !self.send(Message::RqSlData(slot)).await {
+                self.reconnect().await;
+                return;
+            }
+        }
+    }
+
+    /// Sends a message of the rail system to the LocoNet.
+    /// Returns `false`, if the serial port is lost.
+    async fn issue(&mut self, message: SendMessage) -> bool {
+        match self.command(message).await {
+            Some(loco_net_message) => self.send(loco_net_message).await,
+            None => self.state != ConnectionState::Disconnected,
+        }
+    }
+
+    /// Translates a message of the rail system into a LocoNet message.
+    async fn command(&mut self, message: SendMessage) -> Option<Message> {
+        match message {
+            SendMessage::RailOn => Some(Message::GpOn),
+            SendMessage::RailOff => Some(Message::GpOff),
+            SendMessage::TrainSpeed(adr, speed) => self
+                .lookup_slot(adr)
+                .await
+                .ok()
+                .map(|slot| Message::LocoSpd(slot, SpeedArg::from(speed))),
+            // The functions are sent with the direction, so the known ones are kept.
+            SendMessage::TrainDirection(adr, direction) => {
+                self.lookup_slot(adr).await.ok()?;
+                let mut slots = self.slots.lock().await;
+                let owned = slots.get_mut(&adr)?;
+                owned.dirf.set_dir(direction == TrainDirection::Forward);
+                Some(Message::LocoDirf(owned.slot, owned.dirf))
+            }
+            SendMessage::TrainFunction(adr, function, on) => {
+                self.lookup_slot(adr).await.ok()?;
+                let mut slots = self.slots.lock().await;
+                let owned = slots.get_mut(&adr)?;
+                match function {
+                    0..=4 => {
+                        owned.dirf.set_f(function, on);
+                        Some(Message::LocoDirf(owned.slot, owned.dirf))
+                    }
+                    5..=8 => {
+                        owned.snd.set_f(function, on);
+                        Some(Message::LocoSnd(owned.slot, owned.snd))
+                    }
+                    // The extended messages set all functions of a group at once.
+                    _ => {
+                        let mut functions = FunctionArg::new(function_group(function)?);
+                        for function in self.issued.functions.get(&adr).into_iter().flatten() {
+                            functions.set_f(*function, true);
+                        }
+                        Some(Message::UhliFun(owned.slot, functions))
                     }
-                    _ => continue,
                 }
             }
+            SendMessage::Switch(adr, dir) => Some(Message::SwReq(SwitchArg::new(
+                adr.address(),
+                SwitchDirection::from(dir),
+                false,
+            ))),
+            SendMessage::TrainRemoved(adr) => Some(self.slots.lock().await.remove(&adr)?.release()),
+            // The track status is left unchanged by writing the fast clock slot.
+            SendMessage::ClockTick(time, rate) if self.clock_sync.load(Ordering::Relaxed) => {
+                Some(Message::WrSlData(WrSlDataStructure::DataTime(
+                    fast_clock(time, rate),
+                    TrkArg::new(true, false, true, false),
+                    IdArg::new(0),
+                )))
+            }
+            // Signal aspects are not sent, as locodrive cannot send DCC extended accessory packets.
+            _ => None,
+        }
+    }
+
+    /// Returns the slot of a train. If there is none yet, a slot is requested from the
+    /// command station and taken over by this connector.
+    pub async fn lookup_slot(&mut self, adr: Address) -> Result<SlotArg, SlotError> {
+        let known = self.slots.lock().await.get(&adr).map(|owned| owned.slot);
+        if let Some(slot) = known {
+            return Ok(slot);
+        }
+
+        let mut answers = self.rail_messages.subscribe();
+        if !self.send(Message::LocoAdr(adr.address_arg())).await {
+            return Err(SlotError::Disconnected);
+        }
+        let owned = LocoDriveConnector::request_slot(&mut answers, adr).await?;
+        if !self.send(owned.take_over()).await {
+            return Err(SlotError::Disconnected);
+        }
+        self.slots.lock().await.insert(adr, owned);
+        Ok(owned.slot)
+    }
+
+    /// Waits up to [SLOT_TIMEOUT] for the command station to answer a slot request
+    /// for the given train.
+    pub(crate) async fn request_slot(
+        answers: &mut Receiver<LocoDriveMessage>,
+        adr: Address,
+    ) -> Result<Slot, SlotError> {
+        timeout(SLOT_TIMEOUT, LocoDriveConnector::slot_data(answers, adr))
+            .await
+            .map_err(|_elapsed| SlotError::Timeout)?
+    }
+
+    /// Waits for the command station to answer a slot request for the given train.
+    async fn slot_data(
+        answers: &mut Receiver<LocoDriveMessage>,
+        adr: Address,
+    ) -> Result<Slot, SlotError> {
+        let request = Message::LocoAdr(adr.address_arg());
+        loop {
+            match answers.recv().await {
+                Ok(LocoDriveMessage::Message(Message::SlRdData(
+                    slot,
+                    status,
+                    loco_adr,
+                    _,
+                    dirf,
+                    _,
+                    _,
+                    snd,
+                    _,
+                ))) if loco_adr.address() == adr.address() => {
+                    return Ok(Slot {
+                        slot,
+                        status,
+                        dirf,
+                        snd,
+                    });
+                }
+                Ok(LocoDriveMessage::Message(Message::LongAck(lopc, ack)))
+                    if ack.failed() && lopc.check_opc(&request) =>
+                {
+                    return Err(SlotError::NoFreeSlot);
+                }
+                Ok(LocoDriveMessage::SerialPortError(_)) | Err(RecvError::Closed) => {
+                    return Err(SlotError::Disconnected);
+                }
+                Ok(_) | Err(RecvError::Lagged(_)) => continue,
+            }
         }
-        None
     }
 
     pub fn get_reciever(&self) -> Receiver<LocoDriveMessage> {
@@ -97,22 +631,75 @@ impl LocoDriveConnector {
     }
 }
 
+/// Returns the health of the connection shown by the result of sending a message.
+pub(crate) fn connection_state(result: Result<(), LocoDriveSendingError>) -> ConnectionState {
+    match result {
+        Ok(()) => ConnectionState::Connected,
+        Err(LocoDriveSendingError::Timeout) => ConnectionState::Degraded,
+        Err(_err) => ConnectionState::Disconnected,
+    }
+}
+
+/// Returns the delays between the tries to reconnect, doubled from `delay` up to `max_delay`.
+pub(crate) fn reconnect_delays(
+    delay: Duration,
+    max_delay: Duration,
+) -> impl Iterator<Item = Duration> {
+    std::iter::successors(Some(delay), move |delay| Some((*delay * 2).min(max_delay)))
+}
+
+/// Sets the connection state and publishes it to all railroads as
+/// [SendMessage::ConnectionState], if it changed.
+pub(crate) async fn publish_state(
+    current: &mut ConnectionState,
+    state: ConnectionState,
+    railroads: &RailroadContainer,
+) {
+    if *current != state {
+        *current = state;
+        for railroad in railroads.lock().await.iter() {
+            Railroad::handle_feedback(railroad.clone(), SendMessage::ConnectionState(state)).await;
+        }
+    }
+}
+
+/// Returns the group of the extended function messages switching the given function.
+fn function_group(function: u8) -> Option<FunctionGroup> {
+    match function {
+        9..=11 => Some(FunctionGroup::F9TO11),
+        12 | 20 | 28 => Some(FunctionGroup::F12F20F28),
+        13..=19 => Some(FunctionGroup::F13TO19),
+        21..=27 => Some(FunctionGroup::F21TO27),
+        _ => None,
+    }
+}
+
+/// Encodes a model time for the fast clock slot, which counts the minutes up from `256 - 60`
+/// and the hours up from `256 - 24`, both limited to seven bits.
+pub(crate) fn fast_clock(time: ModelTime, rate: u8) -> FastClock {
+    FastClock::new(
+        rate.min(0x7F),
+        0,
+        ((time.minute() + 256 - 60 - 1) & 0x7F) as u8,
+        ((time.hour() + 256 - 24) & 0x7F) as u8,
+        time.day() as u8,
+        CLOCK_VALID,
+    )
+}
+
+/// Decodes the model time of the fast clock slot.
+pub(crate) fn model_time(clock: &FastClock) -> ModelTime {
+    let minute = (60 - (255 - clock.mins() as u32) % 128 % 60) % 60;
+    let hour = (24 - (256 - clock.hours() as u32) % 128 % 24) % 24;
+    ModelTime::new(clock.days() as u32, hour, minute)
+}
+
 #[async_trait]
 impl RailroadConnector<u8, u16, u16, u16, u16, u16> for LocoDriveConnector {
     async fn handle_message(&mut self, message: SendMessage) {
-        if let Some(loco_net_message) = match message {
-            SendMessage::RailOn => Some(locodrive::protocol::Message::GpOn),
-            SendMessage::RailOff => Some(locodrive::protocol::Message::GpOff),
-            SendMessage::TrainSpeed(adr, speed) => self
-                .lookup_slot(adr)
-                .await
-                .map(|slot| locodrive::protocol::Message::LocoSpd(slot, SpeedArg::from(speed))),
-            SendMessage::Switch(adr, dir) => Some(locodrive::protocol::Message::SwReq(
-                SwitchArg::new(adr.address(), SwitchDirection::from(dir), false),
-            )),
-            _ => None,
-        } {
-            let _ = self.rail_controller.send_message(loco_net_message).await;
+        self.issued.record(message);
+        if !self.issue(message).await {
+            self.reconnect().await;
         }
     }
 
@@ -120,7 +707,29 @@ impl RailroadConnector<u8, u16, u16, u16, u16, u16> for LocoDriveConnector {
         &mut self.receiver
     }
 
-    async fn register_railroad(&mut self, railroad: Arc<Railroad<u8, u16, u16, u16, u16>>) {
+    /// Handles the messages of the railroad, checks the connection while idle
+    /// and reconnects, when the serial port is lost.
+    async fn start_connectors(&mut self) {
+        let mut health_check = tokio::time::interval(HEALTH_CHECK_INTERVAL);
+        health_check.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        let port_lost = self.port_lost.clone();
+        loop {
+            select! {
+                message = self.receiver.recv() => match message {
+                    Ok(msg) => {
+                        self.handle_message(msg).await;
+                        health_check.reset();
+                    }
+                    Err(RecvError::Closed) => break,
+                    Err(RecvError::Lagged(_)) => continue,
+                },
+                _ = health_check.tick() => self.check_health().await,
+                _ = port_lost.notified() => self.reconnect().await,
+            }
+        }
+    }
+
+    async fn register_railroad(&mut self, railroad: Arc<Railroad<u8, u16, u16, u16, u16, u16>>) {
         self.railroads.lock().await.push(railroad.clone());
     }
 }
@@ -0,0 +1,292 @@
+use crate::control::clock::ModelTime;
+use crate::control::connectors::locodrive_connector::{
+    connection_state, fast_clock, model_time, publish_state, reconnect_delays, Issued,
+    LocoDriveConnector, SlotError, SlotTable, SLOT_TIMEOUT,
+};
+use crate::control::messages::{ConnectionState, Message};
+use crate::control::rail_system::components::{Address, SLevel, Speed, SwDir, TrainDirection};
+use crate::control::rail_system::railroad_test::create_test_railroad;
+use locodrive::args::{
+    Ack1Arg, AddressArg, Consist, DecoderType, DirfArg, IdArg, InArg, LopcArg, SensorLevel,
+    SlotArg, SnArg, SndArg, SourceType, SpeedArg, Stat1Arg, Stat2Arg, State, SwitchArg,
+    SwitchDirection, TrkArg,
+};
+use locodrive::error::LocoDriveSendingError;
+use locodrive::loco_controller::LocoDriveMessage;
+use locodrive::protocol::Message as LocoNetMessage;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{broadcast, Mutex};
+use tokio::time::Instant;
+
+type SendMessage = Message<u8, u16, u16, u16, u16>;
+
+#[test]
+pub fn test_locodrive_feedback() {
+    let switch_state = LocoNetMessage::SwState(SwitchArg::new(7, SwitchDirection::Straight, false));
+    let ack = |ack| LocoNetMessage::LongAck(LopcArg::new(0xBC), Ack1Arg::new_advanced(ack));
+    let input = |level| {
+        LocoDriveMessage::Message(LocoNetMessage::InputRep(InArg::new(
+            3,
+            SourceType::Switch,
+            level,
+            false,
+        )))
+    };
+    let report = |straight, curved| {
+        LocoDriveMessage::Message(LocoNetMessage::SwRep(SnArg::SwitchDirectionStatus(
+            7, straight, curved,
+        )))
+    };
+
+    let cases: Vec<(LocoDriveMessage, Option<SendMessage>)> = vec![
+        (
+            input(SensorLevel::High),
+            Some(Message::UpdateSensor(Address::new(3), SLevel::Occupied)),
+        ),
+        (
+            input(SensorLevel::Low),
+            Some(Message::UpdateSensor(Address::new(3), SLevel::Free)),
+        ),
+        (
+            report(SensorLevel::High, SensorLevel::Low),
+            Some(Message::SwitchAck(Address::new(7), SwDir::Straight)),
+        ),
+        (
+            report(SensorLevel::Low, SensorLevel::High),
+            Some(Message::SwitchAck(Address::new(7), SwDir::Curved)),
+        ),
+        // Switches in motion report neither or both outputs.
+        (report(SensorLevel::Low, SensorLevel::Low), None),
+        (
+            LocoDriveMessage::Answer(ack(0x30), switch_state),
+            Some(Message::SwitchAck(Address::new(7), SwDir::Straight)),
+        ),
+        (
+            LocoDriveMessage::Answer(ack(0x10), switch_state),
+            Some(Message::SwitchAck(Address::new(7), SwDir::Curved)),
+        ),
+        (LocoDriveMessage::Answer(ack(0x00), switch_state), None),
+        (
+            LocoDriveMessage::Message(LocoNetMessage::GpOn),
+            Some(Message::RailOnAck),
+        ),
+        (
+            LocoDriveMessage::Message(LocoNetMessage::GpOff),
+            Some(Message::RailOffAck),
+        ),
+        (LocoDriveMessage::Message(LocoNetMessage::Idle), None),
+    ];
+
+    for (message, feedback) in cases {
+        assert_eq!(LocoDriveConnector::feedback(&message), feedback);
+    }
+}
+
+#[tokio::test]
+pub async fn test_locodrive_connection_state() {
+    assert_eq!(connection_state(Ok(())), ConnectionState::Connected);
+    assert_eq!(
+        connection_state(Err(LocoDriveSendingError::Timeout)),
+        ConnectionState::Degraded
+    );
+    assert_eq!(
+        connection_state(Err(LocoDriveSendingError::NotWritable)),
+        ConnectionState::Disconnected
+    );
+
+    let (r, ..) = create_test_railroad().await;
+    let r = Arc::new(r);
+    let mut messages = r.subscribe();
+    let railroads = Arc::new(Mutex::new(vec![r.clone()]));
+    let mut state = ConnectionState::Connected;
+
+    // Only changes of the state are published.
+    for next in [
+        ConnectionState::Connected,
+        ConnectionState::Degraded,
+        ConnectionState::Degraded,
+        ConnectionState::Disconnected,
+        ConnectionState::Connected,
+    ] {
+        publish_state(&mut state, next, &railroads).await;
+        assert_eq!(state, next);
+    }
+    let sent: Vec<_> = std::iter::from_fn(|| messages.try_recv().ok()).collect();
+    assert_eq!(
+        sent,
+        vec![
+            Message::ConnectionState(ConnectionState::Degraded),
+            Message::ConnectionState(ConnectionState::Disconnected),
+            Message::ConnectionState(ConnectionState::Connected),
+        ]
+    );
+}
+
+#[test]
+pub fn test_locodrive_reconnect_delays() {
+    let delays: Vec<_> = reconnect_delays(Duration::from_millis(500), Duration::from_secs(5))
+        .take(6)
+        .collect();
+    assert_eq!(
+        delays,
+        [500, 1000, 2000, 4000, 5000, 5000].map(Duration::from_millis)
+    );
+}
+
+#[test]
+pub fn test_locodrive_reissue() {
+    let mut issued = Issued::default();
+    for message in [
+        Message::TrainSpeed(Address::new(3), Speed::Drive(20)),
+        Message::TrainDirection(Address::new(3), TrainDirection::Backward),
+        Message::TrainFunction(Address::new(3), 2, true),
+        Message::TrainFunction(Address::new(3), 5, true),
+        Message::TrainFunction(Address::new(3), 5, false),
+        Message::Switch(Address::new(7), SwDir::Straight),
+        Message::Switch(Address::new(7), SwDir::Curved),
+        Message::TrainSpeed(Address::new(9), Speed::Drive(40)),
+        Message::TrainRemoved(Address::new(9)),
+        Message::RailOn,
+    ] {
+        issued.record(message);
+    }
+
+    // Only the last state of every train still driven and every switch is re-issued.
+    assert_eq!(
+        issued.messages(),
+        vec![
+            Message::TrainDirection(Address::new(3), TrainDirection::Backward),
+            Message::TrainSpeed(Address::new(3), Speed::Drive(20)),
+            Message::TrainFunction(Address::new(3), 2, true),
+            Message::Switch(Address::new(7), SwDir::Curved),
+        ]
+    );
+}
+
+/// Creates the slot data the command station reports for a train.
+fn slot_data(slot: u8, adr: u16, state: State) -> LocoDriveMessage {
+    LocoDriveMessage::Message(LocoNetMessage::SlRdData(
+        SlotArg::new(slot),
+        Stat1Arg::new(false, Consist::Free, state, DecoderType::Dcc128),
+        AddressArg::new(adr),
+        SpeedArg::new(0),
+        DirfArg::new(true, false, false, false, false, false),
+        TrkArg::new(true, false, true, false),
+        Stat2Arg::new(false, false, false),
+        SndArg::new(false, false, false, false),
+        IdArg::new(0),
+    ))
+}
+
+#[tokio::test(start_paused = true)]
+pub async fn test_locodrive_slot_request() {
+    let (sender, mut answers) = broadcast::channel(25);
+
+    let start = Instant::now();
+    assert_eq!(
+        LocoDriveConnector::request_slot(&mut answers, Address::new(3))
+            .await
+            .unwrap_err(),
+        SlotError::Timeout
+    );
+    assert_eq!(start.elapsed(), SLOT_TIMEOUT);
+
+    let failed = LocoNetMessage::LongAck(LopcArg::new(0xBF), Ack1Arg::new(false));
+    sender.send(LocoDriveMessage::Message(failed)).unwrap();
+    assert_eq!(
+        LocoDriveConnector::request_slot(&mut answers, Address::new(3))
+            .await
+            .unwrap_err(),
+        SlotError::NoFreeSlot
+    );
+
+    // Slot data of other trains is skipped.
+    sender.send(slot_data(4, 8, State::Common)).unwrap();
+    sender.send(slot_data(5, 3, State::Common)).unwrap();
+    let slot = LocoDriveConnector::request_slot(&mut answers, Address::new(3))
+        .await
+        .unwrap();
+    assert_eq!(
+        slot.take_over(),
+        LocoNetMessage::MoveSlots(SlotArg::new(5), SlotArg::new(5))
+    );
+    assert_eq!(
+        slot.release(),
+        LocoNetMessage::SlotStat1(
+            SlotArg::new(5),
+            Stat1Arg::new(false, Consist::Free, State::Common, DecoderType::Dcc128)
+        )
+    );
+}
+
+#[tokio::test]
+pub async fn test_locodrive_slot_updates() {
+    let (sender, mut answers) = broadcast::channel(25);
+    sender.send(slot_data(5, 3, State::InUse)).unwrap();
+    sender.send(slot_data(6, 4, State::InUse)).unwrap();
+    let slots: SlotTable = Arc::new(Mutex::new(HashMap::new()));
+    for adr in [3, 4] {
+        let slot = LocoDriveConnector::request_slot(&mut answers, Address::new(adr))
+            .await
+            .unwrap();
+        slots.lock().await.insert(Address::new(adr), slot);
+    }
+
+    // Commands of other throttles for the slot of a train take the train over.
+    let speed = LocoNetMessage::LocoSpd(SlotArg::new(5), SpeedArg::new(20));
+    assert_eq!(
+        LocoDriveConnector::update_slots(&LocoDriveMessage::Message(speed), &slots).await,
+        Some(Address::new(3))
+    );
+    assert!(!slots.lock().await.contains_key(&Address::new(3)));
+    let unknown = LocoNetMessage::LocoSpd(SlotArg::new(7), SpeedArg::new(20));
+    assert_eq!(
+        LocoDriveConnector::update_slots(&LocoDriveMessage::Message(unknown), &slots).await,
+        None
+    );
+
+    // Slots still in use are kept, freed or reassigned slots are released.
+    assert_eq!(
+        LocoDriveConnector::update_slots(&slot_data(6, 4, State::InUse), &slots).await,
+        None
+    );
+    assert!(slots.lock().await.contains_key(&Address::new(4)));
+    assert_eq!(
+        LocoDriveConnector::update_slots(&slot_data(6, 4, State::Free), &slots).await,
+        None
+    );
+    assert!(slots.lock().await.is_empty());
+}
+
+#[test]
+pub fn test_locodrive_fast_clock_round_trip() {
+    for day in [0, 1, 127] {
+        for hour in 0..24 {
+            for minute in 0..60 {
+                let time = ModelTime::new(day, hour, minute);
+                let clock = fast_clock(time, 4);
+                assert!(clock.mins() < 0x80 && clock.hours() < 0x80);
+                assert_eq!(model_time(&clock), time);
+            }
+        }
+    }
+}
+
+#[test]
+pub fn test_locodrive_fast_clock_slot() {
+    // The fast clock slot as written by JMRI at 13:45 of the second day with a rate of 4.
+    let slot = [
+        0xEF, 0x0E, 0x7B, 0x04, 0x00, 0x00, 0x70, 0x07, 0x75, 0x02, 0x40, 0x00, 0x00, 0x21,
+    ];
+    let message = LocoNetMessage::parse(&slot).unwrap();
+    assert_eq!(
+        LocoDriveConnector::feedback(&LocoDriveMessage::Message(message)),
+        Some(Message::ClockSync(ModelTime::new(2, 13, 45), 4))
+    );
+
+    let clock = fast_clock(ModelTime::new(2, 13, 45), 4);
+    assert_eq!((clock.mins(), clock.hours(), clock.days()), (0x70, 0x75, 2));
+    assert_eq!(clock.clk_cntrl(), 0x40);
+}
@@ -0,0 +1,311 @@
+use crate::control::rail_system::components::{Address, SLevel, Speed, SwDir, TrainDirection};
+use crate::control::rail_system::railroad::Railroad;
+use crate::general::AddressType;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio::sync::broadcast::Receiver;
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+
+use super::RailroadConnector;
+
+type MarklinRailroad = Railroad<u8, MarklinAddress, u16, MarklinAddress, u16, u16>;
+type RailroadContainer = Arc<Mutex<Vec<Arc<MarklinRailroad>>>>;
+type SendMessage = crate::control::messages::Message<u8, MarklinAddress, u16, MarklinAddress, u16>;
+
+/// The UDP port the Central Station sends its frames to.
+pub const MARKLIN_RECEIVE_PORT: u16 = 15730;
+/// The UDP port the Central Station receives frames on.
+pub const MARKLIN_SEND_PORT: u16 = 15731;
+
+/// The hash identifying this connector on the CAN bus.
+/// Bits 7 to 9 have to be `0b110` to tell hashes apart from CS1 loco UIDs.
+const HASH: u16 = 0x0B00;
+const FRAME_LENGTH: usize = 13;
+
+const CMD_SYSTEM: u8 = 0x00;
+const CMD_LOCO_SPEED: u8 = 0x04;
+const CMD_LOCO_DIRECTION: u8 = 0x05;
+const CMD_LOCO_FUNCTION: u8 = 0x06;
+const CMD_ACCESSORY: u8 = 0x0B;
+const CMD_S88_EVENT: u8 = 0x11;
+
+const SYSTEM_STOP: u8 = 0x00;
+const SYSTEM_GO: u8 = 0x01;
+const SYSTEM_LOCO_EMERGENCY_STOP: u8 = 0x03;
+
+/// The highest speed a Central Station accepts.
+const MAX_SPEED: u16 = 1000;
+/// The time an accessory output is powered.
+const ACCESSORY_PULSE: Duration = Duration::from_millis(200);
+
+/// A decoder address of the Märklin digital system.
+///
+/// Locomotives are addressed by their protocol, accessories by their Motorola or DCC
+/// address starting at 1.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum MarklinAddress {
+    /// Motorola address
+    Motorola(u16),
+    /// The session id assigned to a mfx decoder
+    Mfx(u16),
+    /// DCC address
+    Dcc(u16),
+}
+
+impl AddressType for MarklinAddress {}
+
+impl MarklinAddress {
+    /// Returns the CAN UID of the locomotive decoder with this address.
+    pub fn loco_uid(&self) -> u32 {
+        match *self {
+            MarklinAddress::Motorola(adr) => adr as u32,
+            MarklinAddress::Mfx(sid) => 0x4000 + sid as u32,
+            MarklinAddress::Dcc(adr) => 0xC000 + adr as u32,
+        }
+    }
+
+    /// Returns the CAN UID of the accessory decoder with this address.
+    /// Mfx and address 0 could not be used for accessories.
+    pub fn accessory_uid(&self) -> Option<u32> {
+        match *self {
+            MarklinAddress::Motorola(adr) if adr > 0 => Some(0x3000 + adr as u32 - 1),
+            MarklinAddress::Dcc(adr) if adr > 0 => Some(0x3800 + adr as u32 - 1),
+            _ => None,
+        }
+    }
+
+    pub fn from_loco_uid(uid: u32) -> Option<Self> {
+        match uid {
+            0x0000..=0x03FF => Some(MarklinAddress::Motorola(uid as u16)),
+            0x4000..=0x7FFF => Some(MarklinAddress::Mfx((uid - 0x4000) as u16)),
+            0xC000..=0xFFFF => Some(MarklinAddress::Dcc((uid - 0xC000) as u16)),
+            _ => None,
+        }
+    }
+
+    pub fn from_accessory_uid(uid: u32) -> Option<Self> {
+        match uid {
+            0x3000..=0x33FF => Some(MarklinAddress::Motorola((uid - 0x3000) as u16 + 1)),
+            0x3800..=0x3FFF => Some(MarklinAddress::Dcc((uid - 0x3800) as u16 + 1)),
+            _ => None,
+        }
+    }
+}
+
+/// Connects to a Märklin Central Station 2 or 3 over its CAN protocol encapsulated in UDP.
+///
+/// Accessories are switched with the green output for [SwDir::Straight] and the red output
+/// for [SwDir::Curved]. S88 contacts are reported by their [contact number](s88_contact),
+/// which tells the contacts of different S88 buses apart.
+pub struct MarklinConnector {
+    receiver: Receiver<SendMessage>,
+    socket: Arc<UdpSocket>,
+    station: SocketAddr,
+    railroads: RailroadContainer,
+    task: JoinHandle<()>,
+}
+
+impl MarklinConnector {
+    /// Connects to the Central Station with the given ip address, using the default ports.
+    pub async fn connect(station: IpAddr, receiver: Receiver<SendMessage>) -> io::Result<Self> {
+        MarklinConnector::connect_with_ports(
+            SocketAddr::new([0, 0, 0, 0].into(), MARKLIN_RECEIVE_PORT),
+            SocketAddr::new(station, MARKLIN_SEND_PORT),
+            receiver,
+        )
+        .await
+    }
+
+    /// Listens for frames on `local` and sends frames to `station`.
+    pub async fn connect_with_ports(
+        local: SocketAddr,
+        station: SocketAddr,
+        receiver: Receiver<SendMessage>,
+    ) -> io::Result<Self> {
+        let socket = Arc::new(UdpSocket::bind(local).await?);
+
+        let railroads: RailroadContainer = Arc::new(Mutex::new(vec![]));
+        let task = tokio::spawn(MarklinConnector::forward_feedback(
+            socket.clone(),
+            railroads.clone(),
+        ));
+
+        Ok(MarklinConnector {
+            receiver,
+            socket,
+            station,
+            railroads,
+            task,
+        })
+    }
+
+    /// Returns the address frames of the Central Station are received on.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    /// Handles all feedback received from the Central Station by every registered railroad.
+    async fn forward_feedback(socket: Arc<UdpSocket>, railroads: RailroadContainer) {
+        let mut buffer = [0u8; 1500];
+        loop {
+            let received = match socket.recv(&mut buffer).await {
+                Ok(received) => received,
+                Err(_err) => continue,
+            };
+
+            for frame in buffer[..received].chunks_exact(FRAME_LENGTH) {
+                if let Some(feedback) = MarklinConnector::feedback(frame) {
+                    for railroad in railroads.lock().await.iter() {
+                        Railroad::handle_feedback(railroad.clone(), feedback).await;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Translates the response frames of the Central Station into feedback messages of the
+    /// rail system.
+    fn feedback(frame: &[u8]) -> Option<SendMessage> {
+        let id = u32::from_be_bytes([frame[0], frame[1], frame[2], frame[3]]);
+        let command = (id >> 17) as u8;
+        let response = id & 0x0001_0000 != 0;
+        let data = &frame[5..FRAME_LENGTH];
+        let uid = u32::from_be_bytes([data[0], data[1], data[2], data[3]]);
+
+        if !response {
+            return None;
+        }
+
+        match command {
+            CMD_SYSTEM => match data[4] {
+                SYSTEM_GO => Some(SendMessage::RailOnAck),
+                SYSTEM_STOP => Some(SendMessage::RailOffAck),
+                _ => None,
+            },
+            CMD_ACCESSORY => Some(SendMessage::SwitchAck(
+                Address::new(MarklinAddress::from_accessory_uid(uid)?),
+                SwDir::from(data[4] == 1),
+            )),
+            CMD_S88_EVENT => Some(SendMessage::UpdateSensor(
+                Address::new(s88_contact(
+                    u16::from_be_bytes([data[0], data[1]]),
+                    u16::from_be_bytes([data[2], data[3]]),
+                )?),
+                if data[5] == 0 {
+                    SLevel::Free
+                } else {
+                    SLevel::Occupied
+                },
+            )),
+            _ => None,
+        }
+    }
+
+    /// Translates a message of the rail system into a frame for the Central Station.
+    fn command(message: SendMessage) -> Option<Vec<u8>> {
+        match message {
+            SendMessage::RailOn => Some(frame(CMD_SYSTEM, &[0, 0, 0, 0, SYSTEM_GO])),
+            SendMessage::RailOff => Some(frame(CMD_SYSTEM, &[0, 0, 0, 0, SYSTEM_STOP])),
+            SendMessage::TrainSpeed(adr, Speed::EmergencyStop) => {
+                let [a, b, c, d] = adr.address().loco_uid().to_be_bytes();
+                Some(frame(CMD_SYSTEM, &[a, b, c, d, SYSTEM_LOCO_EMERGENCY_STOP]))
+            }
+            SendMessage::TrainSpeed(adr, speed) => {
+                let speed = match speed {
+                    Speed::Drive(spd) => (spd.min(126) as u16) * MAX_SPEED / 126,
+                    _ => 0,
+                };
+                let [a, b, c, d] = adr.address().loco_uid().to_be_bytes();
+                let [high, low] = speed.to_be_bytes();
+                Some(frame(CMD_LOCO_SPEED, &[a, b, c, d, high, low]))
+            }
+            // The Central Station stops a locomotive, when its direction changes.
+            SendMessage::TrainDirection(adr, direction) => {
+                let [a, b, c, d] = adr.address().loco_uid().to_be_bytes();
+                let direction = match direction {
+                    TrainDirection::Forward => 1,
+                    TrainDirection::Backward => 2,
+                };
+                Some(frame(CMD_LOCO_DIRECTION, &[a, b, c, d, direction]))
+            }
+            SendMessage::TrainFunction(adr, function, on) => {
+                let [a, b, c, d] = adr.address().loco_uid().to_be_bytes();
+                Some(frame(CMD_LOCO_FUNCTION, &[a, b, c, d, function, on as u8]))
+            }
+            SendMessage::Switch(adr, dir) => accessory_frame(adr, dir, true),
+            // Signal aspects are not sent, as signal addresses do not tell the decoder protocol.
+            _ => None,
+        }
+    }
+}
+
+impl Drop for MarklinConnector {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Returns the number of the S88 `contact` of the given `device`, as the Central Station
+/// numbers it: `device * 1000 + contact`.
+/// Device 0 is the S88 bus of the Central Station itself, so its contacts keep their number.
+/// Devices like the LinkS88 start with 1, so contact 7 of device 1 is reported as 1007.
+/// Contact numbers exceeding the sensor address range are ignored.
+pub fn s88_contact(device: u16, contact: u16) -> Option<u16> {
+    device.checked_mul(1000)?.checked_add(contact)
+}
+
+/// Creates a frame powering or unpowering the output for `dir` of an accessory.
+fn accessory_frame(adr: Address<MarklinAddress>, dir: SwDir, power: bool) -> Option<Vec<u8>> {
+    let [a, b, c, d] = adr.address().accessory_uid()?.to_be_bytes();
+    let position = match dir {
+        SwDir::Straight => 1,
+        SwDir::Curved => 0,
+    };
+    Some(frame(CMD_ACCESSORY, &[a, b, c, d, position, power as u8]))
+}
+
+/// Creates a frame sending `data` with the given command.
+fn frame(command: u8, data: &[u8]) -> Vec<u8> {
+    let id = (command as u32) << 17 | HASH as u32;
+    let mut frame = Vec::with_capacity(FRAME_LENGTH);
+    frame.extend_from_slice(&id.to_be_bytes());
+    frame.push(data.len() as u8);
+    frame.extend_from_slice(data);
+    frame.resize(FRAME_LENGTH, 0);
+    frame
+}
+
+#[async_trait]
+impl RailroadConnector<u8, MarklinAddress, u16, MarklinAddress, u16, u16> for MarklinConnector {
+    async fn handle_message(&mut self, message: SendMessage) {
+        if let Some(frame) = MarklinConnector::command(message) {
+            let _ = self.socket.send_to(&frame, self.station).await;
+        }
+
+        // Accessory outputs have to be switched off again, after the accessory moved.
+        if let SendMessage::Switch(adr, dir) = message {
+            if let Some(frame) = accessory_frame(adr, dir, false) {
+                let socket = self.socket.clone();
+                let station = self.station;
+                tokio::spawn(async move {
+                    tokio::time::sleep(ACCESSORY_PULSE).await;
+                    let _ = socket.send_to(&frame, station).await;
+                });
+            }
+        }
+    }
+
+    async fn reciever(&mut self) -> &mut Receiver<SendMessage> {
+        &mut self.receiver
+    }
+
+    async fn register_railroad(&mut self, railroad: Arc<MarklinRailroad>) {
+        self.railroads.lock().await.push(railroad.clone());
+    }
+}
@@ -0,0 +1,191 @@
+use crate::control::connectors::connector_test::next_feedback;
+use crate::control::connectors::marklin_connector::{
+    s88_contact, MarklinAddress, MarklinConnector,
+};
+use crate::control::connectors::RailroadConnector;
+use crate::control::messages::Message;
+use crate::control::rail_system::components::{
+    Address, Coord, Direction, Position, SLevel, Speed, SwDir, SwitchType, TrainDirection,
+};
+use crate::control::rail_system::railroad::{Builder, Railroad};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio::sync::broadcast::Receiver;
+use tokio::time::timeout;
+
+type MarklinMessage = Message<u8, MarklinAddress, u16, MarklinAddress, u16>;
+
+async fn receive(station: &UdpSocket) -> Vec<u8> {
+    let mut buffer = [0u8; 64];
+    let read = timeout(Duration::from_secs(1), station.recv(&mut buffer))
+        .await
+        .unwrap()
+        .unwrap();
+    buffer[..read].to_vec()
+}
+
+async fn create_marklin_railroad() -> Railroad<u8, MarklinAddress, u16, MarklinAddress, u16, u16> {
+    let mut builder = Builder::new();
+    builder.add_sensor(
+        Address::new(7),
+        Speed::Drive(100),
+        Position::new(Coord(0, 0, 0), Direction::East),
+    );
+    builder.add_switch(
+        Address::new(MarklinAddress::Motorola(5)),
+        Position::new(Coord(4, 0, 0), Direction::East),
+        SwitchType::StraightRight90,
+    );
+    builder.build().await
+}
+
+/// Binds a Central Station stand-in and connects to it.
+async fn connect(receiver: Receiver<MarklinMessage>) -> (MarklinConnector, UdpSocket) {
+    let station = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let connector = MarklinConnector::connect_with_ports(
+        "127.0.0.1:0".parse().unwrap(),
+        station.local_addr().unwrap(),
+        receiver,
+    )
+    .await
+    .unwrap();
+    station
+        .connect(connector.local_addr().unwrap())
+        .await
+        .unwrap();
+    (connector, station)
+}
+
+#[test]
+pub fn test_marklin_addresses() {
+    let addresses = [
+        MarklinAddress::Motorola(78),
+        MarklinAddress::Mfx(12),
+        MarklinAddress::Dcc(1024),
+    ];
+    for address in addresses {
+        assert_eq!(
+            MarklinAddress::from_loco_uid(address.loco_uid()),
+            Some(address)
+        );
+    }
+    assert_eq!(MarklinAddress::Mfx(12).loco_uid(), 0x400C);
+
+    assert_eq!(MarklinAddress::Dcc(1).accessory_uid(), Some(0x3800));
+    assert_eq!(
+        MarklinAddress::from_accessory_uid(0x3004),
+        Some(MarklinAddress::Motorola(5))
+    );
+    assert_eq!(MarklinAddress::Mfx(3).accessory_uid(), None);
+
+    assert_eq!(s88_contact(0, 7), Some(7));
+    assert_eq!(s88_contact(65, 535), Some(u16::MAX));
+    assert_eq!(s88_contact(66, 0), None);
+}
+
+#[tokio::test]
+pub async fn test_marklin_commands() {
+    let r = create_marklin_railroad().await;
+    let (mut connector, station) = connect(r.subscribe()).await;
+
+    connector.handle_message(Message::RailOn).await;
+    assert_eq!(
+        receive(&station).await,
+        vec![0x00, 0x00, 0x0B, 0x00, 5, 0, 0, 0, 0, 0x01, 0, 0, 0]
+    );
+
+    connector
+        .handle_message(Message::TrainSpeed(
+            Address::new(MarklinAddress::Mfx(5)),
+            Speed::Drive(63),
+        ))
+        .await;
+    assert_eq!(
+        receive(&station).await,
+        vec![0x00, 0x08, 0x0B, 0x00, 6, 0x00, 0x00, 0x40, 0x05, 0x01, 0xF4, 0, 0]
+    );
+
+    connector
+        .handle_message(Message::TrainDirection(
+            Address::new(MarklinAddress::Mfx(5)),
+            TrainDirection::Backward,
+        ))
+        .await;
+    assert_eq!(
+        receive(&station).await,
+        vec![0x00, 0x0A, 0x0B, 0x00, 5, 0x00, 0x00, 0x40, 0x05, 0x02, 0, 0, 0]
+    );
+
+    connector
+        .handle_message(Message::TrainFunction(
+            Address::new(MarklinAddress::Mfx(5)),
+            2,
+            true,
+        ))
+        .await;
+    assert_eq!(
+        receive(&station).await,
+        vec![0x00, 0x0C, 0x0B, 0x00, 6, 0x00, 0x00, 0x40, 0x05, 0x02, 0x01, 0, 0]
+    );
+
+    connector
+        .handle_message(Message::Switch(
+            Address::new(MarklinAddress::Dcc(3)),
+            SwDir::Straight,
+        ))
+        .await;
+    assert_eq!(
+        receive(&station).await,
+        vec![0x00, 0x16, 0x0B, 0x00, 6, 0x00, 0x00, 0x38, 0x02, 0x01, 0x01, 0, 0]
+    );
+    assert_eq!(
+        receive(&station).await,
+        vec![0x00, 0x16, 0x0B, 0x00, 6, 0x00, 0x00, 0x38, 0x02, 0x01, 0x00, 0, 0]
+    );
+}
+
+#[tokio::test]
+pub async fn test_marklin_feedback() {
+    let r = Arc::new(create_marklin_railroad().await);
+    let mut feedback = r.subscribe();
+    let (mut connector, station) = connect(r.subscribe()).await;
+    connector.register_railroad(r.clone()).await;
+
+    // S88 events of the Central Station and a LinkS88 followed by the response to switching an
+    // accessory in one datagram.
+    let mut datagram = vec![
+        0x00, 0x23, 0x4F, 0x12, 8, 0x00, 0x00, 0x00, 0x07, 0x00, 0x01, 0x00, 0x00,
+    ];
+    datagram.extend_from_slice(&[
+        0x00, 0x23, 0x4F, 0x12, 8, 0x00, 0x01, 0x00, 0x07, 0x01, 0x00, 0x00, 0x00,
+    ]);
+    datagram.extend_from_slice(&[
+        0x00, 0x17, 0x4F, 0x12, 6, 0x00, 0x00, 0x30, 0x04, 0x01, 0x01, 0, 0,
+    ]);
+    station.send(&datagram).await.unwrap();
+
+    assert_eq!(
+        next_feedback(&mut feedback).await,
+        Message::UpdateSensor(Address::new(7), SLevel::Occupied)
+    );
+    assert_eq!(
+        next_feedback(&mut feedback).await,
+        Message::UpdateSensor(Address::new(1007), SLevel::Free)
+    );
+    assert_eq!(
+        next_feedback(&mut feedback).await,
+        Message::SwitchAck(Address::new(MarklinAddress::Motorola(5)), SwDir::Straight)
+    );
+
+    // Requests of other devices are no feedback.
+    station
+        .send(&[0x00, 0x00, 0x4F, 0x12, 5, 0, 0, 0, 0, 0x00, 0, 0, 0])
+        .await
+        .unwrap();
+    station
+        .send(&[0x00, 0x01, 0x4F, 0x12, 5, 0, 0, 0, 0, 0x01, 0, 0, 0])
+        .await
+        .unwrap();
+    assert_eq!(next_feedback(&mut feedback).await, Message::RailOnAck);
+}
@@ -7,12 +7,74 @@ use crate::general::{AddressType, SpeedType};
 
 use super::{messages::Message, rail_system::railroad::Railroad};
 
+/// Helpers shared by the tests of the network connectors
+#[cfg(all(
+    test,
+    any(
+        feature = "dccex_connect",
+        feature = "z21_connect",
+        feature = "marklin_connect"
+    )
+))]
+mod connector_test;
+
 /// Note: Only available, when  locodrive  dependency is activated.
 ///
 /// Handles the connection between this controlling programm and a Railroad controlled by the
 /// protocoll implemented in the locodrive project.
 #[cfg(feature = "locodrive")]
 pub mod locodrive_connector;
+/// Tests translating LocoNet messages
+#[cfg(all(test, feature = "locodrive"))]
+mod locodrive_connector_test;
+
+/// Note: Only available, when the `dccex_connect` feature is activated.
+///
+/// Handles the connection to a DCC-EX command station over its text protocol.
+#[cfg(feature = "dccex_connect")]
+pub mod dccex_connector;
+/// Tests talking to an in-memory DCC-EX command station
+#[cfg(all(test, feature = "dccex_connect"))]
+mod dccex_connector_test;
+
+/// Note: Only available, when the `z21_connect` feature is activated.
+///
+/// Handles the connection to a Roco/Fleischmann Z21 over its UDP LAN protocol.
+#[cfg(feature = "z21_connect")]
+pub mod z21_connector;
+/// Tests talking to a local Z21 stand-in
+#[cfg(all(test, feature = "z21_connect"))]
+mod z21_connector_test;
+
+/// Note: Only available, when the `marklin_connect` feature is activated.
+///
+/// Handles the connection to a Märklin Central Station over its CAN protocol encapsulated in UDP.
+#[cfg(feature = "marklin_connect")]
+pub mod marklin_connector;
+/// Tests talking to a local Central Station stand-in
+#[cfg(all(test, feature = "marklin_connect"))]
+mod marklin_connector_test;
+
+/// Note: Only available, when the `trace_connect` feature is activated.
+///
+/// Records the messages of a railroad to a trace and replays recorded traces.
+#[cfg(feature = "trace_connect")]
+pub mod trace_connector;
+/// Tests recording and replaying traces
+#[cfg(all(test, feature = "trace_connect"))]
+mod trace_connector_test;
+
+/// Simulates a physical railroad, so the rail system can be driven without any hardware.
+pub mod simulation_connector;
+/// Tests driving a railroad with the simulation connector
+#[cfg(test)]
+mod simulation_connector_test;
+
+/// Fans the messages of one railroad out to multiple connectors.
+pub mod multiplex_connector;
+/// Tests routing messages to multiple connectors
+#[cfg(test)]
+mod multiplex_connector_test;
 
 /// General Railroad connector to connect physical railroads with this programm
 #[async_trait]
@@ -54,6 +116,6 @@ pub trait RailroadConnector<
 
     async fn register_railroad(
         &mut self,
-        railroad: Arc<Railroad<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr>>,
+        railroad: Arc<Railroad<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr, CrossingAddr>>,
     );
 }
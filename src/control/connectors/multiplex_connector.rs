@@ -0,0 +1,299 @@
+use crate::control::messages::Message;
+use crate::control::rail_system::components::Address;
+use crate::control::rail_system::railroad::Railroad;
+use crate::general::{AddressType, DefaultAddressType, DefaultSpeedType, SpeedType};
+use async_trait::async_trait;
+use std::collections::HashSet;
+use std::ops::RangeInclusive;
+use std::sync::Arc;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::broadcast::{channel, Receiver, Sender};
+use tokio::task::JoinHandle;
+
+use super::RailroadConnector;
+
+type Backend<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr, CrossingAddr> = (
+    Routing<TrainAddr, SensorAddr, SwitchAddr, SignalAddr>,
+    Box<
+        dyn RailroadConnector<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr, CrossingAddr>
+            + Send,
+    >,
+);
+type Started<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr> = (
+    Routing<TrainAddr, SensorAddr, SwitchAddr, SignalAddr>,
+    Sender<Message<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr>>,
+);
+
+/// The number of messages buffered for every running connector.
+const CAPACITY: usize = 25;
+
+/// The addresses of one kind of component a connector is responsible for.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum Route<Addr: AddressType> {
+    /// Every address
+    #[default]
+    All,
+    /// Every address in the range
+    Range(RangeInclusive<Addr>),
+    /// Only the listed addresses
+    Addresses(HashSet<Addr>),
+}
+
+impl<Addr: AddressType> Route<Addr> {
+    /// A route without any address.
+    pub fn none() -> Self {
+        Route::Addresses(HashSet::new())
+    }
+
+    pub fn contains(&self, adr: &Address<Addr>) -> bool {
+        match self {
+            Route::All => true,
+            Route::Range(range) => range.contains(&adr.address()),
+            Route::Addresses(addresses) => addresses.contains(&adr.address()),
+        }
+    }
+}
+
+impl<Addr: AddressType> From<RangeInclusive<Addr>> for Route<Addr> {
+    fn from(range: RangeInclusive<Addr>) -> Self {
+        Route::Range(range)
+    }
+}
+
+impl<Addr: AddressType> FromIterator<Addr> for Route<Addr> {
+    fn from_iter<T: IntoIterator<Item = Addr>>(iter: T) -> Self {
+        Route::Addresses(iter.into_iter().collect())
+    }
+}
+
+/// Selects the messages a connector of a [MultiplexConnector] handles.
+///
+/// By default a connector handles the messages of all components.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Routing<
+    TrainAddr: AddressType,
+    SensorAddr: AddressType,
+    SwitchAddr: AddressType,
+    SignalAddr: AddressType,
+> {
+    pub trains: Route<TrainAddr>,
+    pub sensors: Route<SensorAddr>,
+    pub switches: Route<SwitchAddr>,
+    pub signals: Route<SignalAddr>,
+}
+
+impl<
+        TrainAddr: AddressType,
+        SensorAddr: AddressType,
+        SwitchAddr: AddressType,
+        SignalAddr: AddressType,
+    > Routing<TrainAddr, SensorAddr, SwitchAddr, SignalAddr>
+{
+    /// A routing handling no component at all.
+    /// Only the track power is switched by connectors with this routing.
+    pub fn none() -> Self {
+        Routing {
+            trains: Route::none(),
+            sensors: Route::none(),
+            switches: Route::none(),
+            signals: Route::none(),
+        }
+    }
+
+    pub fn trains(mut self, trains: impl Into<Route<TrainAddr>>) -> Self {
+        self.trains = trains.into();
+        self
+    }
+
+    pub fn sensors(mut self, sensors: impl Into<Route<SensorAddr>>) -> Self {
+        self.sensors = sensors.into();
+        self
+    }
+
+    pub fn switches(mut self, switches: impl Into<Route<SwitchAddr>>) -> Self {
+        self.switches = switches.into();
+        self
+    }
+
+    pub fn signals(mut self, signals: impl Into<Route<SignalAddr>>) -> Self {
+        self.signals = signals.into();
+        self
+    }
+
+    /// Checks if a message should be handled by a connector with this routing.
+    ///
+    /// Switching the track power and the time of the fast clock are handled by every connector.
+    /// Feedback is reported by the connectors, so it is never routed to them.
+    /// Neither are reported deadlocks, which no hardware could resolve.
+    pub fn routes<Spd: SpeedType>(
+        &self,
+        message: &Message<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr>,
+    ) -> bool {
+        match message {
+            Message::RailOn | Message::RailOff | Message::ClockTick(..) => true,
+            Message::TrainSpeed(adr, _)
+            | Message::TrainDirection(adr, _)
+            | Message::TrainFunction(adr, ..)
+            | Message::TrainRemoved(adr) => self.trains.contains(adr),
+            Message::Switch(adr, _) => self.switches.contains(adr),
+            Message::UpdateSignal(adr, _) | Message::TrainGranted(adr, _) => {
+                self.signals.contains(adr)
+            }
+            Message::TrainOnSensor(adr, _) => self.sensors.contains(adr),
+            Message::RailOnAck
+            | Message::RailOffAck
+            | Message::SwitchAck(..)
+            | Message::UpdateSensor(..)
+            | Message::ConnectionState(..)
+            | Message::TrainTakenOver(..)
+            | Message::TrainDeadlocked(..)
+            | Message::ClockSync(..) => false,
+        }
+    }
+}
+
+/// Fans the messages of a railroad out to multiple connectors, so one railroad can be
+/// controlled by multiple command stations, e.g. the locomotives over LocoNet and the
+/// accessories over a DCC-EX booster.
+///
+/// Every message is handled by all connectors whose [Routing] routes it.
+/// [RailroadConnector::start_connectors] spawns the loop of every connector on its own task,
+/// so connectors advancing simulations, replaying traces or checking their connection keep
+/// doing so. Their receivers are replaced by receivers of the routed messages only.
+/// Registered railroads are registered at every connector,
+/// which report their feedback to the railroads themselves.
+/// Railroads should be registered before starting the connectors.
+pub struct MultiplexConnector<
+    Spd: SpeedType = DefaultSpeedType,
+    TrainAddr: AddressType = DefaultAddressType,
+    SensorAddr: AddressType = DefaultAddressType,
+    SwitchAddr: AddressType = DefaultAddressType,
+    SignalAddr: AddressType = DefaultAddressType,
+    CrossingAddr: AddressType = DefaultAddressType,
+> {
+    receiver: Receiver<Message<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr>>,
+    backends: Vec<Backend<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr, CrossingAddr>>,
+    /// The routing of every started connector and the sender of its routed messages
+    started: Vec<Started<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr>>,
+    /// The tasks running the started connectors
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl<
+        Spd: SpeedType,
+        TrainAddr: AddressType,
+        SensorAddr: AddressType,
+        SwitchAddr: AddressType,
+        SignalAddr: AddressType,
+        CrossingAddr: AddressType,
+    > MultiplexConnector<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr, CrossingAddr>
+{
+    pub fn new(
+        receiver: Receiver<Message<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr>>,
+    ) -> Self {
+        MultiplexConnector {
+            receiver,
+            backends: vec![],
+            started: vec![],
+            tasks: vec![],
+        }
+    }
+
+    /// Adds a connector handling all messages routed by `routing`.
+    ///
+    /// Connectors should be added before registering a railroad,
+    /// as railroads are only registered at the connectors known at that time.
+    pub fn add_connector<
+        C: RailroadConnector<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr, CrossingAddr>
+            + Send
+            + 'static,
+    >(
+        &mut self,
+        connector: C,
+        routing: Routing<TrainAddr, SensorAddr, SwitchAddr, SignalAddr>,
+    ) -> &mut Self {
+        self.backends.push((routing, Box::new(connector)));
+        self
+    }
+}
+
+#[async_trait]
+impl<
+        Spd: SpeedType,
+        TrainAddr: AddressType,
+        SensorAddr: AddressType,
+        SwitchAddr: AddressType,
+        SignalAddr: AddressType,
+        CrossingAddr: AddressType,
+    > RailroadConnector<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr, CrossingAddr>
+    for MultiplexConnector<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr, CrossingAddr>
+{
+    async fn handle_message(
+        &mut self,
+        message: Message<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr>,
+    ) {
+        for (routing, connector) in self.backends.iter_mut() {
+            if routing.routes(&message) {
+                connector.handle_message(message).await;
+            }
+        }
+        for (routing, sender) in &self.started {
+            if routing.routes(&message) {
+                let _ = sender.send(message);
+            }
+        }
+    }
+
+    async fn reciever(
+        &mut self,
+    ) -> &mut Receiver<Message<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr>> {
+        &mut self.receiver
+    }
+
+    /// Starts every connector on its own task and routes the messages of the railroad to them,
+    /// until the railroad closes its channel.
+    async fn start_connectors(&mut self) {
+        for (routing, mut connector) in self.backends.drain(..) {
+            let (sender, receiver) = channel(CAPACITY);
+            *connector.reciever().await = receiver;
+            self.started.push((routing, sender));
+            self.tasks.push(tokio::spawn(
+                async move { connector.start_connectors().await },
+            ));
+        }
+
+        loop {
+            match self.receiver.recv().await {
+                Ok(msg) => self.handle_message(msg).await,
+                Err(RecvError::Closed) => break,
+                Err(RecvError::Lagged(_)) => continue,
+            }
+        }
+        // Closing the channels stops the connectors.
+        self.started.clear();
+    }
+
+    async fn register_railroad(
+        &mut self,
+        railroad: Arc<Railroad<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr, CrossingAddr>>,
+    ) {
+        for (_routing, connector) in self.backends.iter_mut() {
+            connector.register_railroad(railroad.clone()).await;
+        }
+    }
+}
+
+impl<
+        Spd: SpeedType,
+        TrainAddr: AddressType,
+        SensorAddr: AddressType,
+        SwitchAddr: AddressType,
+        SignalAddr: AddressType,
+        CrossingAddr: AddressType,
+    > Drop
+    for MultiplexConnector<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr, CrossingAddr>
+{
+    fn drop(&mut self) {
+        self.tasks.iter().for_each(JoinHandle::abort);
+    }
+}
@@ -0,0 +1,132 @@
+use crate::control::connectors::multiplex_connector::{MultiplexConnector, Route, Routing};
+use crate::control::connectors::simulation_connector::{SimulationConfig, SimulationConnector};
+use crate::control::connectors::RailroadConnector;
+use crate::control::messages::Message;
+use crate::control::rail_system::components::{Address, Aspect, SLevel, Speed, SwDir};
+use crate::control::rail_system::railroad::Railroad;
+use crate::control::rail_system::railroad_test::{create_line_railroad, create_test_railroad};
+use async_trait::async_trait;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast::Receiver;
+use tokio::sync::Mutex;
+
+type TestMessage = Message<u8, u16, u16, u16, u16>;
+
+/// Remembers all handled messages and registered railroads.
+struct TestConnector {
+    receiver: Receiver<TestMessage>,
+    messages: Arc<Mutex<Vec<TestMessage>>>,
+    railroads: Arc<Mutex<usize>>,
+}
+
+#[async_trait]
+impl RailroadConnector<u8, u16, u16, u16, u16, u16> for TestConnector {
+    async fn handle_message(&mut self, message: TestMessage) {
+        self.messages.lock().await.push(message);
+    }
+
+    async fn reciever(&mut self) -> &mut Receiver<TestMessage> {
+        &mut self.receiver
+    }
+
+    async fn register_railroad(&mut self, _railroad: Arc<Railroad>) {
+        *self.railroads.lock().await += 1;
+    }
+}
+
+#[tokio::test]
+pub async fn test_multiplex_routing() {
+    let (r, ..) = create_test_railroad().await;
+    let r = Arc::new(r);
+    let mut multiplexer = MultiplexConnector::new(r.subscribe());
+
+    let locos = Arc::new(Mutex::new(vec![]));
+    let accessories = Arc::new(Mutex::new(vec![]));
+    let railroads = Arc::new(Mutex::new(0));
+    multiplexer
+        .add_connector(
+            TestConnector {
+                receiver: r.subscribe(),
+                messages: locos.clone(),
+                railroads: railroads.clone(),
+            },
+            Routing::none().trains(1..=9),
+        )
+        .add_connector(
+            TestConnector {
+                receiver: r.subscribe(),
+                messages: accessories.clone(),
+                railroads: railroads.clone(),
+            },
+            Routing::none()
+                .switches([1, 5].into_iter().collect::<Route<u16>>())
+                .signals(Route::All),
+        );
+    multiplexer.register_railroad(r.clone()).await;
+    assert_eq!(*railroads.lock().await, 2);
+
+    let messages = [
+        Message::RailOn,
+        Message::TrainSpeed(Address::new(3), Speed::Drive(20)),
+        Message::TrainSpeed(Address::new(12), Speed::Stop),
+        Message::Switch(Address::new(5), SwDir::Curved),
+        Message::Switch(Address::new(2), SwDir::Curved),
+        Message::UpdateSignal(Address::new(7), Aspect::Proceed),
+        Message::SwitchAck(Address::new(5), SwDir::Curved),
+        Message::UpdateSensor(Address::new(1), SLevel::Occupied),
+    ];
+    for message in messages {
+        multiplexer.handle_message(message).await;
+    }
+
+    assert_eq!(
+        *locos.lock().await,
+        vec![
+            Message::RailOn,
+            Message::TrainSpeed(Address::new(3), Speed::Drive(20)),
+        ]
+    );
+    assert_eq!(
+        *accessories.lock().await,
+        vec![
+            Message::RailOn,
+            Message::Switch(Address::new(5), SwDir::Curved),
+            Message::UpdateSignal(Address::new(7), Aspect::Proceed),
+        ]
+    );
+}
+
+#[tokio::test(start_paused = true)]
+pub async fn test_multiplex_runs_connectors() {
+    let (r, [_first, _second, _signal, third]) = create_line_railroad().await;
+    let mut multiplexer = MultiplexConnector::new(r.subscribe());
+    let locos = Arc::new(Mutex::new(vec![]));
+    multiplexer
+        .add_connector(
+            SimulationConnector::new(r.subscribe(), SimulationConfig::default()),
+            Routing::default(),
+        )
+        .add_connector(
+            TestConnector {
+                receiver: r.subscribe(),
+                messages: locos.clone(),
+                railroads: Arc::new(Mutex::new(0)),
+            },
+            Routing::none().trains(2..=9),
+        );
+    multiplexer.register_railroad(r.clone()).await;
+    let tasks = [
+        tokio::spawn(async move { multiplexer.start_connectors().await }),
+        Railroad::start_scheduler(r.clone(), Duration::from_millis(50)),
+    ];
+
+    // The simulation runs on its own, so the train is reported on the sensors of its route.
+    let train = r.get_train(&Address::new(1)).unwrap();
+    assert!(train.lock().await.trigger_drive_to(third, r.clone()).await);
+    tokio::time::sleep(Duration::from_secs(5)).await;
+    assert_eq!(train.lock().await.position(), third);
+    assert!(locos.lock().await.is_empty());
+
+    tasks.iter().for_each(|task| task.abort());
+}
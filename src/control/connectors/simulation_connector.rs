@@ -0,0 +1,416 @@
+use crate::control::messages::Message;
+use crate::control::rail_system::components::{
+    Address, Node, Rail, SLevel, Speed, SwDir, TrainDirection,
+};
+use crate::control::rail_system::railroad::{reversed_node, Railroad};
+use crate::general::{AddressType, DefaultAddressType, DefaultSpeedType, SpeedType};
+use async_trait::async_trait;
+use num_traits::ToPrimitive;
+use petgraph::graph::{DiGraph, NodeIndex};
+use petgraph::Direction;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::select;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::broadcast::Receiver;
+
+use super::RailroadConnector;
+
+type SimulatedRoad<SensorAddr, SwitchAddr, SignalAddr, CrossingAddr> =
+    DiGraph<Node<SensorAddr, SwitchAddr, SignalAddr, CrossingAddr>, Vec<Rail>>;
+type SimulatedRailroad<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr, CrossingAddr> =
+    Option<Arc<Railroad<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr, CrossingAddr>>>;
+type Feedback<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr> = Vec<(
+    Duration,
+    Message<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr>,
+)>;
+
+/// Timing of the simulated hardware.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct SimulationConfig {
+    /// The time a switch needs to move before it is acknowledged
+    pub switch_delay: Duration,
+    /// The time between a sensor changing its level and reporting it
+    pub sensor_latency: Duration,
+    /// The interval the simulation is advanced in by [SimulationConnector::start_connectors]
+    pub tick: Duration,
+    /// The distance a train drives per second and speed step, measured in rail units
+    pub distance_per_speed_step: f64,
+}
+
+impl Default for SimulationConfig {
+    fn default() -> Self {
+        SimulationConfig {
+            switch_delay: Duration::from_millis(500),
+            sensor_latency: Duration::from_millis(50),
+            tick: Duration::from_millis(50),
+            distance_per_speed_step: 0.1,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+struct SimulatedTrain<SensorAddr: AddressType> {
+    /// The last node the train passed
+    node: NodeIndex,
+    /// The node the train is driving to
+    next: Option<NodeIndex>,
+    /// The distance driven since passing `node`
+    progress: f64,
+    /// The commanded speed in rail units per second
+    speed: f64,
+    /// The commanded direction of travel
+    direction: TrainDirection,
+    /// The sensor the train is reported on
+    sensor: Option<Address<SensorAddr>>,
+}
+
+/// Simulates a physical railroad from the rail graph of a [Railroad].
+///
+/// Trains drive along the rails with their commanded speed and follow the simulated switches.
+/// A sensor reports [SLevel::Occupied], when a train reaches it, and [SLevel::Free], when that
+/// train reaches the next sensor. Switches are acknowledged after the configured delay.
+/// Trains reverse onto the opposite direction of bidirectional sensors and at buffer stops.
+/// All feedback is handled by [Railroad::handle_feedback] of the registered railroad.
+///
+/// Only one railroad is simulated. Registering another one replaces the previous one.
+pub struct SimulationConnector<
+    Spd: SpeedType = DefaultSpeedType,
+    TrainAddr: AddressType = DefaultAddressType,
+    SensorAddr: AddressType = DefaultAddressType,
+    SwitchAddr: AddressType = DefaultAddressType,
+    SignalAddr: AddressType = DefaultAddressType,
+    CrossingAddr: AddressType = DefaultAddressType,
+> {
+    receiver: Receiver<Message<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr>>,
+    config: SimulationConfig,
+    railroad: SimulatedRailroad<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr, CrossingAddr>,
+    road: SimulatedRoad<SensorAddr, SwitchAddr, SignalAddr, CrossingAddr>,
+    trains: HashMap<Address<TrainAddr>, SimulatedTrain<SensorAddr>>,
+    switches: HashMap<Address<SwitchAddr>, SwDir>,
+    /// Feedback waiting to be sent, together with the simulation time to send it at
+    pending: Feedback<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr>,
+    now: Duration,
+}
+
+impl<
+        Spd: SpeedType + ToPrimitive,
+        TrainAddr: AddressType,
+        SensorAddr: AddressType,
+        SwitchAddr: AddressType,
+        SignalAddr: AddressType,
+        CrossingAddr: AddressType,
+    > SimulationConnector<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr, CrossingAddr>
+{
+    pub fn new(
+        receiver: Receiver<Message<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr>>,
+        config: SimulationConfig,
+    ) -> Self {
+        SimulationConnector {
+            receiver,
+            config,
+            railroad: None,
+            road: DiGraph::new(),
+            trains: HashMap::new(),
+            switches: HashMap::new(),
+            pending: Vec::new(),
+            now: Duration::ZERO,
+        }
+    }
+
+    /// Returns the last node the given train passed.
+    pub fn train_position(&self, train: &Address<TrainAddr>) -> Option<NodeIndex> {
+        Some(self.trains.get(train)?.node)
+    }
+
+    /// Returns the direction the given switch is physically set to.
+    pub fn switch_dir(&self, switch: &Address<SwitchAddr>) -> Option<SwDir> {
+        self.switches.get(switch).copied()
+    }
+
+    /// Moves all trains by the distance they drive in `elapsed`
+    /// and reports all feedback that is due afterwards.
+    pub async fn advance(&mut self, elapsed: Duration) {
+        self.now += elapsed;
+        self.send_due_feedback().await;
+
+        let addresses: Vec<_> = self.trains.keys().copied().collect();
+        for address in addresses {
+            self.drive(address, elapsed);
+        }
+
+        self.send_due_feedback().await;
+    }
+
+    async fn simulated_train(
+        &mut self,
+        address: Address<TrainAddr>,
+    ) -> Option<&mut SimulatedTrain<SensorAddr>> {
+        if !self.trains.contains_key(&address) {
+            let railroad = self.railroad.as_ref()?;
+            let position = railroad.get_train(&address)?.lock().await.position();
+            self.trains.insert(
+                address,
+                SimulatedTrain {
+                    node: position,
+                    next: None,
+                    progress: 0.0,
+                    speed: 0.0,
+                    direction: TrainDirection::Forward,
+                    sensor: self.sensor_of(position),
+                },
+            );
+        }
+        self.trains.get_mut(&address)
+    }
+
+    fn drive(&mut self, address: Address<TrainAddr>, elapsed: Duration) {
+        let mut train = match self.trains.get(&address) {
+            Some(train) => *train,
+            None => return,
+        };
+
+        let mut distance = train.speed * elapsed.as_secs_f64();
+        // Limits the passed nodes, so rails without length can not loop forever.
+        let mut passable = self.road.node_count();
+
+        while distance > 0.0 && passable > 0 {
+            let next = match train.next.or_else(|| self.next_node(train.node)) {
+                Some(next) => next,
+                None => break,
+            };
+            train.next = Some(next);
+
+            let remaining = self.rail_length(train.node, next) - train.progress;
+            if distance < remaining {
+                train.progress += distance;
+                break;
+            }
+
+            distance -= remaining;
+            passable -= 1;
+            train.node = next;
+            train.next = None;
+            train.progress = 0.0;
+
+            if let Some(sensor) = self.sensor_of(next) {
+                if train.sensor != Some(sensor) {
+                    self.report(Message::UpdateSensor(sensor, SLevel::Occupied));
+                    if let Some(old) = train.sensor.replace(sensor) {
+                        self.report(Message::UpdateSensor(old, SLevel::Free));
+                    }
+                }
+            }
+        }
+
+        self.trains.insert(address, train);
+    }
+
+    /// Turns a train around, so it drives back the way it came.
+    /// Trains can only be turned on bidirectional sensors and at buffer stops.
+    fn reverse(&self, train: &mut SimulatedTrain<SensorAddr>) {
+        let node = match self.reversed(train.node) {
+            Some(node) => node,
+            None => return,
+        };
+
+        match train.next {
+            Some(next) if train.progress > 0.0 => {
+                let next_reversed = match self.reversed(next) {
+                    Some(next_reversed) => next_reversed,
+                    None => return,
+                };
+                if self.road.find_edge(next_reversed, node).is_none() {
+                    return;
+                }
+                train.progress = (self.rail_length(next_reversed, node) - train.progress).max(0.0);
+                train.node = next_reversed;
+                train.next = Some(node);
+            }
+            _ => {
+                train.node = node;
+                train.next = None;
+                train.progress = 0.0;
+            }
+        }
+    }
+
+    /// Returns the node representing `node` in the opposite direction.
+    /// A buffer stop is passed in both directions.
+    fn reversed(&self, node: NodeIndex) -> Option<NodeIndex> {
+        match self.road.node_weight(node)? {
+            Node::Buffer(..) => Some(node),
+            _ => reversed_node(&self.road, node),
+        }
+    }
+
+    /// Returns the node a train passing `node` drives to, regarding the simulated switches.
+    fn next_node(&self, node: NodeIndex) -> Option<NodeIndex> {
+        let neighbours: Vec<_> = self
+            .road
+            .neighbors_directed(node, Direction::Outgoing)
+            .collect();
+
+        if neighbours.len() < 2 {
+            return neighbours.first().copied();
+        }
+
+        if let Some(Node::Switch(adr, _, _, default, _)) = self.road.node_weight(node) {
+            let dir = self.switches.get(adr).copied().unwrap_or(SwDir::Straight);
+            if let Some(default) = default.filter(|default| neighbours.contains(default)) {
+                return match dir {
+                    SwDir::Straight => Some(default),
+                    SwDir::Curved => neighbours.into_iter().find(|n| *n != default),
+                };
+            }
+            return match dir {
+                SwDir::Straight => neighbours.first().copied(),
+                SwDir::Curved => neighbours.last().copied(),
+            };
+        }
+
+        neighbours.first().copied()
+    }
+
+    fn rail_length(&self, from: NodeIndex, to: NodeIndex) -> f64 {
+        self.road
+            .find_edge(from, to)
+            .and_then(|edge| self.road.edge_weight(edge))
+            .map_or(0, |rails| rails.iter().map(Rail::manhattan_distance).sum()) as f64
+    }
+
+    fn sensor_of(&self, node: NodeIndex) -> Option<Address<SensorAddr>> {
+        match self.road.node_weight(node)? {
+            Node::Sensor(adr, _) | Node::Station(adr, _) => Some(*adr),
+            _ => None,
+        }
+    }
+
+    fn speed_of(&self, speed: Speed<Spd>) -> f64 {
+        match speed {
+            Speed::Drive(spd) => {
+                spd.to_f64().unwrap_or_default() * self.config.distance_per_speed_step
+            }
+            Speed::Stop | Speed::EmergencyStop => 0.0,
+        }
+    }
+
+    fn report(&mut self, message: Message<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr>) {
+        self.pending
+            .push((self.now + self.config.sensor_latency, message));
+    }
+
+    async fn send_due_feedback(&mut self) {
+        let now = self.now;
+        let (mut due, pending): (Vec<_>, Vec<_>) =
+            self.pending.drain(..).partition(|(time, _)| *time <= now);
+        self.pending = pending;
+        due.sort_by_key(|(time, _)| *time);
+
+        for (_time, message) in due {
+            if let Message::SwitchAck(adr, dir) = message {
+                self.switches.insert(adr, dir);
+            }
+            if let Some(railroad) = &self.railroad {
+                Railroad::handle_feedback(railroad.clone(), message).await;
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl<
+        Spd: SpeedType + ToPrimitive,
+        TrainAddr: AddressType,
+        SensorAddr: AddressType,
+        SwitchAddr: AddressType,
+        SignalAddr: AddressType,
+        CrossingAddr: AddressType,
+    > RailroadConnector<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr, CrossingAddr>
+    for SimulationConnector<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr, CrossingAddr>
+{
+    async fn handle_message(
+        &mut self,
+        message: Message<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr>,
+    ) {
+        match message {
+            Message::TrainSpeed(adr, speed) => {
+                let speed = self.speed_of(speed);
+                if let Some(train) = self.simulated_train(adr).await {
+                    train.speed = speed;
+                }
+            }
+            Message::TrainDirection(adr, direction) => {
+                if let Some(mut train) = self.simulated_train(adr).await.copied() {
+                    if train.direction != direction {
+                        train.direction = direction;
+                        self.reverse(&mut train);
+                        self.trains.insert(adr, train);
+                    }
+                }
+            }
+            Message::RailOn => self.pending.push((self.now, Message::RailOnAck)),
+            Message::RailOff => self.pending.push((self.now, Message::RailOffAck)),
+            Message::Switch(adr, dir) => {
+                self.pending.push((
+                    self.now + self.config.switch_delay,
+                    Message::SwitchAck(adr, dir),
+                ));
+            }
+            _ => {}
+        }
+    }
+
+    async fn reciever(
+        &mut self,
+    ) -> &mut Receiver<Message<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr>> {
+        &mut self.receiver
+    }
+
+    /// Handles the received messages and advances the simulation every configured tick.
+    async fn start_connectors(&mut self) {
+        let mut interval = tokio::time::interval(self.config.tick);
+        loop {
+            select! {
+                msg = self.receiver.recv() => match msg {
+                    Ok(msg) => self.handle_message(msg).await,
+                    Err(RecvError::Closed) => break,
+                    Err(_err) => continue,
+                },
+                _ = interval.tick() => self.advance(self.config.tick).await,
+            }
+        }
+    }
+
+    async fn register_railroad(
+        &mut self,
+        railroad: Arc<Railroad<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr, CrossingAddr>>,
+    ) {
+        self.road = railroad.road().await;
+        self.trains.clear();
+        self.pending.clear();
+
+        let state = railroad.state().await;
+        self.switches = state
+            .switches
+            .iter()
+            .map(|switch| (switch.address, switch.dir))
+            .collect();
+        for train in state.trains {
+            self.trains.insert(
+                train.address,
+                SimulatedTrain {
+                    node: train.position,
+                    next: None,
+                    progress: 0.0,
+                    speed: self.speed_of(train.speed),
+                    direction: train.direction,
+                    sensor: self.sensor_of(train.position),
+                },
+            );
+        }
+
+        self.railroad = Some(railroad);
+    }
+}
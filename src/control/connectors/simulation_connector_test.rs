@@ -0,0 +1,156 @@
+use crate::control::connectors::simulation_connector::{SimulationConfig, SimulationConnector};
+use crate::control::connectors::RailroadConnector;
+use crate::control::messages::Message;
+use crate::control::rail_system::components::{Address, SLevel, Speed, SwDir, TrainDirection};
+use crate::control::rail_system::railroad::Railroad;
+use crate::control::rail_system::railroad_test::{
+    create_switch_railroad, create_terminus_railroad,
+};
+use std::time::Duration;
+use tokio::sync::broadcast::Receiver;
+
+fn config() -> SimulationConfig {
+    SimulationConfig {
+        switch_delay: Duration::from_millis(300),
+        sensor_latency: Duration::from_millis(50),
+        tick: Duration::from_millis(100),
+        distance_per_speed_step: 1.0,
+    }
+}
+
+/// Advances the simulation and collects all feedback the railroad handled in the meantime.
+async fn advance(
+    connector: &mut SimulationConnector,
+    feedback: &mut Receiver<Message<u8, u16, u16, u16, u16>>,
+    duration: Duration,
+) -> Vec<Message<u8, u16, u16, u16, u16>> {
+    let mut messages = vec![];
+    let mut elapsed = Duration::ZERO;
+    while elapsed < duration {
+        connector.advance(config().tick).await;
+        elapsed += config().tick;
+        while let Ok(message) = feedback.try_recv() {
+            messages.push(message);
+        }
+    }
+    messages
+}
+
+#[tokio::test]
+pub async fn test_train_reaches_sensor() {
+    let (railroad, [first, straight, _curved]) = create_switch_railroad().await;
+    let mut feedback = railroad.subscribe();
+    let mut connector = SimulationConnector::new(railroad.subscribe(), config());
+    connector.register_railroad(railroad.clone()).await;
+    assert_eq!(connector.train_position(&Address::new(1)), Some(first));
+
+    connector
+        .handle_message(Message::TrainSpeed(Address::new(1), Speed::Drive(10)))
+        .await;
+
+    let messages = advance(&mut connector, &mut feedback, Duration::from_millis(500)).await;
+    assert!(!messages.contains(&Message::UpdateSensor(Address::new(2), SLevel::Occupied)));
+
+    let messages = advance(&mut connector, &mut feedback, Duration::from_millis(500)).await;
+    assert_eq!(connector.train_position(&Address::new(1)), Some(straight));
+    assert!(messages.contains(&Message::UpdateSensor(Address::new(2), SLevel::Occupied)));
+    assert!(messages.contains(&Message::UpdateSensor(Address::new(1), SLevel::Free)));
+
+    let state = railroad.state().await;
+    let sensor = state
+        .sensors
+        .iter()
+        .find(|sensor| sensor.address == Address::new(2))
+        .unwrap();
+    assert_eq!(sensor.level, SLevel::Occupied);
+}
+
+#[tokio::test]
+pub async fn test_switch_is_acknowledged() {
+    let (railroad, [_first, _straight, curved]) = create_switch_railroad().await;
+    let mut feedback = railroad.subscribe();
+    let mut connector = SimulationConnector::new(railroad.subscribe(), config());
+    connector.register_railroad(railroad.clone()).await;
+
+    railroad
+        .get_switch_mutex(&Address::new(1))
+        .unwrap()
+        .lock()
+        .await
+        .switch(SwDir::Curved, &railroad)
+        .await;
+    while let Ok(message) = connector.reciever().await.try_recv() {
+        connector.handle_message(message).await;
+    }
+    feedback.try_recv().unwrap();
+
+    advance(&mut connector, &mut feedback, Duration::from_millis(200)).await;
+    assert_eq!(
+        connector.switch_dir(&Address::new(1)),
+        Some(SwDir::Straight)
+    );
+
+    let messages = advance(&mut connector, &mut feedback, Duration::from_millis(200)).await;
+    assert_eq!(connector.switch_dir(&Address::new(1)), Some(SwDir::Curved));
+    assert_eq!(
+        messages,
+        vec![Message::SwitchAck(Address::new(1), SwDir::Curved)]
+    );
+    let switch = railroad.state().await.switches[0].clone();
+    assert_eq!((switch.dir, switch.updated), (SwDir::Curved, true));
+
+    connector
+        .handle_message(Message::TrainSpeed(Address::new(1), Speed::Drive(10)))
+        .await;
+    advance(&mut connector, &mut feedback, Duration::from_secs(1)).await;
+    assert_eq!(connector.train_position(&Address::new(1)), Some(curved));
+}
+
+#[tokio::test]
+pub async fn test_train_reverses() {
+    let (railroad, [first, second], _buffer) = create_terminus_railroad().await;
+    let mut feedback = railroad.subscribe();
+    let mut connector = SimulationConnector::new(railroad.subscribe(), config());
+    connector.register_railroad(railroad.clone()).await;
+
+    // The only way back leads over a reversal.
+    let (_cost, path) = Railroad::shortest_path(railroad.clone(), first.0, first.1)
+        .await
+        .unwrap();
+    assert_eq!(
+        (path.first(), path.last()),
+        (Some(&first.0), Some(&first.1))
+    );
+    let mut reversals = 0;
+    for step in path.windows(2) {
+        if railroad.is_reversal(step[0], step[1]).await {
+            reversals += 1;
+        }
+    }
+    assert_eq!(reversals, 1);
+
+    connector
+        .handle_message(Message::TrainSpeed(Address::new(1), Speed::Drive(10)))
+        .await;
+    advance(&mut connector, &mut feedback, Duration::from_millis(400)).await;
+    connector
+        .handle_message(Message::TrainSpeed(Address::new(1), Speed::Stop))
+        .await;
+    assert_eq!(connector.train_position(&Address::new(1)), Some(second.0));
+
+    connector
+        .handle_message(Message::TrainDirection(
+            Address::new(1),
+            TrainDirection::Backward,
+        ))
+        .await;
+    assert_eq!(connector.train_position(&Address::new(1)), Some(second.1));
+
+    connector
+        .handle_message(Message::TrainSpeed(Address::new(1), Speed::Drive(10)))
+        .await;
+    let messages = advance(&mut connector, &mut feedback, Duration::from_millis(500)).await;
+    assert_eq!(connector.train_position(&Address::new(1)), Some(first.1));
+    assert!(messages.contains(&Message::UpdateSensor(Address::new(1), SLevel::Occupied)));
+    assert!(messages.contains(&Message::UpdateSensor(Address::new(2), SLevel::Free)));
+}
@@ -0,0 +1,273 @@
+use crate::control::messages::Message;
+use crate::control::rail_system::railroad::Railroad;
+use crate::general::{AddressType, DefaultAddressType, DefaultSpeedType, SpeedType};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::marker::PhantomData;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::fs::File;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::sync::broadcast::Receiver;
+use tokio::time::Instant;
+
+use super::RailroadConnector;
+
+type Railroads<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr, CrossingAddr> =
+    Vec<Arc<Railroad<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr, CrossingAddr>>>;
+
+/// One message of a trace and the time it was recorded at.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(bound = "")]
+pub struct TraceEntry<
+    Spd: SpeedType,
+    TrainAddr: AddressType,
+    SensorAddr: AddressType,
+    SwitchAddr: AddressType,
+    SignalAddr: AddressType,
+> {
+    /// The time passed since the recording started
+    pub time: Duration,
+    pub message: Message<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr>,
+}
+
+/// Records every message of the railroad channel as trace.
+///
+/// The trace is written as JSON lines, one [TraceEntry] per line.
+pub struct RecordConnector<
+    W: AsyncWrite + Unpin + Send,
+    Spd: SpeedType = DefaultSpeedType,
+    TrainAddr: AddressType = DefaultAddressType,
+    SensorAddr: AddressType = DefaultAddressType,
+    SwitchAddr: AddressType = DefaultAddressType,
+    SignalAddr: AddressType = DefaultAddressType,
+    CrossingAddr: AddressType = DefaultAddressType,
+> {
+    receiver: Receiver<Message<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr>>,
+    writer: W,
+    start: Instant,
+    crossings: PhantomData<CrossingAddr>,
+}
+
+impl<
+        Spd: SpeedType,
+        TrainAddr: AddressType,
+        SensorAddr: AddressType,
+        SwitchAddr: AddressType,
+        SignalAddr: AddressType,
+        CrossingAddr: AddressType,
+    > RecordConnector<File, Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr, CrossingAddr>
+{
+    /// Records to the file at the given path, replacing an existing file.
+    pub async fn create<P: AsRef<Path>>(
+        path: P,
+        receiver: Receiver<Message<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr>>,
+    ) -> io::Result<Self> {
+        Ok(RecordConnector::new(File::create(path).await?, receiver))
+    }
+}
+
+impl<
+        W: AsyncWrite + Unpin + Send,
+        Spd: SpeedType,
+        TrainAddr: AddressType,
+        SensorAddr: AddressType,
+        SwitchAddr: AddressType,
+        SignalAddr: AddressType,
+        CrossingAddr: AddressType,
+    > RecordConnector<W, Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr, CrossingAddr>
+{
+    /// Records to the given writer. The recording time starts now.
+    pub fn new(
+        writer: W,
+        receiver: Receiver<Message<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr>>,
+    ) -> Self {
+        RecordConnector {
+            receiver,
+            writer,
+            start: Instant::now(),
+            crossings: PhantomData,
+        }
+    }
+
+    /// Stops the recording and returns the writer.
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    async fn record(
+        &mut self,
+        message: Message<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr>,
+    ) -> io::Result<()> {
+        let entry = TraceEntry {
+            time: self.start.elapsed(),
+            message,
+        };
+        let mut line = serde_json::to_vec(&entry)?;
+        line.push(b'\n');
+        self.writer.write_all(&line).await?;
+        self.writer.flush().await
+    }
+}
+
+#[async_trait]
+impl<
+        W: AsyncWrite + Unpin + Send,
+        Spd: SpeedType,
+        TrainAddr: AddressType,
+        SensorAddr: AddressType,
+        SwitchAddr: AddressType,
+        SignalAddr: AddressType,
+        CrossingAddr: AddressType,
+    > RailroadConnector<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr, CrossingAddr>
+    for RecordConnector<W, Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr, CrossingAddr>
+{
+    async fn handle_message(
+        &mut self,
+        message: Message<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr>,
+    ) {
+        let _ = self.record(message).await;
+    }
+
+    async fn reciever(
+        &mut self,
+    ) -> &mut Receiver<Message<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr>> {
+        &mut self.receiver
+    }
+
+    /// The recording does only need the messages of the channel.
+    async fn register_railroad(
+        &mut self,
+        _railroad: Arc<Railroad<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr, CrossingAddr>>,
+    ) {
+    }
+}
+
+/// Replays the feedback of a recorded trace with its original timing.
+///
+/// Only feedback messages, as checked by [Message::is_feedback], are replayed. They are handled
+/// by [Railroad::handle_feedback] of every registered railroad, so the rail system reacts as it
+/// did while recording. All other messages are sent by the rail system itself while replaying.
+pub struct ReplayConnector<
+    Spd: SpeedType = DefaultSpeedType,
+    TrainAddr: AddressType = DefaultAddressType,
+    SensorAddr: AddressType = DefaultAddressType,
+    SwitchAddr: AddressType = DefaultAddressType,
+    SignalAddr: AddressType = DefaultAddressType,
+    CrossingAddr: AddressType = DefaultAddressType,
+> {
+    receiver: Receiver<Message<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr>>,
+    trace: Vec<TraceEntry<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr>>,
+    acceleration: f64,
+    railroads: Railroads<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr, CrossingAddr>,
+}
+
+impl<
+        Spd: SpeedType,
+        TrainAddr: AddressType,
+        SensorAddr: AddressType,
+        SwitchAddr: AddressType,
+        SignalAddr: AddressType,
+        CrossingAddr: AddressType,
+    > ReplayConnector<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr, CrossingAddr>
+{
+    pub fn new(
+        trace: Vec<TraceEntry<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr>>,
+        receiver: Receiver<Message<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr>>,
+    ) -> Self {
+        ReplayConnector {
+            receiver,
+            trace,
+            acceleration: 1.0,
+            railroads: vec![],
+        }
+    }
+
+    /// Reads the trace to replay from the file at the given path.
+    pub async fn open<P: AsRef<Path>>(
+        path: P,
+        receiver: Receiver<Message<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr>>,
+    ) -> io::Result<Self> {
+        let trace = Self::read_trace(BufReader::new(File::open(path).await?)).await?;
+        Ok(ReplayConnector::new(trace, receiver))
+    }
+
+    /// Reads a trace written by a [RecordConnector].
+    pub async fn read_trace<R: AsyncBufRead + Unpin>(
+        reader: R,
+    ) -> io::Result<Vec<TraceEntry<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr>>> {
+        let mut lines = reader.lines();
+        let mut trace = vec![];
+        while let Some(line) = lines.next_line().await? {
+            if !line.trim().is_empty() {
+                trace.push(serde_json::from_str(&line)?);
+            }
+        }
+        Ok(trace)
+    }
+
+    /// Replays the trace `acceleration` times faster than it was recorded.
+    /// An infinite acceleration replays without any delay.
+    pub fn accelerate(mut self, acceleration: f64) -> Self {
+        self.acceleration = acceleration;
+        self
+    }
+
+    /// Replays the trace to all registered railroads.
+    pub async fn replay(&self) {
+        let start = Instant::now();
+        for entry in self
+            .trace
+            .iter()
+            .filter(|entry| entry.message.is_feedback())
+        {
+            let due = entry.time.as_secs_f64() / self.acceleration;
+            if due.is_finite() && due > 0.0 {
+                tokio::time::sleep_until(start + Duration::from_secs_f64(due)).await;
+            }
+
+            for railroad in &self.railroads {
+                Railroad::handle_feedback(railroad.clone(), entry.message).await;
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl<
+        Spd: SpeedType,
+        TrainAddr: AddressType,
+        SensorAddr: AddressType,
+        SwitchAddr: AddressType,
+        SignalAddr: AddressType,
+        CrossingAddr: AddressType,
+    > RailroadConnector<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr, CrossingAddr>
+    for ReplayConnector<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr, CrossingAddr>
+{
+    /// The replayed trace does not react to the rail system.
+    async fn handle_message(
+        &mut self,
+        _message: Message<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr>,
+    ) {
+    }
+
+    async fn reciever(
+        &mut self,
+    ) -> &mut Receiver<Message<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr>> {
+        &mut self.receiver
+    }
+
+    /// Replays the trace once by calling [ReplayConnector::replay].
+    async fn start_connectors(&mut self) {
+        self.replay().await;
+    }
+
+    async fn register_railroad(
+        &mut self,
+        railroad: Arc<Railroad<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr, CrossingAddr>>,
+    ) {
+        self.railroads.push(railroad);
+    }
+}
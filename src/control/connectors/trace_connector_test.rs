@@ -0,0 +1,76 @@
+use crate::control::connectors::trace_connector::{RecordConnector, ReplayConnector};
+use crate::control::connectors::RailroadConnector;
+use crate::control::messages::Message;
+use crate::control::rail_system::components::{Address, SLevel, Speed, SwDir};
+use crate::control::rail_system::railroad_test::create_test_railroad;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::Instant;
+
+#[tokio::test(start_paused = true)]
+pub async fn test_record_and_replay() {
+    let (r, ..) = create_test_railroad().await;
+    let start = Instant::now();
+    let mut recorder: RecordConnector<Vec<u8>> = RecordConnector::new(vec![], r.subscribe());
+
+    let recorded = [
+        (Duration::ZERO, Message::RailOn),
+        (Duration::from_millis(100), Message::RailOnAck),
+        (
+            Duration::from_secs(1),
+            Message::TrainSpeed(Address::new(3), Speed::Drive(20)),
+        ),
+        (
+            Duration::from_secs(2),
+            Message::UpdateSensor(Address::new(8), SLevel::Occupied),
+        ),
+        (
+            Duration::from_secs(4),
+            Message::SwitchAck(Address::new(1), SwDir::Curved),
+        ),
+    ];
+    for (time, message) in recorded {
+        tokio::time::sleep_until(start + time).await;
+        recorder.handle_message(message).await;
+    }
+
+    let trace = recorder.into_inner();
+    let trace = ReplayConnector::<u8, u16, u16, u16, u16, u16>::read_trace(&trace[..])
+        .await
+        .unwrap();
+    assert_eq!(trace.len(), recorded.len());
+    for (entry, (time, message)) in trace.iter().zip(recorded) {
+        assert_eq!(entry.time, time);
+        assert_eq!(entry.message, message);
+    }
+
+    let (r, ..) = create_test_railroad().await;
+    let r = Arc::new(r);
+    let mut feedback = r.subscribe();
+    let mut replay = ReplayConnector::new(trace, r.subscribe()).accelerate(2.0);
+    replay.register_railroad(r.clone()).await;
+
+    let start = Instant::now();
+    replay.start_connectors().await;
+    assert_eq!(start.elapsed(), Duration::from_secs(2));
+
+    // Only the feedback is replayed and handled by the railroad.
+    assert_eq!(feedback.try_recv().unwrap(), Message::RailOnAck);
+    assert_eq!(
+        feedback.try_recv().unwrap(),
+        Message::UpdateSensor(Address::new(8), SLevel::Occupied)
+    );
+    assert_eq!(
+        feedback.try_recv().unwrap(),
+        Message::SwitchAck(Address::new(1), SwDir::Curved)
+    );
+    let sensor = r.get_sensor_mutex(&Address::new(8)).unwrap();
+    assert_eq!(sensor.lock().await.state().level, SLevel::Occupied);
+}
+
+#[tokio::test]
+pub async fn test_malformed_trace() {
+    let trace = b"{\"time\":{\"secs\":0,\"nanos\":0},\"message\":\"RailOn\"}\nno trace\n";
+    let result = ReplayConnector::<u8, u16, u16, u16, u16, u16>::read_trace(&trace[..]).await;
+    assert_eq!(result.unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+}
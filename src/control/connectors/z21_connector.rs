@@ -0,0 +1,338 @@
+use crate::control::rail_system::components::{Address, SLevel, Speed, SwDir, TrainDirection};
+use crate::control::rail_system::railroad::Railroad;
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::{ToSocketAddrs, UdpSocket};
+use tokio::sync::broadcast::Receiver;
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+
+use super::RailroadConnector;
+
+type RailroadContainer = Arc<Mutex<Vec<Arc<Railroad<u8, u16, u16, u16, u16, u16>>>>>;
+type SendMessage = crate::control::messages::Message<u8, u16, u16, u16, u16>;
+
+/// The UDP port a Z21 listens on.
+pub const Z21_PORT: u16 = 21105;
+/// The interval the connection is kept alive in, as the Z21 drops clients after 60 seconds
+/// of silence.
+pub const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// The time a turnout output is powered.
+const TURNOUT_PULSE: Duration = Duration::from_millis(150);
+
+/// Subscribes to driving and switching, R-Bus feedback and LocoNet detector broadcasts.
+const BROADCAST_FLAGS: u32 = 0x0000_0001 | 0x0000_0002 | 0x0800_0000;
+
+const LAN_GET_SERIAL_NUMBER: u16 = 0x10;
+const LAN_LOGOFF: u16 = 0x30;
+const LAN_X: u16 = 0x40;
+const LAN_SET_BROADCASTFLAGS: u16 = 0x50;
+const LAN_RMBUS_DATACHANGED: u16 = 0x80;
+const LAN_LOCONET_DETECTOR: u16 = 0xA4;
+
+const X_TRACK_POWER: u8 = 0x61;
+const X_TURNOUT_INFO: u8 = 0x43;
+/// The only LocoNet detector type reporting plain occupancy.
+const DETECTOR_OCCUPANCY: u8 = 0x01;
+/// The feedback modules per R-Bus group.
+const RMBUS_MODULES: u16 = 10;
+
+/// Connects to a Roco/Fleischmann Z21 over its UDP LAN protocol.
+///
+/// On connecting the connector subscribes to driving, switching and feedback broadcasts and
+/// keeps the connection alive until it is dropped.
+///
+/// Turnouts are switched with output 1 for [SwDir::Straight] and output 2 for [SwDir::Curved].
+/// R-Bus inputs are addressed from 0 on, counting eight inputs per feedback module.
+/// Speed and direction of a locomotive are set together, so the last of both is kept.
+pub struct Z21Connector {
+    receiver: Receiver<SendMessage>,
+    socket: Arc<UdpSocket>,
+    railroads: RailroadContainer,
+    tasks: Vec<JoinHandle<()>>,
+    throttles: HashMap<Address, (Speed<u8>, TrainDirection)>,
+}
+
+impl Z21Connector {
+    /// Connects to the Z21 at the given address, usually `192.168.0.111:21105`.
+    pub async fn connect<A: ToSocketAddrs>(
+        address: A,
+        receiver: Receiver<SendMessage>,
+    ) -> io::Result<Self> {
+        Z21Connector::connect_with_keepalive(address, receiver, KEEPALIVE_INTERVAL).await
+    }
+
+    /// Connects to the Z21 at the given address and keeps the connection alive
+    /// in the given interval.
+    pub async fn connect_with_keepalive<A: ToSocketAddrs>(
+        address: A,
+        receiver: Receiver<SendMessage>,
+        keepalive: Duration,
+    ) -> io::Result<Self> {
+        let socket = UdpSocket::bind("0.0.0.0:0").await?;
+        socket.connect(address).await?;
+        let socket = Arc::new(socket);
+
+        socket
+            .send(&packet(
+                LAN_SET_BROADCASTFLAGS,
+                &BROADCAST_FLAGS.to_le_bytes(),
+            ))
+            .await?;
+
+        let railroads: RailroadContainer = Arc::new(Mutex::new(vec![]));
+        let tasks = vec![
+            tokio::spawn(Z21Connector::forward_feedback(
+                socket.clone(),
+                railroads.clone(),
+            )),
+            tokio::spawn(Z21Connector::keep_alive(socket.clone(), keepalive)),
+        ];
+
+        Ok(Z21Connector {
+            receiver,
+            socket,
+            railroads,
+            tasks,
+            throttles: HashMap::new(),
+        })
+    }
+
+    async fn keep_alive(socket: Arc<UdpSocket>, keepalive: Duration) {
+        let mut interval = tokio::time::interval(keepalive);
+        // The first tick completes immediately, right after subscribing.
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            let _ = socket.send(&packet(LAN_GET_SERIAL_NUMBER, &[])).await;
+        }
+    }
+
+    /// Handles all feedback received from the Z21 by every registered railroad.
+    async fn forward_feedback(socket: Arc<UdpSocket>, railroads: RailroadContainer) {
+        let mut buffer = [0u8; 1500];
+        let mut rmbus = HashMap::new();
+        loop {
+            let received = match socket.recv(&mut buffer).await {
+                Ok(received) => received,
+                Err(_err) => continue,
+            };
+
+            let mut datagram = &buffer[..received];
+            // One datagram may hold multiple packets.
+            while datagram.len() >= 4 {
+                let length = u16::from_le_bytes([datagram[0], datagram[1]]) as usize;
+                if length < 4 || length > datagram.len() {
+                    break;
+                }
+                let header = u16::from_le_bytes([datagram[2], datagram[3]]);
+
+                for feedback in Z21Connector::feedback(header, &datagram[4..length], &mut rmbus) {
+                    for railroad in railroads.lock().await.iter() {
+                        Railroad::handle_feedback(railroad.clone(), feedback).await;
+                    }
+                }
+
+                datagram = &datagram[length..];
+            }
+        }
+    }
+
+    /// Translates a packet of the Z21 into feedback messages of the rail system.
+    /// `rmbus` holds the last known state of every R-Bus group, so only changed inputs are
+    /// reported. Inputs of groups, that were not reported yet, are expected to be free.
+    fn feedback(header: u16, data: &[u8], rmbus: &mut HashMap<u8, Vec<u8>>) -> Vec<SendMessage> {
+        match header {
+            LAN_X => match data {
+                [X_TRACK_POWER, 0x00, ..] => vec![SendMessage::RailOffAck],
+                [X_TRACK_POWER, 0x01, ..] => vec![SendMessage::RailOnAck],
+                [X_TURNOUT_INFO, msb, lsb, state, ..] => {
+                    let address = Address::new(u16::from_be_bytes([*msb, *lsb]));
+                    match state & 0x03 {
+                        0x01 => vec![SendMessage::SwitchAck(address, SwDir::Straight)],
+                        0x02 => vec![SendMessage::SwitchAck(address, SwDir::Curved)],
+                        _ => vec![],
+                    }
+                }
+                _ => vec![],
+            },
+            LAN_RMBUS_DATACHANGED if !data.is_empty() => {
+                let group = data[0];
+                let modules = &data[1..];
+                let old = rmbus.insert(group, modules.to_vec());
+
+                let mut feedback = vec![];
+                for (module, status) in modules.iter().enumerate() {
+                    let old_status = old.as_ref().and_then(|old| old.get(module));
+                    let changed = status ^ old_status.unwrap_or(&0);
+                    for input in 0..8u16 {
+                        let bit = 1 << input;
+                        if changed & bit != 0 {
+                            let address =
+                                (group as u16 * RMBUS_MODULES + module as u16) * 8 + input;
+                            feedback.push(SendMessage::UpdateSensor(
+                                Address::new(address),
+                                if status & bit != 0 {
+                                    SLevel::Occupied
+                                } else {
+                                    SLevel::Free
+                                },
+                            ));
+                        }
+                    }
+                }
+                feedback
+            }
+            LAN_LOCONET_DETECTOR => match data {
+                [DETECTOR_OCCUPANCY, lsb, msb, info, ..] => vec![SendMessage::UpdateSensor(
+                    Address::new(u16::from_le_bytes([*lsb, *msb])),
+                    if info & 0x01 != 0 {
+                        SLevel::Occupied
+                    } else {
+                        SLevel::Free
+                    },
+                )],
+                _ => vec![],
+            },
+            _ => vec![],
+        }
+    }
+
+    /// Translates a message of the rail system into a packet for the Z21.
+    fn command(&mut self, message: SendMessage) -> Option<Vec<u8>> {
+        match message {
+            SendMessage::RailOn => Some(x_packet(&[0x21, 0x81])),
+            SendMessage::RailOff => Some(x_packet(&[0x21, 0x80])),
+            SendMessage::TrainSpeed(adr, speed) => {
+                let throttle = self
+                    .throttles
+                    .entry(adr)
+                    .or_insert((speed, TrainDirection::Forward));
+                throttle.0 = speed;
+                Some(drive_packet(adr, *throttle))
+            }
+            SendMessage::TrainDirection(adr, direction) => {
+                let throttle = self
+                    .throttles
+                    .entry(adr)
+                    .or_insert((Speed::Stop, direction));
+                throttle.1 = direction;
+                Some(drive_packet(adr, *throttle))
+            }
+            // The two highest bits switch the function on or off.
+            SendMessage::TrainFunction(adr, function, on) => {
+                let [msb, lsb] = loco_address(adr);
+                let switch = if on { 0x40 } else { 0x00 };
+                Some(x_packet(&[
+                    0xE4,
+                    0xF8,
+                    msb,
+                    lsb,
+                    switch | (function & 0x3F),
+                ]))
+            }
+            SendMessage::TrainRemoved(adr) => {
+                self.throttles.remove(&adr);
+                None
+            }
+            SendMessage::Switch(adr, dir) => Some(turnout_packet(adr, dir, true)),
+            // Signals are extended accessory decoders showing the aspect number.
+            SendMessage::UpdateSignal(adr, aspect) => {
+                let [msb, lsb] = adr.address().to_be_bytes();
+                Some(x_packet(&[0x54, msb, lsb, aspect.dcc_aspect(), 0x00]))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl Drop for Z21Connector {
+    fn drop(&mut self) {
+        self.tasks.iter().for_each(JoinHandle::abort);
+        let _ = self.socket.try_send(&packet(LAN_LOGOFF, &[]));
+    }
+}
+
+/// Returns the bytes addressing a locomotive. Long addresses are marked by the highest bits.
+fn loco_address(adr: Address<u16>) -> [u8; 2] {
+    let [mut msb, lsb] = adr.address().to_be_bytes();
+    if adr.address() >= 128 {
+        msb |= 0xC0;
+    }
+    [msb, lsb]
+}
+
+/// Creates a command driving a locomotive with 128 speed steps.
+fn drive_packet(adr: Address<u16>, (speed, direction): (Speed<u8>, TrainDirection)) -> Vec<u8> {
+    let [msb, lsb] = loco_address(adr);
+    // 0 stops and 1 stops immediately, the highest bit drives forward.
+    let speed = match speed {
+        Speed::Stop | Speed::Drive(0) => 0,
+        Speed::EmergencyStop => 1,
+        Speed::Drive(spd) => spd.min(126) + 1,
+    };
+    let direction = match direction {
+        TrainDirection::Forward => 0x80,
+        TrainDirection::Backward => 0x00,
+    };
+    x_packet(&[0xE4, 0x13, msb, lsb, direction | speed])
+}
+
+/// Creates a queued turnout command activating or deactivating the output for `dir`.
+fn turnout_packet(adr: Address<u16>, dir: SwDir, activate: bool) -> Vec<u8> {
+    let [msb, lsb] = adr.address().to_be_bytes();
+    let output = match dir {
+        SwDir::Straight => 0x00,
+        SwDir::Curved => 0x01,
+    };
+    let activate = if activate { 0x08 } else { 0x00 };
+    x_packet(&[0x53, msb, lsb, 0xA0 | activate | output])
+}
+
+/// Creates a packet with the given header and data.
+fn packet(header: u16, data: &[u8]) -> Vec<u8> {
+    let length = (data.len() + 4) as u16;
+    let mut packet = Vec::with_capacity(length as usize);
+    packet.extend_from_slice(&length.to_le_bytes());
+    packet.extend_from_slice(&header.to_le_bytes());
+    packet.extend_from_slice(data);
+    packet
+}
+
+/// Creates an X-Bus packet with the given data, followed by its checksum.
+fn x_packet(data: &[u8]) -> Vec<u8> {
+    let checksum = data.iter().fold(0, |checksum, byte| checksum ^ byte);
+    let mut data = data.to_vec();
+    data.push(checksum);
+    packet(LAN_X, &data)
+}
+
+#[async_trait]
+impl RailroadConnector<u8, u16, u16, u16, u16, u16> for Z21Connector {
+    async fn handle_message(&mut self, message: SendMessage) {
+        if let Some(packet) = self.command(message) {
+            let _ = self.socket.send(&packet).await;
+        }
+
+        // Turnout outputs have to be switched off again, after the turnout moved.
+        if let SendMessage::Switch(adr, dir) = message {
+            let socket = self.socket.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(TURNOUT_PULSE).await;
+                let _ = socket.send(&turnout_packet(adr, dir, false)).await;
+            });
+        }
+    }
+
+    async fn reciever(&mut self) -> &mut Receiver<SendMessage> {
+        &mut self.receiver
+    }
+
+    async fn register_railroad(&mut self, railroad: Arc<Railroad<u8, u16, u16, u16, u16, u16>>) {
+        self.railroads.lock().await.push(railroad.clone());
+    }
+}
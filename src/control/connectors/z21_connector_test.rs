@@ -0,0 +1,169 @@
+use crate::control::connectors::connector_test::next_feedback;
+use crate::control::connectors::z21_connector::Z21Connector;
+use crate::control::connectors::RailroadConnector;
+use crate::control::messages::Message;
+use crate::control::rail_system::components::{
+    Address, Aspect, SLevel, Speed, SwDir, TrainDirection,
+};
+use crate::control::rail_system::railroad_test::create_test_railroad;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio::sync::broadcast::Receiver;
+use tokio::time::timeout;
+
+async fn receive(station: &UdpSocket) -> (Vec<u8>, SocketAddr) {
+    let mut buffer = [0u8; 64];
+    let (read, client) = timeout(Duration::from_secs(1), station.recv_from(&mut buffer))
+        .await
+        .unwrap()
+        .unwrap();
+    (buffer[..read].to_vec(), client)
+}
+
+/// Binds a Z21 stand-in and connects to it, checking the broadcast subscription.
+async fn connect(
+    receiver: Receiver<Message<u8, u16, u16, u16, u16>>,
+    keepalive: Duration,
+) -> (Z21Connector, UdpSocket, SocketAddr) {
+    let station = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let connector =
+        Z21Connector::connect_with_keepalive(station.local_addr().unwrap(), receiver, keepalive)
+            .await
+            .unwrap();
+
+    let (subscription, client) = receive(&station).await;
+    assert_eq!(
+        subscription,
+        vec![0x08, 0x00, 0x50, 0x00, 0x03, 0x00, 0x00, 0x08]
+    );
+
+    (connector, station, client)
+}
+
+#[tokio::test]
+pub async fn test_z21_commands() {
+    let (r, ..) = create_test_railroad().await;
+    let (mut connector, station, _client) =
+        connect(r.subscribe(), Duration::from_millis(200)).await;
+
+    connector.handle_message(Message::RailOn).await;
+    assert_eq!(
+        receive(&station).await.0,
+        vec![0x07, 0x00, 0x40, 0x00, 0x21, 0x81, 0xA0]
+    );
+
+    connector
+        .handle_message(Message::TrainSpeed(Address::new(3), Speed::Drive(20)))
+        .await;
+    assert_eq!(
+        receive(&station).await.0,
+        vec![0x0A, 0x00, 0x40, 0x00, 0xE4, 0x13, 0x00, 0x03, 0x95, 0x61]
+    );
+
+    connector
+        .handle_message(Message::TrainDirection(
+            Address::new(3),
+            TrainDirection::Backward,
+        ))
+        .await;
+    assert_eq!(
+        receive(&station).await.0,
+        vec![0x0A, 0x00, 0x40, 0x00, 0xE4, 0x13, 0x00, 0x03, 0x15, 0xE1]
+    );
+
+    // Speed tables could map low speeds to speed step 0, which stops without an emergency stop.
+    connector
+        .handle_message(Message::TrainSpeed(Address::new(3), Speed::Drive(0)))
+        .await;
+    assert_eq!(
+        receive(&station).await.0,
+        vec![0x0A, 0x00, 0x40, 0x00, 0xE4, 0x13, 0x00, 0x03, 0x00, 0xF4]
+    );
+
+    connector
+        .handle_message(Message::TrainFunction(Address::new(3), 2, true))
+        .await;
+    assert_eq!(
+        receive(&station).await.0,
+        vec![0x0A, 0x00, 0x40, 0x00, 0xE4, 0xF8, 0x00, 0x03, 0x42, 0x5D]
+    );
+
+    connector
+        .handle_message(Message::Switch(Address::new(5), SwDir::Curved))
+        .await;
+    assert_eq!(
+        receive(&station).await.0,
+        vec![0x09, 0x00, 0x40, 0x00, 0x53, 0x00, 0x05, 0xA9, 0xFF]
+    );
+    assert_eq!(
+        receive(&station).await.0,
+        vec![0x09, 0x00, 0x40, 0x00, 0x53, 0x00, 0x05, 0xA1, 0xF7]
+    );
+
+    connector
+        .handle_message(Message::UpdateSignal(Address::new(9), Aspect::ExpectStop))
+        .await;
+    assert_eq!(
+        receive(&station).await.0,
+        vec![0x0A, 0x00, 0x40, 0x00, 0x54, 0x00, 0x09, 0x02, 0x00, 0x5F]
+    );
+
+    // The connection is kept alive.
+    assert_eq!(receive(&station).await.0, vec![0x04, 0x00, 0x10, 0x00]);
+}
+
+#[tokio::test]
+pub async fn test_z21_feedback() {
+    let (r, ..) = create_test_railroad().await;
+    let r = Arc::new(r);
+    let mut feedback = r.subscribe();
+    let (mut connector, station, client) = connect(r.subscribe(), Duration::from_secs(30)).await;
+    connector.register_railroad(r.clone()).await;
+
+    // Track power on, followed by a LocoNet detector report in the same datagram.
+    let datagram = [
+        0x07, 0x00, 0x40, 0x00, 0x61, 0x01, 0x60, 0x08, 0x00, 0xA4, 0x00, 0x01, 0x13, 0x00, 0x01,
+    ];
+    station.send_to(&datagram, client).await.unwrap();
+    assert_eq!(next_feedback(&mut feedback).await, Message::RailOnAck);
+    assert_eq!(
+        next_feedback(&mut feedback).await,
+        Message::UpdateSensor(Address::new(19), SLevel::Occupied)
+    );
+
+    // The first R-Bus report of a group announces all occupied inputs.
+    let mut rmbus = vec![0x0F, 0x00, 0x80, 0x00, 0x00, 0x02];
+    rmbus.extend_from_slice(&[0; 9]);
+    station.send_to(&rmbus, client).await.unwrap();
+    assert_eq!(
+        next_feedback(&mut feedback).await,
+        Message::UpdateSensor(Address::new(1), SLevel::Occupied)
+    );
+
+    // Later reports only announce the changed inputs.
+    rmbus[5] = 0x00;
+    rmbus[6] = 0x01;
+    station.send_to(&rmbus, client).await.unwrap();
+    assert_eq!(
+        next_feedback(&mut feedback).await,
+        Message::UpdateSensor(Address::new(1), SLevel::Free)
+    );
+    assert_eq!(
+        next_feedback(&mut feedback).await,
+        Message::UpdateSensor(Address::new(8), SLevel::Occupied)
+    );
+
+    station
+        .send_to(
+            &[0x09, 0x00, 0x40, 0x00, 0x43, 0x00, 0x01, 0x02, 0x40],
+            client,
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        next_feedback(&mut feedback).await,
+        Message::SwitchAck(Address::new(1), SwDir::Curved)
+    );
+}
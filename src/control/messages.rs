@@ -1,7 +1,22 @@
-use crate::control::rail_system::components::{Address, SLevel, Speed, SwDir};
+use crate::control::clock::ModelTime;
+use crate::control::rail_system::components::{
+    Address, Aspect, SLevel, Speed, SwDir, TrainDirection,
+};
 use crate::general::{AddressType, SpeedType};
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+/// The health of the connection between a connector and its command station.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub enum ConnectionState {
+    Connected,
+    /// The command station is reachable, but does not answer in time
+    Degraded,
+    /// The command station is not reachable. The connector tries to reconnect.
+    Disconnected,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+#[serde(bound = "")]
 pub enum Message<
     Spd: SpeedType,
     TrainAddr: AddressType,
@@ -11,11 +26,54 @@ pub enum Message<
 > {
     RailOn,
     RailOff,
+    RailOnAck,
+    RailOffAck,
     TrainSpeed(Address<TrainAddr>, Speed<Spd>),
+    TrainDirection(Address<TrainAddr>, TrainDirection),
+    /// Switches a function of the train's decoder on or off, numbered from `F0`.
+    TrainFunction(Address<TrainAddr>, u8, bool),
     Switch(Address<SwitchAddr>, SwDir),
     SwitchAck(Address<SwitchAddr>, SwDir),
     UpdateSensor(Address<SensorAddr>, SLevel),
-    UpdateSignal(Address<SignalAddr>, SwDir),
+    /// The signal changed its aspect, so connectors could show it on the physical signal.
+    UpdateSignal(Address<SignalAddr>, Aspect<Spd>),
     TrainGranted(Address<SignalAddr>, Address<TrainAddr>),
     TrainOnSensor(Address<SensorAddr>, Address<TrainAddr>),
+    /// The train waits for the block behind the signal in a deadlock,
+    /// which could not be resolved by rerouting one of its trains.
+    TrainDeadlocked(Address<TrainAddr>, Address<SignalAddr>),
+    ConnectionState(ConnectionState),
+    /// The train was removed from the railroad and is no longer controlled by it.
+    TrainRemoved(Address<TrainAddr>),
+    /// Another throttle took over the control of the train.
+    TrainTakenOver(Address<TrainAddr>),
+    /// The model time of the fast clock and the ratio it runs with, `0` while it is paused.
+    /// Sent every model minute and whenever the clock is set.
+    ClockTick(ModelTime, u8),
+    /// The fast clock of the command station was set to the model time and ratio.
+    ClockSync(ModelTime, u8),
+}
+
+impl<
+        Spd: SpeedType,
+        TrainAddr: AddressType,
+        SensorAddr: AddressType,
+        SwitchAddr: AddressType,
+        SignalAddr: AddressType,
+    > Message<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr>
+{
+    /// Checks if this message is reported by the physical railroad,
+    /// instead of being sent by the rail system.
+    pub fn is_feedback(&self) -> bool {
+        matches!(
+            self,
+            Message::RailOnAck
+                | Message::RailOffAck
+                | Message::SwitchAck(..)
+                | Message::UpdateSensor(..)
+                | Message::ConnectionState(..)
+                | Message::TrainTakenOver(..)
+                | Message::ClockSync(..)
+        )
+    }
 }
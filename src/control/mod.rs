@@ -1,8 +1,26 @@
+/// Drives the trains of a railroad to random stations.
+pub mod auto_mode;
+/// Tests driving trains in auto mode
+#[cfg(test)]
+mod auto_mode_test;
+/// The fast clock running the model time.
+pub mod clock;
+/// Tests running the fast clock
+#[cfg(test)]
+mod clock_test;
 /// All handlers controlling the actual Railroad connection.
 pub mod connectors;
 /// The messages that can be send to and received from the rail system.
 pub mod messages;
+/// Acceleration and braking profiles of trains.
+pub mod momentum;
+/// Tests ramping the speed of trains
+#[cfg(test)]
+mod momentum_test;
 /// The rail system including it's handlers.
 pub mod rail_system;
 /// Train handling and controlling.
 pub mod train;
+/// Tests driving trains along their routes
+#[cfg(test)]
+mod train_test;
@@ -0,0 +1,200 @@
+use crate::control::rail_system::components::Speed;
+use crate::general::SpeedType;
+use num_traits::NumCast;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// The interval the speed of a train is updated in, while it accelerates or brakes.
+pub const DEFAULT_TICK: Duration = Duration::from_millis(10);
+
+/// The shape of a speed ramp.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Curve {
+    /// Changes the speed by the same amount every tick.
+    #[default]
+    Linear,
+    /// Starts and ends the ramp gently and changes the speed fastest in its middle.
+    SCurve,
+}
+
+impl Curve {
+    /// Maps the elapsed part of a ramp to the part of the speed difference reached,
+    /// both from `0.0` to `1.0`.
+    pub fn shape(&self, progress: f64) -> f64 {
+        let progress = progress.clamp(0.0, 1.0);
+        match self {
+            Curve::Linear => progress,
+            Curve::SCurve => progress * progress * (3.0 - 2.0 * progress),
+        }
+    }
+}
+
+/// Describes how fast a train changes its speed.
+///
+/// The rates are measured in speed steps per second. A ramp takes as long as a linear ramp
+/// with the same rate would take, the [Curve] only changes how the speed is distributed over it.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct MomentumProfile {
+    /// The interval the speed is updated in
+    pub tick: Duration,
+    /// The speed steps gained per second, while accelerating
+    pub acceleration: u32,
+    /// The speed steps lost per second, while braking
+    pub deceleration: u32,
+    pub curve: Curve,
+}
+
+impl Default for MomentumProfile {
+    /// Changes the speed by 5 steps every [DEFAULT_TICK] in both directions.
+    fn default() -> Self {
+        MomentumProfile::linear(500)
+    }
+}
+
+impl MomentumProfile {
+    /// A linear ramp accelerating and braking with the same rate.
+    pub fn linear(rate: u32) -> Self {
+        MomentumProfile {
+            tick: DEFAULT_TICK,
+            acceleration: rate,
+            deceleration: rate,
+            curve: Curve::Linear,
+        }
+    }
+
+    /// Sets separate rates for accelerating and braking.
+    pub fn rates(mut self, acceleration: u32, deceleration: u32) -> Self {
+        self.acceleration = acceleration;
+        self.deceleration = deceleration;
+        self
+    }
+
+    pub fn curve(mut self, curve: Curve) -> Self {
+        self.curve = curve;
+        self
+    }
+
+    pub fn tick(mut self, tick: Duration) -> Self {
+        self.tick = tick;
+        self
+    }
+
+    /// Calculates the speed of every tick to change the speed from `from` to `to`.
+    /// The last speed is always `to`. Emergency stops are not ramped.
+    pub fn ramp<Spd: SpeedType>(&self, from: Speed<Spd>, to: Speed<Spd>) -> Vec<Speed<Spd>> {
+        let (start, end) = (speed_value(from), speed_value(to));
+        let rate = if end > start {
+            self.acceleration
+        } else {
+            self.deceleration
+        };
+        if to == Speed::EmergencyStop || start == end || rate == 0 {
+            return vec![to];
+        }
+
+        let duration = (end - start).abs() / rate as f64;
+        let ticks = (duration / self.tick.as_secs_f64()).ceil().max(1.0) as usize;
+
+        let mut ramp: Vec<Speed<Spd>> = (1..ticks)
+            .map(|tick| {
+                let progress = self.curve.shape(tick as f64 / ticks as f64);
+                round_speed(start + (end - start) * progress)
+            })
+            .collect();
+        ramp.push(to);
+        ramp
+    }
+
+    /// Calculates the distance a train needs to stop from `speed`.
+    /// The speeds are converted to distances with `distance_per_speed_step`,
+    /// the distance driven per second and speed step.
+    pub fn braking_distance<Spd: SpeedType>(
+        &self,
+        speed: Speed<Spd>,
+        distance_per_speed_step: f64,
+    ) -> f64 {
+        // Every speed is driven for one tick, before it is changed to the next one.
+        let ramp = self.ramp(speed, Speed::Stop);
+        let steps: f64 = std::iter::once(speed)
+            .chain(ramp)
+            .take_while(|speed| *speed != Speed::Stop)
+            .map(speed_value)
+            .sum();
+        steps * self.tick.as_secs_f64() * distance_per_speed_step
+    }
+}
+
+/// Maps the logical speeds of a train to the speed steps of its decoder,
+/// so trains with different motors drive alike at the same logical speed.
+///
+/// Speeds between two entries are interpolated linearly, speeds above the last entry
+/// are mapped to its decoder step.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(bound = "")]
+pub struct SpeedTable<Spd: SpeedType> {
+    /// Pairs of a logical speed and its decoder step, sorted by the logical speed
+    steps: Vec<(Spd, Spd)>,
+}
+
+impl<Spd: SpeedType> SpeedTable<Spd> {
+    pub fn new(mut steps: Vec<(Spd, Spd)>) -> Self {
+        steps.sort();
+        steps.dedup_by_key(|(speed, _)| *speed);
+        SpeedTable { steps }
+    }
+
+    pub fn steps(&self) -> &[(Spd, Spd)] {
+        &self.steps
+    }
+
+    /// Returns the speed to send to the decoder for a logical speed.
+    pub fn decoder_speed(&self, speed: Speed<Spd>) -> Speed<Spd> {
+        let logical = match speed {
+            Speed::Drive(logical) => logical,
+            _ => return speed,
+        };
+
+        let upper = self.steps.iter().position(|(step, _)| *step >= logical);
+        let ((low_speed, low_step), (high_speed, high_step)) = match upper {
+            Some(index) if self.steps[index].0 == logical => {
+                return Speed::Drive(self.steps[index].1)
+            }
+            Some(0) => ((0.0, 0.0), self.point(0)),
+            Some(index) => (self.point(index - 1), self.point(index)),
+            None => {
+                return self
+                    .steps
+                    .last()
+                    .map_or(speed, |(_, step)| Speed::Drive(*step))
+            }
+        };
+
+        let logical = logical.to_f64().unwrap_or_default();
+        let share = (logical - low_speed) / (high_speed - low_speed);
+        round_speed(low_step + (high_step - low_step) * share)
+    }
+
+    fn point(&self, index: usize) -> (f64, f64) {
+        let (speed, step) = self.steps[index];
+        (
+            speed.to_f64().unwrap_or_default(),
+            step.to_f64().unwrap_or_default(),
+        )
+    }
+}
+
+/// The numeric value of a speed, with stops being 0.
+fn speed_value<Spd: SpeedType>(speed: Speed<Spd>) -> f64 {
+    match speed {
+        Speed::Drive(spd) => spd.to_f64().unwrap_or_default(),
+        Speed::Stop | Speed::EmergencyStop => 0.0,
+    }
+}
+
+/// Rounds a numeric value to a speed. Values rounding to 0 stop the train.
+fn round_speed<Spd: SpeedType>(value: f64) -> Speed<Spd> {
+    match <Spd as NumCast>::from(value.round()) {
+        Some(spd) if value.round() > 0.0 => Speed::Drive(spd),
+        _ => Speed::Stop,
+    }
+}
@@ -0,0 +1,144 @@
+use crate::control::messages::Message;
+use crate::control::momentum::{Curve, MomentumProfile, SpeedTable};
+use crate::control::rail_system::components::{Address, Speed};
+use crate::control::rail_system::railroad_test::{create_line_railroad, create_test_railroad};
+use std::sync::Arc;
+use std::time::Duration;
+
+#[test]
+pub fn test_linear_ramp() {
+    let profile = MomentumProfile::default();
+    assert_eq!(
+        profile.ramp(Speed::<u8>::Stop, Speed::Drive(20)),
+        vec![
+            Speed::Drive(5),
+            Speed::Drive(10),
+            Speed::Drive(15),
+            Speed::Drive(20)
+        ]
+    );
+    assert_eq!(
+        profile.ramp(Speed::<u8>::Drive(20), Speed::EmergencyStop),
+        vec![Speed::EmergencyStop]
+    );
+    assert_eq!(
+        profile.ramp(Speed::<u8>::Drive(20), Speed::Drive(20)),
+        vec![Speed::Drive(20)]
+    );
+
+    // Braking slower takes more ticks, but ends with the requested stop.
+    let profile = profile.rates(500, 100);
+    let ramp = profile.ramp(Speed::<u8>::Drive(20), Speed::Stop);
+    assert_eq!(ramp.len(), 20);
+    assert_eq!(ramp.first(), Some(&Speed::Drive(19)));
+    assert_eq!(ramp.last(), Some(&Speed::Stop));
+}
+
+#[test]
+pub fn test_s_curve_ramp() {
+    let profile = MomentumProfile::linear(100).curve(Curve::SCurve);
+    let ramp = profile.ramp(Speed::<u8>::Stop, Speed::Drive(100));
+    assert_eq!(ramp.len(), 100);
+    assert!(ramp.windows(2).all(|steps| steps[0] <= steps[1]));
+
+    let value = |index: usize| match ramp[index] {
+        Speed::Drive(spd) => spd,
+        _ => 0,
+    };
+    // The speed changes slowly at the ends and fast in the middle.
+    assert!(value(9) < 5);
+    assert!(value(54) - value(44) > 10);
+    assert!(value(99) - value(89) < 5);
+}
+
+#[test]
+pub fn test_speed_table() {
+    let table = SpeedTable::new(vec![(100u8, 60), (10, 20)]);
+    assert_eq!(table.steps(), &[(10, 20), (100, 60)]);
+
+    assert_eq!(table.decoder_speed(Speed::Drive(5)), Speed::Drive(10));
+    assert_eq!(table.decoder_speed(Speed::Drive(10)), Speed::Drive(20));
+    assert_eq!(table.decoder_speed(Speed::Drive(55)), Speed::Drive(40));
+    assert_eq!(table.decoder_speed(Speed::Drive(200)), Speed::Drive(60));
+    assert_eq!(table.decoder_speed(Speed::Stop), Speed::Stop);
+    assert_eq!(
+        table.decoder_speed(Speed::EmergencyStop),
+        Speed::EmergencyStop
+    );
+}
+
+#[tokio::test(start_paused = true)]
+pub async fn test_train_ramps_speed() {
+    let (mut r, _switches, _bi_dir_switches, sensors, _bi_dir_sensors, _signals) =
+        create_test_railroad().await;
+    r.create_train(Address::new(1), sensors[2].0).await.unwrap();
+    let r = Arc::new(r);
+    let mut messages = r.subscribe();
+
+    let mut train = r.get_train(&Address::new(1)).unwrap().lock().await;
+    train.set_momentum(MomentumProfile::linear(100));
+    train.set_speed_table(Some(SpeedTable::new(vec![(3, 6)])));
+    train.set_speed(Speed::Drive(3), r.clone()).await;
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let sent: Vec<_> = std::iter::from_fn(|| messages.try_recv().ok()).collect();
+    assert_eq!(
+        sent,
+        vec![
+            Message::TrainSpeed(Address::new(1), Speed::Drive(2)),
+            Message::TrainSpeed(Address::new(1), Speed::Drive(4)),
+            Message::TrainSpeed(Address::new(1), Speed::Drive(6)),
+        ]
+    );
+}
+
+#[test]
+pub fn test_braking_distance() {
+    let profile = MomentumProfile::linear(100);
+    // 10 ticks of 10 ms driving 10, 9, ..., 1 rail units per second.
+    let distance = profile.braking_distance(Speed::<u8>::Drive(10), 1.0);
+    assert!((distance - 0.55).abs() < 1e-9);
+    assert_eq!(profile.braking_distance(Speed::<u8>::Stop, 1.0), 0.0);
+}
+
+#[tokio::test]
+pub async fn test_stopping_point() {
+    let (r, [_first, _second, signal, third]) = create_line_railroad().await;
+    let mut train = r.get_train(&Address::new(1)).unwrap().lock().await;
+    assert_eq!(train.stopping_point(&r).await, None);
+
+    assert!(train.trigger_drive_to(third, r.clone()).await);
+    assert_eq!(train.stopping_point(&r).await, Some((signal, 8.0)));
+
+    // With the block behind the signal granted, the train stops at its destination.
+    train.drive_ok(signal);
+    assert_eq!(train.stopping_point(&r).await, Some((third, 12.0)));
+}
+
+#[tokio::test(start_paused = true)]
+pub async fn test_train_stops_at_signal() {
+    let (r, [_first, _second, _signal, third]) = create_line_railroad().await;
+    let mut messages = r.subscribe();
+    let train = r.get_train(&Address::new(1)).unwrap();
+    {
+        let mut train = train.lock().await;
+        train.trigger_drive_to(third, r.clone()).await;
+        train.set_momentum(MomentumProfile::linear(100));
+        train.set_distance_per_speed_step(Some(1.0));
+        train.set_speed(Speed::Drive(10), r.clone()).await;
+        train.schedule_braking(r.clone()).await;
+    }
+
+    // The signal is 8 units ahead and the train needs 0.55 units to stop
+    // from 10 units per second, so it starts braking after 745 ms.
+    tokio::time::sleep(Duration::from_millis(700)).await;
+    assert_eq!(train.lock().await.speed(), Speed::Drive(10));
+
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert_eq!(train.lock().await.speed(), Speed::Stop);
+    let sent: Vec<_> = std::iter::from_fn(|| messages.try_recv().ok()).collect();
+    assert_eq!(
+        sent.last(),
+        Some(&Message::TrainSpeed(Address::new(1), Speed::Stop))
+    );
+}
@@ -1,7 +1,7 @@
 use crate::control::rail_system::components::{Address, Speed};
-use locodrive::args::{AddressArg, SpeedArg, SwitchDirection};
+use locodrive::args::{AddressArg, SensorLevel, SpeedArg, SwitchDirection};
 
-use super::SwDir;
+use super::{SLevel, SwDir};
 
 impl Address<u16> {
     pub fn address_arg(&self) -> AddressArg {
@@ -41,3 +41,21 @@ impl From<SwDir> for SwitchDirection {
         }
     }
 }
+
+impl From<SwitchDirection> for SwDir {
+    fn from(dir: SwitchDirection) -> Self {
+        match dir {
+            SwitchDirection::Straight => SwDir::Straight,
+            SwitchDirection::Curved => SwDir::Curved,
+        }
+    }
+}
+
+impl From<SensorLevel> for SLevel {
+    fn from(level: SensorLevel) -> Self {
+        match level {
+            SensorLevel::High => SLevel::Occupied,
+            SensorLevel::Low => SLevel::Free,
+        }
+    }
+}
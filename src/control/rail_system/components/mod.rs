@@ -6,12 +6,14 @@ mod signal_checks;
 
 use crate::control::messages::Message;
 use crate::control::rail_system::railroad::Railroad;
+use crate::control::rail_system::state::{SensorState, SignalState, SwitchState};
 use crate::general::{AddressType, DefaultAddressType, DefaultSpeedType, SpeedType};
 use async_recursion::async_recursion;
 use petgraph::graph::NodeIndex;
 use petgraph::visit::{VisitMap, Visitable};
+use serde::{Deserialize, Serialize};
 use std::cmp::Ordering;
-use std::collections::VecDeque;
+use std::collections::{HashMap, VecDeque};
 use std::future::Future;
 use std::ops;
 use std::ops::{Add, Index, Not, Sub};
@@ -20,7 +22,10 @@ use std::time::Duration;
 use tokio::sync::{Mutex, Notify};
 use tokio::{select, spawn};
 
-#[derive(Debug, Copy, Clone, Default, PartialEq, PartialOrd, Eq, Ord, Hash)]
+#[derive(
+    Debug, Copy, Clone, Default, PartialEq, PartialOrd, Eq, Ord, Hash, Serialize, Deserialize,
+)]
+#[serde(bound = "")]
 pub struct Address<Ix: AddressType = DefaultAddressType>(Ix);
 
 impl<Ix> Address<Ix>
@@ -37,7 +42,8 @@ where
     }
 }
 
-#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+#[serde(bound = "")]
 pub enum Speed<Spd: SpeedType = DefaultSpeedType> {
     /// Performs a normal stop. Trains may stop smoothly.
     Stop,
@@ -121,7 +127,37 @@ impl<Spd: SpeedType> Ord for Speed<Spd> {
     }
 }
 
-#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+/// The direction a locomotive drives in, relative to its front.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum TrainDirection {
+    #[default]
+    Forward,
+    Backward,
+}
+
+impl Not for TrainDirection {
+    type Output = Self;
+
+    fn not(self) -> Self::Output {
+        match self {
+            TrainDirection::Forward => TrainDirection::Backward,
+            TrainDirection::Backward => TrainDirection::Forward,
+        }
+    }
+}
+
+impl From<bool> for TrainDirection {
+    fn from(forward: bool) -> Self {
+        if forward {
+            TrainDirection::Forward
+        } else {
+            TrainDirection::Backward
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+#[serde(bound = "")]
 pub enum Node<
     SensorAddr: AddressType,
     SwitchAddr: AddressType,
@@ -135,7 +171,7 @@ pub enum Node<
         Position,
         SwitchType,
         Option<NodeIndex>,
-        petgraph::Direction,
+        #[serde(with = "GraphDirection")] petgraph::Direction,
     ),
     Station(Address<SensorAddr>, Position),
     Cross(Address<CrossingAddr>),
@@ -174,8 +210,16 @@ impl<
     }
 }
 
+/// Serialization helper for the edge direction stored in [Node::Switch].
+#[derive(Serialize, Deserialize)]
+#[serde(remote = "petgraph::Direction")]
+enum GraphDirection {
+    Outgoing,
+    Incoming,
+}
+
 #[repr(u8)]
-#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Ord, PartialOrd)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Ord, PartialOrd, Serialize, Deserialize)]
 pub enum Direction {
     North = 0,
     Northeast = 1,
@@ -255,7 +299,7 @@ impl Direction {
 /// 0 = x-Position
 /// 1 = y-Position
 /// 2 = z-Position
-#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Ord, PartialOrd)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Ord, PartialOrd, Serialize, Deserialize)]
 pub struct Coord(pub usize, pub usize, pub usize);
 
 impl Coord {
@@ -402,7 +446,7 @@ impl Coord {
     }
 }
 
-#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Ord, PartialOrd)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Ord, PartialOrd, Serialize, Deserialize)]
 pub struct Position {
     coord: Coord,
     dir: Direction,
@@ -442,7 +486,7 @@ impl Position {
     }
 }
 
-#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Ord, PartialOrd)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Ord, PartialOrd, Serialize, Deserialize)]
 pub struct Rail {
     length: usize,
     pos: Position,
@@ -618,7 +662,7 @@ impl Rail {
 }
 
 #[repr(u8)]
-#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Ord, PartialOrd)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Ord, PartialOrd, Serialize, Deserialize)]
 pub enum SwitchType {
     StraightRight90 = 0,
     StraightRight180 = 1,
@@ -634,7 +678,7 @@ pub enum SwitchType {
     RightLeft180 = 11,
 }
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum SwDir {
     Straight,
     Curved,
@@ -661,59 +705,150 @@ impl From<bool> for SwDir {
     }
 }
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
-pub struct Switch<SwitchAddr: AddressType> {
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Switch<SwitchAddr: AddressType, TrainAddr: AddressType> {
     address: Address<SwitchAddr>,
     dir: SwDir,
     updated: bool,
+    /// The train, whose route locks this switch
+    locked: Option<Address<TrainAddr>>,
+    /// The trains, whose routes over the given switches are protected by this switch
+    /// leading other trains away from them
+    flank_locks: Vec<(Address<TrainAddr>, Address<SwitchAddr>)>,
 }
 
-impl<SwitchAddr: AddressType> Switch<SwitchAddr> {
+impl<SwitchAddr: AddressType, TrainAddr: AddressType> Switch<SwitchAddr, TrainAddr> {
     pub fn new(address: Address<SwitchAddr>) -> Self {
         Switch {
             address,
             dir: SwDir::Straight,
             updated: false,
+            locked: None,
+            flank_locks: vec![],
         }
     }
 
-    /// Moves this switch in the correct orientation, so the requested way could be driven.
+    /// Returns the train, whose route locks this switch.
+    pub fn locked_by(&self) -> Option<Address<TrainAddr>> {
+        self.locked
+    }
+
+    /// Checks if this switch protects the route of the train over the `protected` switch.
+    pub fn protects(&self, train: Address<TrainAddr>, protected: Address<SwitchAddr>) -> bool {
+        self.flank_locks.contains(&(train, protected))
+    }
+
+    /// Checks if the switch could be directed to `dir` for the train,
+    /// without moving it for the locks of other trains.
+    fn could_direct(&self, train: Address<TrainAddr>, dir: SwDir) -> bool {
+        let others = self.locked.is_some_and(|locked| locked != train)
+            || self.flank_locks.iter().any(|(locked, _)| *locked != train);
+        !others || self.dir == dir
+    }
+
+    /// Locks this switch in the direction `dir` for the route of the train,
+    /// so no other route moves it.
+    /// Returns `false`, if the switch is locked for the route of another train,
+    /// or another train needs it in the other direction.
+    pub fn lock(&mut self, train: Address<TrainAddr>, dir: SwDir) -> bool {
+        if self.locked.is_some_and(|locked| locked != train) || !self.could_direct(train, dir) {
+            return false;
+        }
+        self.locked = Some(train);
+        true
+    }
+
+    /// Releases the lock of the train, after its tail passed this switch.
+    pub fn unlock(&mut self, train: Address<TrainAddr>) {
+        if self.locked == Some(train) {
+            self.locked = None;
+        }
+    }
+
+    /// Locks this switch in the direction `dir`, leading other trains away from the route of
+    /// the train over the `protected` switch.
+    /// Several trains could lock the switch, as long as they need the same direction.
+    /// Returns `false`, if another train needs the switch in the other direction.
+    pub fn lock_flank(
+        &mut self,
+        train: Address<TrainAddr>,
+        protected: Address<SwitchAddr>,
+        dir: SwDir,
+    ) -> bool {
+        if !self.could_direct(train, dir) {
+            return false;
+        }
+        if !self.protects(train, protected) {
+            self.flank_locks.push((train, protected));
+        }
+        true
+    }
+
+    /// Releases the flank protection of the route of the train over the `protected` switch.
+    pub fn unlock_flank(&mut self, train: Address<TrainAddr>, protected: Address<SwitchAddr>) {
+        self.flank_locks.retain(|lock| *lock != (train, protected));
+    }
+
+    /// Releases all locks of the train.
+    pub fn release(&mut self, train: Address<TrainAddr>) {
+        self.unlock(train);
+        self.flank_locks.retain(|(locked, _)| *locked != train);
+    }
+
+    /// Returns the direction the switch at `switch_node` needs,
+    /// to lead from `from_index` to `to_index`.
+    pub async fn path_dir<
+        Spd: SpeedType,
+        SensorAddr: AddressType,
+        SignalAddr: AddressType,
+        CrossingAddr: AddressType,
+    >(
+        switch_node: NodeIndex,
+        from_index: NodeIndex,
+        to_index: NodeIndex,
+        railroad: &Railroad<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr, CrossingAddr>,
+    ) -> Option<SwDir> {
+        match railroad.road().await.node_weight(switch_node)? {
+            Node::Switch(_adr, _pos, _s_type, Some(node), _dir) => {
+                Some(SwDir::from(from_index == *node || to_index == *node))
+            }
+            Node::Switch(..) => Some(SwDir::Curved),
+            _ => None,
+        }
+    }
+
+    /// Locks this switch for the route of the train and moves it in the correct orientation,
+    /// so the requested way could be driven.
+    /// Returns `false` without moving the switch, if it is locked for another train.
     /// This function can return before the actual switch is completely performed.
     /// Please use the [Switch::switch_in_correct_state] Method to check
     /// if the switch already performed it's switch operation in the correct state,
     /// else you may wait until the switch is performed completely.
     pub async fn request_path<
         Spd: SpeedType,
-        TrainAddr: AddressType,
         SensorAddr: AddressType,
         SignalAddr: AddressType,
         CrossingAddr: AddressType,
     >(
         &mut self,
+        train: Address<TrainAddr>,
         switch_node: NodeIndex,
         from_index: NodeIndex,
         to_index: NodeIndex,
         railroad: &Railroad<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr, CrossingAddr>,
-    ) {
-        if let Some(Node::Switch(_adr, _pos, _s_type, some_node, _dir)) =
-            railroad.road().await.node_weight(switch_node)
-        {
-            if let Some(node) = some_node {
-                self.switch(
-                    SwDir::from(from_index == *node || to_index == *node),
-                    railroad,
-                )
-                .await;
-            } else {
-                self.switch(SwDir::Curved, railroad).await;
+    ) -> bool {
+        match Switch::path_dir(switch_node, from_index, to_index, railroad).await {
+            Some(dir) if self.lock(train, dir) => {
+                self.direct(dir, railroad);
+                true
             }
+            _ => false,
         }
     }
 
     /// Checks if the switch is in the correct state to pass.
     pub async fn switch_in_correct_state<
         Spd: SpeedType,
-        TrainAddr: AddressType,
         SensorAddr: AddressType,
         SignalAddr: AddressType,
         CrossingAddr: AddressType,
@@ -724,25 +859,36 @@ impl<SwitchAddr: AddressType> Switch<SwitchAddr> {
         to_index: NodeIndex,
         railroad: &Railroad<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr, CrossingAddr>,
     ) -> bool {
-        if let Some(Node::Switch(_adr, _pos, _s_type, some_node, _dir)) =
-            railroad.road().await.node_weight(switch_node)
-        {
-            let test_dir = if let Some(node) = some_node {
-                SwDir::from(from_index == *node || to_index == *node)
-            } else {
-                SwDir::Curved
-            };
-            self.dir == test_dir && self.updated
-        } else {
-            false
-        }
+        Switch::path_dir(switch_node, from_index, to_index, railroad)
+            .await
+            .is_some_and(|dir| self.dir == dir && self.updated)
     }
 
     /// Requests a switching of this switch to the requested direction,
     /// if the switch is not already directed correctly.
+    /// Returns `false` without moving the switch, if it is locked for the route of a train
+    /// or protects one in the other direction.
     pub async fn switch<
         Spd: SpeedType,
-        TrainAddr: AddressType,
+        SensorAddr: AddressType,
+        SignalAddr: AddressType,
+        CrossingAddr: AddressType,
+    >(
+        &mut self,
+        dir: SwDir,
+        railroad: &Railroad<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr, CrossingAddr>,
+    ) -> bool {
+        let locked = self.locked.is_some() || !self.flank_locks.is_empty();
+        if locked && self.dir != dir {
+            return false;
+        }
+        self.direct(dir, railroad);
+        true
+    }
+
+    /// Sends the command moving this switch to `dir`, regardless of its locks.
+    fn direct<
+        Spd: SpeedType,
         SensorAddr: AddressType,
         SignalAddr: AddressType,
         CrossingAddr: AddressType,
@@ -755,6 +901,7 @@ impl<SwitchAddr: AddressType> Switch<SwitchAddr> {
             return;
         }
 
+        self.dir = dir;
         self.updated = false;
         let message = Message::Switch(self.address, dir);
         railroad.send(message);
@@ -764,7 +911,6 @@ impl<SwitchAddr: AddressType> Switch<SwitchAddr> {
     /// Note: This method may later be private depending on further implementations.
     pub async fn ack_switch_state<
         Spd: SpeedType,
-        TrainAddr: AddressType,
         SensorAddr: AddressType,
         SignalAddr: AddressType,
         CrossingAddr: AddressType,
@@ -777,13 +923,31 @@ impl<SwitchAddr: AddressType> Switch<SwitchAddr> {
             self.updated = true;
         } else {
             self.updated = false;
-            self.switch(self.dir, railroad).await;
+            self.direct(self.dir, railroad);
         }
     }
 
     pub fn address(&self) -> Address<SwitchAddr> {
         self.address
     }
+
+    /// Returns the current direction and acknowledgement of this switch.
+    pub fn state(&self) -> SwitchState<SwitchAddr, TrainAddr> {
+        SwitchState {
+            address: self.address,
+            dir: self.dir,
+            updated: self.updated,
+            locked: self.locked,
+            flank_locks: self.flank_locks.clone(),
+        }
+    }
+
+    pub fn restore_state(&mut self, state: &SwitchState<SwitchAddr, TrainAddr>) {
+        self.dir = state.dir;
+        self.updated = state.updated;
+        self.locked = state.locked;
+        self.flank_locks = state.flank_locks.clone();
+    }
 }
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
@@ -805,9 +969,17 @@ impl<CrossAddr: AddressType> Cross<CrossAddr> {
     pub fn address(&self) -> Address<CrossAddr> {
         self.address
     }
+
+    pub fn pos(&self) -> Position {
+        self.pos
+    }
+
+    pub fn nodes(&self) -> (NodeIndex, NodeIndex) {
+        self.nodes
+    }
 }
 
-#[derive(Debug, Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash)]
+#[derive(Debug, Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Serialize, Deserialize)]
 pub enum Status {
     Free,
     Reserved,
@@ -834,7 +1006,7 @@ impl ops::BitOr for Status {
     }
 }
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum SLevel {
     Occupied,
     Free,
@@ -920,7 +1092,10 @@ impl<Spd: SpeedType, SensorAddr: AddressType, TrainAddr: AddressType>
                 self.reenter_notifier.notify_waiters();
                 self.level = s_level;
             }
-            SLevel::Free => self.sensor_free(railroad),
+            SLevel::Free => {
+                self.level = s_level;
+                self.sensor_free(railroad)
+            }
         }
     }
 
@@ -955,18 +1130,35 @@ impl<Spd: SpeedType, SensorAddr: AddressType, TrainAddr: AddressType>
         adr: Address<SensorAddr>,
         railroad: Arc<Railroad<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr, CrossingAddr>>,
     ) {
-        if let Some(sensor) = railroad.get_sensor_mutex(&adr) {
-            let mut sensor = sensor.lock().await;
+        let mutex = match railroad.get_sensor_mutex(&adr) {
+            Some(mutex) => mutex,
+            None => return,
+        };
+        let train = {
+            let sensor = mutex.lock().await;
             if sensor.status == Status::Occupied {
                 return;
             }
-            sensor.status = Status::Free;
-            sensor.reenter_notifier.notify_waiters();
-            sensor
-                .train
-                .into_iter()
-                .for_each(|t| sensor.free(t, &railroad));
+            sensor.train
+        };
+
+        // A train knowing the sensors it occupies decides itself, when its tail left the sensor.
+        if let Some(train) = train.and_then(|train| railroad.get_train(&train)) {
+            if train.lock().await.sensor_left(adr, &railroad).await {
+                return;
+            }
+        }
+
+        let mut sensor = mutex.lock().await;
+        if sensor.level == SLevel::Occupied {
+            return;
         }
+        sensor.status = Status::Free;
+        sensor.reenter_notifier.notify_waiters();
+        sensor
+            .train
+            .into_iter()
+            .for_each(|t| sensor.free(t, &railroad));
     }
 
     pub fn free<SwitchAddr: AddressType, SignalAddr: AddressType, CrossingAddr: AddressType>(
@@ -989,6 +1181,22 @@ impl<Spd: SpeedType, SensorAddr: AddressType, TrainAddr: AddressType>
     pub fn status(&self) -> Status {
         self.status
     }
+
+    /// Returns the current reservation and occupation of this sensor.
+    pub fn state(&self) -> SensorState<SensorAddr, TrainAddr> {
+        SensorState {
+            address: self.address,
+            status: self.status,
+            level: self.level,
+            train: self.train,
+        }
+    }
+
+    pub fn restore_state(&mut self, state: &SensorState<SensorAddr, TrainAddr>) {
+        self.status = state.status;
+        self.level = state.level;
+        self.train = state.train;
+    }
 }
 
 async fn wait_and_run<T>(duration: Duration, interrupter: Arc<Notify>, call: T)
@@ -1003,13 +1211,51 @@ where
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub enum SignalType {
     Block,
     Path,
     IntelligentPath,
 }
 
+/// The aspect a signal shows the trains approaching it.
+///
+/// Every signal also acts as the distant signal of the next signal on the route,
+/// so no separate distant signals are placed in front of them.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+#[serde(bound = "")]
+pub enum Aspect<Spd: SpeedType = DefaultSpeedType> {
+    /// The block behind the signal is not granted to any train.
+    Stop,
+    /// The train may drive on.
+    Proceed,
+    /// The train may drive on over a diverging route, but not faster than the given speed.
+    ProceedLimited(Speed<Spd>),
+    /// The train may drive on, but the next signal shows [Aspect::Stop].
+    ExpectStop,
+}
+
+impl<Spd: SpeedType> Aspect<Spd> {
+    /// Returns the speed trains must not exceed behind a signal showing this aspect.
+    pub fn speed_limit(&self) -> Option<Speed<Spd>> {
+        match self {
+            Aspect::ProceedLimited(speed) => Some(*speed),
+            _ => None,
+        }
+    }
+
+    /// Returns the aspect number, which shows this aspect on a DCC extended accessory decoder.
+    /// `0` is the absolute stop, as defined by the DCC standard.
+    pub fn dcc_aspect(&self) -> u8 {
+        match self {
+            Aspect::Stop => 0,
+            Aspect::Proceed => 1,
+            Aspect::ExpectStop => 2,
+            Aspect::ProceedLimited(_) => 3,
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Signal<SignalAddr: AddressType, TrainAddr: AddressType, SensorAddr: AddressType> {
     address: Address<SignalAddr>,
@@ -1018,9 +1264,14 @@ pub struct Signal<SignalAddr: AddressType, TrainAddr: AddressType, SensorAddr: A
     status: Status,
     trains: Vec<Address<TrainAddr>>,
     requesters: VecDeque<Address<TrainAddr>>,
+    /// The routes through the block, the requesting trains asked for
+    routes: HashMap<Address<TrainAddr>, Vec<NodeIndex>>,
     other_input_signals: Vec<Address<SignalAddr>>,
     block_sensors: Vec<Address<SensorAddr>>,
     calculation_group: Arc<Mutex<Address<SignalAddr>>>,
+    /// The trains the block is granted to, together with their route through it,
+    /// whose switches did not acknowledge the route yet
+    switching: Vec<(Address<TrainAddr>, Vec<NodeIndex>)>,
 }
 
 impl<SignalAddr: AddressType, TrainAddr: AddressType, SensorAddr: AddressType>
@@ -1038,9 +1289,11 @@ impl<SignalAddr: AddressType, TrainAddr: AddressType, SensorAddr: AddressType>
             status: Status::Free,
             trains: vec![],
             requesters: VecDeque::new(),
+            routes: HashMap::new(),
             other_input_signals: vec![],
             block_sensors: vec![],
             calculation_group: Arc::new(Mutex::new(address)),
+            switching: vec![],
         }
     }
 
@@ -1048,6 +1301,10 @@ impl<SignalAddr: AddressType, TrainAddr: AddressType, SensorAddr: AddressType>
         self.representing_node
     }
 
+    pub fn signal_type(&self) -> SignalType {
+        self.sig_type
+    }
+
     async fn reset_group<Spd: SpeedType, SwitchAddr: AddressType, CrossingAddr: AddressType>(
         &mut self,
         signal: &Address<SignalAddr>,
@@ -1078,6 +1335,10 @@ impl<SignalAddr: AddressType, TrainAddr: AddressType, SensorAddr: AddressType>
         self.other_input_signals = signals;
     }
 
+    /// Requests the block behind this signal for the train driving the given route through it.
+    /// A train, that already waits for the block, only updates its route.
+    ///
+    /// The route is passed in, because the train is locked while it requests the block.
     pub async fn request_block<
         Spd: SpeedType,
         SwitchAddr: AddressType,
@@ -1085,17 +1346,55 @@ impl<SignalAddr: AddressType, TrainAddr: AddressType, SensorAddr: AddressType>
     >(
         &mut self,
         train: Address<TrainAddr>,
+        route: Vec<NodeIndex>,
         railroad: Arc<Railroad<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr, CrossingAddr>>,
     ) {
+        if self.trains.contains(&train) {
+            return;
+        }
+        self.routes.insert(train, route);
+        if self.requesters.contains(&train) {
+            return;
+        }
         self.requesters.push_back(train);
-        let sig_adr = Arc::new(self.address());
-        let rail = railroad.clone();
-        let _ = spawn(async move {
-            let sig = sig_adr.clone();
-            let rail = rail.clone();
-            Signal::get_signal_and_next(sig.clone(), rail.clone()).await;
-        })
-        .await;
+
+        // The calculation needs this signal, so it waits until the caller releases it.
+        spawn(Signal::get_signal_and_next(
+            Arc::new(self.address()),
+            railroad,
+        ));
+    }
+
+    /// Takes back the request of the train for the block behind this signal,
+    /// e.g. because it drives another route now.
+    pub fn withdraw(&mut self, train: Address<TrainAddr>) {
+        self.requesters.retain(|t| *t != train);
+        self.routes.remove(&train);
+    }
+
+    /// Checks if the block behind this signal is granted to the given train
+    /// and all switches of its route through the block acknowledged the route.
+    pub fn granted(&self, train: Address<TrainAddr>) -> bool {
+        self.trains.contains(&train) && !self.switching.iter().any(|(t, _)| *t == train)
+    }
+
+    pub fn block_sensors(&self) -> &[Address<SensorAddr>] {
+        &self.block_sensors
+    }
+
+    /// Takes back the grant of a train, that passed this signal.
+    /// The block is granted to the next waiting train, as soon as it is free.
+    pub async fn passed<Spd: SpeedType, SwitchAddr: AddressType, CrossingAddr: AddressType>(
+        &mut self,
+        train: Address<TrainAddr>,
+        railroad: Arc<Railroad<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr, CrossingAddr>>,
+    ) {
+        self.trains.retain(|t| *t != train);
+        self.switching.retain(|(t, _)| *t != train);
+        if self.trains.is_empty() {
+            self.status = Status::Free;
+            self.next(railroad).await;
+        }
     }
 
     async fn get_signal_and_next<
@@ -1121,10 +1420,17 @@ impl<SignalAddr: AddressType, TrainAddr: AddressType, SensorAddr: AddressType>
         let cloned = self.calculation_group.clone();
         let _calculate = cloned.lock().await;
         if let Some(free_road) = self.drive(&railroad).await {
-            if !self.requesters.is_empty() {
+            let train = match self.requesters.front() {
+                Some(train) => *train,
+                None => return,
+            };
+            // Trains driving no route through the block only get its sensors.
+            let route = self.routes.get(&train).cloned().unwrap_or_default();
+            if !Signal::set_route(train, &route, &railroad).await {
                 return;
             }
-            let train = self.requesters.pop_front().unwrap();
+            self.requesters.pop_front();
+            self.routes.remove(&train);
             self.status = Status::Reserved;
             self.trains.push(train);
 
@@ -1134,7 +1440,29 @@ impl<SignalAddr: AddressType, TrainAddr: AddressType, SensorAddr: AddressType>
                     sensor.block(train);
                 }
             }
+            self.switching.push((train, route));
+            self.confirm_switches(&railroad).await;
+        }
+    }
+
+    /// Sends [Message::TrainGranted] for the trains, whose route switches acknowledged the route.
+    pub(crate) async fn confirm_switches<
+        Spd: SpeedType,
+        SwitchAddr: AddressType,
+        CrossingAddr: AddressType,
+    >(
+        &mut self,
+        railroad: &Railroad<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr, CrossingAddr>,
+    ) {
+        let mut switching = vec![];
+        for (train, route) in std::mem::take(&mut self.switching) {
+            if Signal::route_switched(&route, railroad).await {
+                railroad.send(Message::TrainGranted(self.address, train));
+            } else {
+                switching.push((train, route));
+            }
         }
+        self.switching = switching;
     }
 
     pub fn address(&self) -> Address<SignalAddr> {
@@ -1145,6 +1473,28 @@ impl<SignalAddr: AddressType, TrainAddr: AddressType, SensorAddr: AddressType>
         self.status
     }
 
+    /// Returns the current reservations of this signal.
+    pub fn state(&self) -> SignalState<SignalAddr, TrainAddr> {
+        let mut routes: Vec<_> = self.routes.clone().into_iter().collect();
+        routes.sort_by_key(|(train, _)| *train);
+        SignalState {
+            address: self.address,
+            status: self.status,
+            trains: self.trains.clone(),
+            requesters: self.requesters.clone(),
+            routes,
+            switching: self.switching.clone(),
+        }
+    }
+
+    pub fn restore_state(&mut self, state: &SignalState<SignalAddr, TrainAddr>) {
+        self.status = state.status;
+        self.trains = state.trains.clone();
+        self.requesters = state.requesters.clone();
+        self.routes = state.routes.iter().cloned().collect();
+        self.switching = state.switching.clone();
+    }
+
     pub fn trigger_update(&mut self, trigger: &Status) {
         self.status = match trigger {
             Status::Reserved => match self.status {
@@ -1212,6 +1562,7 @@ impl<SignalAddr: AddressType, TrainAddr: AddressType, SensorAddr: AddressType>
         &mut self,
         railroad: Arc<Railroad<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr, CrossingAddr>>,
     ) {
+        self.confirm_switches(&railroad).await;
         if self.trains.is_empty() {
             self.next(railroad).await;
         }
@@ -15,6 +15,57 @@ fn handle_cross_route<CrossingAddr: AddressType>(
     }
 }
 
+/// The switches on a route with their direction, followed by the switches protecting its flanks
+/// with the switch of the route they protect and their direction.
+type RouteSwitches<SwitchAddr> = (
+    Vec<(Address<SwitchAddr>, SwDir)>,
+    Vec<(Address<SwitchAddr>, Address<SwitchAddr>, SwDir)>,
+);
+
+/// Returns the switch protecting the route entering the merging `switch` from `from` against
+/// trains coming over its other leg, together with the direction leading them away.
+///
+/// The other leg is followed back over plain track to the next switch.
+/// Only switches diverging onto the leg could protect it.
+fn flank_protection<
+    SensorAddr: AddressType,
+    SwitchAddr: AddressType,
+    SignalAddr: AddressType,
+    CrossingAddr: AddressType,
+>(
+    graph: &DiGraph<Node<SensorAddr, SwitchAddr, SignalAddr, CrossingAddr>, Vec<Rail>>,
+    switch: NodeIndex,
+    from: NodeIndex,
+) -> Option<(Address<SwitchAddr>, SwDir)> {
+    let mut legs = graph.neighbors_directed(switch, petgraph::Direction::Incoming);
+    let mut node = legs.find(|leg| *leg != from)?;
+    let mut next = switch;
+
+    // Every node is passed once at most, even on circular tracks.
+    for _ in 0..graph.node_count() {
+        match graph.node_weight(node)? {
+            Node::Switch(adr, _pos, _s_type, default, _dir) => {
+                if graph
+                    .neighbors_directed(node, petgraph::Direction::Outgoing)
+                    .count()
+                    != 2
+                {
+                    return None;
+                }
+                return Some((*adr, SwDir::from((*default)? != next)));
+            }
+            Node::Cross(..) | Node::Buffer(..) => return None,
+            _ => {
+                next = node;
+                node = graph
+                    .neighbors_directed(node, petgraph::Direction::Incoming)
+                    .next()?;
+            }
+        }
+    }
+    None
+}
+
 /// Pushes the `succ`essor node to the end of the `stack`. Then checks if the `parent_node` is a
 /// crossing. If so it will push the cross successor as well.
 async fn handle_found_node<
@@ -55,7 +106,7 @@ async fn handle_successor<
     signal: &NodeIndex,
     in_signals: &mut Vec<Address<SignalAddr>>,
 ) {
-    if discovered.visit(succ) {
+    if !discovered.visit(succ) {
         return;
     }
 
@@ -175,29 +226,12 @@ impl<SignalAddr: AddressType, TrainAddr: AddressType, SensorAddr: AddressType>
         }
     }
 
-    async fn get_route<Spd: SpeedType, SwitchAddr: AddressType, CrossingAddr: AddressType>(
-        train: &Address<TrainAddr>,
-        signal: &Address<SignalAddr>,
-        railroad: &Railroad<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr, CrossingAddr>,
-    ) -> Option<Vec<NodeIndex>> {
-        let train = railroad.get_train(train)?.lock().await;
-        Some(
-            train
-                .request_route(*signal, railroad)
-                .await?
-                .iter()
-                .map(|x| **x)
-                .collect(),
-        )
-    }
-
     async fn path_behaviour<Spd: SpeedType, SwitchAddr: AddressType, CrossingAddr: AddressType>(
         &self,
         railroad: &Railroad<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr, CrossingAddr>,
     ) -> Option<Vec<Address<SensorAddr>>> {
-        let first = &self.requesters.front()?;
-        let route = Signal::get_route(first, &self.address, railroad).await?;
-        if !Signal::path_free(&route, railroad, matches!(&self.sig_type, SignalType::Path)).await {
+        let route = self.routes.get(self.requesters.front()?)?;
+        if !Signal::path_free(route, railroad, matches!(&self.sig_type, SignalType::Path)).await {
             return None;
         }
 
@@ -212,9 +246,8 @@ impl<SignalAddr: AddressType, TrainAddr: AddressType, SensorAddr: AddressType>
         &self,
         railroad: &Railroad<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr, CrossingAddr>,
     ) -> Option<Vec<Address<SensorAddr>>> {
-        let first = &self.requesters.front()?;
-        let route = Signal::get_route(first, &self.address, railroad).await?;
-        if !Signal::path_free(&route, railroad, matches!(&self.sig_type, SignalType::Path)).await {
+        let route = self.routes.get(self.requesters.front()?)?;
+        if !Signal::path_free(route, railroad, matches!(&self.sig_type, SignalType::Path)).await {
             return None;
         }
 
@@ -285,4 +318,122 @@ impl<SignalAddr: AddressType, TrainAddr: AddressType, SensorAddr: AddressType>
         }
         true
     }
+
+    /// Returns the switches on the `route` with the direction leading along it,
+    /// followed by the switches protecting its flanks, together with the switch of the route
+    /// they protect and the direction leading other trains away from it.
+    async fn route_switches<Spd: SpeedType, SwitchAddr: AddressType, CrossingAddr: AddressType>(
+        route: &[NodeIndex],
+        railroad: &Railroad<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr, CrossingAddr>,
+    ) -> RouteSwitches<SwitchAddr> {
+        let road = railroad.road().await;
+        let mut switches = vec![];
+        let mut flanks = vec![];
+        for step in route.windows(3) {
+            if let Some(Node::Switch(adr, ..)) = road.node_weight(step[1]) {
+                if let Some(dir) = Switch::path_dir(step[1], step[0], step[2], railroad).await {
+                    switches.push((*adr, dir));
+                }
+                if let Some((flank, dir)) = flank_protection(&road, step[1], step[0]) {
+                    flanks.push((flank, *adr, dir));
+                }
+            }
+        }
+        // Switches of the route are directed along it.
+        flanks.retain(|(flank, ..)| !switches.iter().any(|(adr, _)| adr == flank));
+        (switches, flanks)
+    }
+
+    /// Locks the switches on the `route` for the `train` and directs them along it.
+    /// The switches protecting its flanks are locked leading other trains away from it.
+    /// Returns `false` without locking any switch, if one of them is locked for another train.
+    pub(super) async fn set_route<
+        Spd: SpeedType,
+        SwitchAddr: AddressType,
+        CrossingAddr: AddressType,
+    >(
+        train: Address<TrainAddr>,
+        route: &[NodeIndex],
+        railroad: &Railroad<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr, CrossingAddr>,
+    ) -> bool {
+        let (switches, flanks) = Signal::route_switches(route, railroad).await;
+
+        let mut locked = vec![];
+        let mut flanks_locked = vec![];
+        let mut lockable = true;
+        for (adr, dir) in &switches {
+            if let Some(switch) = railroad.get_switch_mutex(adr) {
+                let mut switch = switch.lock().await;
+                let was_locked = switch.locked_by() == Some(train);
+                lockable = switch.lock(train, *dir);
+                if !lockable {
+                    break;
+                }
+                if !was_locked {
+                    locked.push(*adr);
+                }
+            }
+        }
+        for (flank, protected, dir) in &flanks {
+            if !lockable {
+                break;
+            }
+            if let Some(switch) = railroad.get_switch_mutex(flank) {
+                let mut switch = switch.lock().await;
+                let was_locked = switch.protects(train, *protected);
+                lockable = switch.lock_flank(train, *protected, *dir);
+                if !lockable {
+                    break;
+                }
+                if !was_locked {
+                    flanks_locked.push((*flank, *protected));
+                }
+            }
+        }
+
+        if !lockable {
+            for adr in locked {
+                if let Some(switch) = railroad.get_switch_mutex(&adr) {
+                    switch.lock().await.unlock(train);
+                }
+            }
+            for (flank, protected) in flanks_locked {
+                if let Some(switch) = railroad.get_switch_mutex(&flank) {
+                    switch.lock().await.unlock_flank(train, protected);
+                }
+            }
+            return false;
+        }
+
+        let flanks = flanks.into_iter().map(|(flank, _, dir)| (flank, dir));
+        for (adr, dir) in switches.into_iter().chain(flanks) {
+            if let Some(switch) = railroad.get_switch_mutex(&adr) {
+                switch.lock().await.direct(dir, railroad);
+            }
+        }
+        true
+    }
+
+    /// Checks if all switches on the `route` and protecting its flanks acknowledged their
+    /// direction.
+    pub(super) async fn route_switched<
+        Spd: SpeedType,
+        SwitchAddr: AddressType,
+        CrossingAddr: AddressType,
+    >(
+        route: &[NodeIndex],
+        railroad: &Railroad<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr, CrossingAddr>,
+    ) -> bool {
+        let (switches, flanks) = Signal::route_switches(route, railroad).await;
+        let flanks = flanks.into_iter().map(|(flank, _, dir)| (flank, dir));
+        for (adr, dir) in switches.into_iter().chain(flanks) {
+            if let Some(switch) = railroad.get_switch_mutex(&adr) {
+                let state = switch.lock().await.state();
+                if state.dir != dir || !state.updated {
+                    return false;
+                }
+            }
+        }
+        true
+    }
 }
@@ -0,0 +1,201 @@
+use crate::control::momentum::{MomentumProfile, SpeedTable};
+use crate::control::rail_system::components::{Address, Node, Position, Rail, SignalType, Speed};
+use crate::control::train::Headlights;
+use crate::general::{AddressType, SpeedType};
+use petgraph::graph::{DiGraph, NodeIndex};
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+
+/// The version of the layout format written by this crate.
+/// Layouts of other versions are rejected on loading.
+pub const LAYOUT_VERSION: u32 = 2;
+
+/// A serializable description of a complete railroad layout.
+///
+/// Create one from a [Builder](crate::control::rail_system::railroad::Builder) by calling
+/// [Builder::to_layout](crate::control::rail_system::railroad::Builder::to_layout) and turn it
+/// back into a builder with
+/// [Builder::from_layout](crate::control::rail_system::railroad::Builder::from_layout),
+/// which validates the layout before returning the builder.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(bound = "")]
+pub struct Layout<
+    Spd: SpeedType,
+    TrainAddr: AddressType,
+    SensorAddr: AddressType,
+    SwitchAddr: AddressType,
+    SignalAddr: AddressType,
+    CrossingAddr: AddressType,
+> {
+    /// The format version this layout was written with
+    pub version: u32,
+    /// The rail graph
+    pub road: DiGraph<Node<SensorAddr, SwitchAddr, SignalAddr, CrossingAddr>, Vec<Rail>>,
+    pub sensors: Vec<SensorLayout<Spd, SensorAddr>>,
+    pub signals: Vec<SignalLayout<SignalAddr>>,
+    pub crossings: Vec<CrossingLayout<CrossingAddr>>,
+    pub switches: Vec<SwitchLayout<SwitchAddr>>,
+    /// The trains initially placed on the layout
+    pub trains: Vec<TrainLayout<Spd, TrainAddr>>,
+}
+
+/// One sensor or station and all nodes representing it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(bound = "")]
+pub struct SensorLayout<Spd: SpeedType, SensorAddr: AddressType> {
+    pub address: Address<SensorAddr>,
+    pub max_speed: Speed<Spd>,
+    pub nodes: Vec<NodeIndex>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(bound = "")]
+pub struct SignalLayout<SignalAddr: AddressType> {
+    pub address: Address<SignalAddr>,
+    pub signal_type: SignalType,
+    pub node: NodeIndex,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(bound = "")]
+pub struct CrossingLayout<CrossingAddr: AddressType> {
+    pub address: Address<CrossingAddr>,
+    pub position: Position,
+    pub nodes: (NodeIndex, NodeIndex),
+}
+
+/// One switch and all nodes representing it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(bound = "")]
+pub struct SwitchLayout<SwitchAddr: AddressType> {
+    pub address: Address<SwitchAddr>,
+    pub nodes: Vec<NodeIndex>,
+}
+
+/// One train and its configuration, which does not change while it drives
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(bound = "")]
+pub struct TrainLayout<Spd: SpeedType, TrainAddr: AddressType> {
+    pub address: Address<TrainAddr>,
+    /// The sensor or station node the train is standing on
+    pub position: NodeIndex,
+    pub headlights: Headlights,
+    pub momentum: MomentumProfile,
+    pub speed_table: Option<SpeedTable<Spd>>,
+    pub distance_per_speed_step: Option<f64>,
+    /// The length of the train, measured in rail units
+    pub length: Option<f64>,
+}
+
+/// The reasons a layout could be rejected.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LayoutError {
+    /// The layout was written with an unsupported format version.
+    UnsupportedVersion(u32),
+    /// A component references a node, that is not part of the rail graph.
+    MissingNode(NodeIndex),
+    /// A component references a node of another kind or address.
+    NodeMismatch(NodeIndex),
+    /// A node of the rail graph is not represented by any component.
+    UnregisteredNode(NodeIndex),
+    /// An address is used twice. The node is the first node of the second component.
+    DuplicateAddress(NodeIndex),
+    /// A sensor or switch is not placed on any node.
+    NoNodes,
+    /// A node has more connections than its kind allows.
+    TooManyNeighbours(NodeIndex),
+    /// The default connection of a switch references a node, that does not exist.
+    InvalidSwitchDefault(NodeIndex),
+    /// A train is not placed on a free sensor or station.
+    InvalidTrainPosition(NodeIndex),
+    /// The layout could not be read or written in the requested format.
+    Format(String),
+}
+
+impl Display for LayoutError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LayoutError::UnsupportedVersion(version) => {
+                write!(f, "unsupported layout version {}", version)
+            }
+            LayoutError::MissingNode(node) => write!(f, "node {} does not exist", node.index()),
+            LayoutError::NodeMismatch(node) => {
+                write!(f, "node {} does not match its component", node.index())
+            }
+            LayoutError::UnregisteredNode(node) => {
+                write!(f, "node {} belongs to no component", node.index())
+            }
+            LayoutError::DuplicateAddress(node) => {
+                write!(f, "the address of node {} is already used", node.index())
+            }
+            LayoutError::NoNodes => write!(f, "a sensor or switch is placed on no node"),
+            LayoutError::TooManyNeighbours(node) => {
+                write!(f, "node {} has too many neighbours", node.index())
+            }
+            LayoutError::InvalidSwitchDefault(node) => {
+                write!(
+                    f,
+                    "switch {} has an invalid default connection",
+                    node.index()
+                )
+            }
+            LayoutError::InvalidTrainPosition(node) => {
+                write!(f, "a train can not be placed on node {}", node.index())
+            }
+            LayoutError::Format(err) => write!(f, "malformed layout: {}", err),
+        }
+    }
+}
+
+impl Error for LayoutError {}
+
+impl<
+        Spd: SpeedType,
+        TrainAddr: AddressType,
+        SensorAddr: AddressType,
+        SwitchAddr: AddressType,
+        SignalAddr: AddressType,
+        CrossingAddr: AddressType,
+    > Layout<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr, CrossingAddr>
+{
+    /// Checks if this layout could be read by this version of the crate.
+    pub fn check_version(&self) -> Result<(), LayoutError> {
+        if self.version == LAYOUT_VERSION {
+            Ok(())
+        } else {
+            Err(LayoutError::UnsupportedVersion(self.version))
+        }
+    }
+
+    /// Writes this layout as pretty printed JSON.
+    #[cfg(feature = "json")]
+    pub fn to_json(&self) -> Result<String, LayoutError> {
+        serde_json::to_string_pretty(self).map_err(|err| LayoutError::Format(err.to_string()))
+    }
+
+    /// Reads a layout from JSON and checks its version.
+    #[cfg(feature = "json")]
+    pub fn from_json(json: &str) -> Result<Self, LayoutError> {
+        let layout: Self =
+            serde_json::from_str(json).map_err(|err| LayoutError::Format(err.to_string()))?;
+        layout.check_version()?;
+        Ok(layout)
+    }
+
+    /// Writes this layout as pretty printed RON.
+    #[cfg(feature = "ron")]
+    pub fn to_ron(&self) -> Result<String, LayoutError> {
+        ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())
+            .map_err(|err| LayoutError::Format(err.to_string()))
+    }
+
+    /// Reads a layout from RON and checks its version.
+    #[cfg(feature = "ron")]
+    pub fn from_ron(ron: &str) -> Result<Self, LayoutError> {
+        let layout: Self =
+            ron::from_str(ron).map_err(|err| LayoutError::Format(err.to_string()))?;
+        layout.check_version()?;
+        Ok(layout)
+    }
+}
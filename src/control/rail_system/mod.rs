@@ -1,7 +1,11 @@
 /// Components needed for railroad creation
 pub mod components;
+/// Serializable railroad layouts
+pub mod layout;
 /// Railroad containing rail graph and elements
 pub mod railroad;
 /// A test railroad and some tests on it
 // #[cfg(any(test, doctests))]
 pub mod railroad_test;
+/// Snapshots of the live railroad state
+pub mod state;
@@ -1,20 +1,30 @@
+use crate::control::auto_mode::AutoMode;
+use crate::control::clock::Clock;
 use crate::control::messages::Message;
 use crate::control::rail_system::components::{
-    Address, Cross, Node, Position, Rail, Sensor, Signal, SignalType, Speed, Switch, SwitchType,
+    Address, Aspect, Cross, Node, Position, Rail, SLevel, Sensor, Signal, SignalType, Speed, SwDir,
+    Switch, SwitchType,
 };
+use crate::control::rail_system::layout::{
+    CrossingLayout, Layout, LayoutError, SensorLayout, SignalLayout, SwitchLayout, TrainLayout,
+    LAYOUT_VERSION,
+};
+use crate::control::rail_system::state::{RailroadState, StateError, STATE_VERSION};
 use crate::control::train::Train;
 use crate::general::{AddressType, DefaultAddressType, DefaultSpeedType, SpeedType};
-use petgraph::algo::astar;
+use petgraph::algo::{astar, tarjan_scc};
 use petgraph::graph::{DiGraph, EdgeIndex, NodeIndex};
+use petgraph::graphmap::DiGraphMap;
 use petgraph::visit::{Bfs, EdgeRef};
 use petgraph::{Direction, Graph};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::hash::Hash;
 use std::ops::Index;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::broadcast::{channel, Receiver};
 use tokio::sync::{broadcast::Sender, Mutex};
-use tokio::task::spawn_blocking;
+use tokio::task::{spawn_blocking, JoinHandle};
 
 type Road<SensorAddr, SwitchAddr, SignalAddr, CrossingAddr> =
     Mutex<DiGraph<Node<SensorAddr, SwitchAddr, SignalAddr, CrossingAddr>, Vec<Rail>>>;
@@ -24,8 +34,8 @@ type Sensors<Spd, SensorAddr, TrainAddr> =
 type Signals<SignalAddr, TrainAddr, SensorAddr> =
     HashMap<Address<SignalAddr>, Mutex<Signal<SignalAddr, TrainAddr, SensorAddr>>>;
 type Crossings<CrossingAddr> = HashMap<Address<CrossingAddr>, Mutex<Cross<CrossingAddr>>>;
-type Switches<SwitchAddr> =
-    HashMap<Address<SwitchAddr>, (Mutex<Switch<SwitchAddr>>, Vec<NodeIndex>)>;
+type Switches<SwitchAddr, TrainAddr> =
+    HashMap<Address<SwitchAddr>, (Mutex<Switch<SwitchAddr, TrainAddr>>, Vec<NodeIndex>)>;
 type Channel<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr> =
     Sender<Message<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr>>;
 
@@ -43,8 +53,14 @@ pub struct Railroad<
     sensors: Sensors<Spd, SensorAddr, TrainAddr>,
     signals: Signals<SignalAddr, TrainAddr, SensorAddr>,
     crossings: Crossings<CrossingAddr>,
-    switches: Switches<SwitchAddr>,
+    switches: Switches<SwitchAddr, TrainAddr>,
     channel: Channel<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr>,
+    clock: Mutex<Clock>,
+    auto_mode: Mutex<Option<AutoMode<TrainAddr>>>,
+    /// The trains reported waiting in a deadlock, which is not resolved yet
+    deadlocked: Mutex<HashSet<Address<TrainAddr>>>,
+    /// The aspects the signals show, as sent to the connectors last
+    aspects: Mutex<HashMap<Address<SignalAddr>, Aspect<Spd>>>,
 }
 
 impl<
@@ -90,6 +106,25 @@ impl<
         self.trains.get(&address)
     }
 
+    /// Removes a train from the railroad and frees all sensors reserved and switches locked for it.
+    /// Connectors are informed by [Message::TrainRemoved], so they could release the train.
+    pub async fn remove_train(
+        &mut self,
+        address: &Address<TrainAddr>,
+    ) -> Option<Train<Spd, TrainAddr>> {
+        let train = self.trains.remove(address)?.into_inner();
+
+        for (sensor, _nodes) in self.sensors.values() {
+            sensor.lock().await.free(*address, self);
+        }
+        for (switch, _nodes) in self.switches.values() {
+            switch.lock().await.release(*address);
+        }
+        self.send(Message::TrainRemoved(*address));
+
+        Some(train)
+    }
+
     pub fn get_sensor_mutex(
         &self,
         adr: &Address<SensorAddr>,
@@ -157,10 +192,22 @@ impl<
     pub fn get_switch_mutex(
         &self,
         adr: &Address<SwitchAddr>,
-    ) -> Option<&Mutex<Switch<SwitchAddr>>> {
+    ) -> Option<&Mutex<Switch<SwitchAddr, TrainAddr>>> {
         Some(&self.switches.get(adr)?.0)
     }
 
+    /// Releases the lock of the train on the switch,
+    /// together with the flank protection of its route over the switch.
+    pub async fn unlock_switch(&self, adr: &Address<SwitchAddr>, train: Address<TrainAddr>) {
+        for (switch, _nodes) in self.switches.values() {
+            let mut switch = switch.lock().await;
+            if switch.address() == *adr {
+                switch.unlock(train);
+            }
+            switch.unlock_flank(train, *adr);
+        }
+    }
+
     pub fn get_crossing_mutex(
         &self,
         adr: &Address<CrossingAddr>,
@@ -168,19 +215,49 @@ impl<
         self.crossings.get(adr)
     }
 
+    /// Searches the cheapest path from `start` to `destination`.
+    ///
+    /// The path may reverse the train at buffer stops and between the two directions of a
+    /// bidirectional sensor or station. Reversals are expensive, so they are only used, if
+    /// there is no comparable path without. Use [Railroad::is_reversal] to find them.
     pub async fn shortest_path(
         rail: Arc<Self>,
         start: NodeIndex,
         destination: NodeIndex,
     ) -> Option<(usize, Vec<NodeIndex>)> {
-        let graph = { rail.road.lock().await.clone() };
+        Railroad::shortest_path_avoiding(rail, start, destination, &[]).await
+    }
+
+    /// Searches the cheapest path from `start` to `destination` like [Railroad::shortest_path],
+    /// which does not pass any of the `avoided` nodes.
+    pub async fn shortest_path_avoiding(
+        rail: Arc<Self>,
+        start: NodeIndex,
+        destination: NodeIndex,
+        avoided: &[NodeIndex],
+    ) -> Option<(usize, Vec<NodeIndex>)> {
+        let mut graph = { rail.road.lock().await.clone() };
+        graph.retain_edges(|graph, edge| {
+            graph
+                .edge_endpoints(edge)
+                .is_some_and(|(_, target)| !avoided.contains(&target))
+        });
+        let rails = graph.edge_count();
+        for (node, reversed) in reversals(&graph) {
+            graph.add_edge(node, reversed, vec![]);
+        }
+
         spawn_blocking(move || {
             astar(
                 &graph,
                 start,
                 |goal| goal == destination,
                 |cost| {
-                    let rail_cost: usize = cost.weight().iter().map(Rail::manhattan_distance).sum();
+                    let rail_cost: usize = if cost.id().index() < rails {
+                        cost.weight().iter().map(Rail::manhattan_distance).sum()
+                    } else {
+                        REVERSAL_COST
+                    };
                     rail_cost + node_cost(&graph, cost.target(), rail.clone())
                 },
                 |node| estimate_costs(&graph, node, rail.clone(), destination),
@@ -190,6 +267,11 @@ impl<
         .unwrap_or_default()
     }
 
+    /// Checks if a train has to reverse at `node` to drive on to `next`.
+    pub async fn is_reversal(&self, node: NodeIndex, next: NodeIndex) -> bool {
+        reverses(&*self.road.lock().await, node, next)
+    }
+
     /// Returns one possible input signal of a block.
     /// This can be used, if a train is directly placed into one block,
     /// without entering it over a specific signal.
@@ -212,11 +294,516 @@ impl<
         self.channel.subscribe()
     }
 
+    /// Handles a feedback message reported by a connector of the physical railroad.
+    /// The message is sent to the message channel of this railroad and then applied to the
+    /// component it belongs to: Sensor levels are passed to [Sensor::handle_sensor_level]
+    /// and switch acknowledgements to [Switch::ack_switch_state].
+    /// Blocks, whose route switches are all acknowledged then, are granted right away.
+    /// Trains reaching an occupied sensor are moved onto it by [Train::sensor_reached].
+    pub async fn handle_feedback(
+        rail: Arc<Self>,
+        message: Message<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr>,
+    ) {
+        rail.send(message);
+
+        match message {
+            Message::UpdateSensor(adr, level) => {
+                if let Some(sensor) = rail.get_sensor_mutex(&adr) {
+                    sensor
+                        .lock()
+                        .await
+                        .handle_sensor_level(level, rail.clone())
+                        .await;
+                    if level == SLevel::Occupied {
+                        Railroad::sensor_reached(rail.clone(), adr).await;
+                    }
+                }
+            }
+            Message::SwitchAck(adr, dir) => {
+                if let Some(switch) = rail.get_switch_mutex(&adr) {
+                    switch.lock().await.ack_switch_state(dir, &rail).await;
+                }
+                // Blocks waiting for the switch are granted without waiting for the scheduler.
+                for signal in rail.signals.values() {
+                    signal.lock().await.confirm_switches(&rail).await;
+                }
+            }
+            Message::ClockSync(time, rate) => rail.clock.lock().await.sync(time, rate),
+            _ => {}
+        }
+    }
+
+    /// Moves the train, the sensor is reserved for, onto the sensor.
+    /// Sensors reserved for no train are passed to the first train expecting them on its route.
+    async fn sensor_reached(rail: Arc<Self>, adr: Address<SensorAddr>) {
+        let reserved = match rail.get_sensor_mutex(&adr) {
+            Some(sensor) => *sensor.lock().await.train(),
+            None => return,
+        };
+        let trains: Vec<_> = match reserved {
+            Some(train) => vec![train],
+            None => rail.trains.keys().copied().collect(),
+        };
+
+        for train in trains {
+            if let Some(train) = rail.get_train(&train) {
+                if train.lock().await.sensor_reached(adr, rail.clone()).await {
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Returns the fast clock of the layout.
+    pub async fn clock(&self) -> Clock {
+        *self.clock.lock().await
+    }
+
+    /// Replaces the fast clock of the layout and sends its time as [Message::ClockTick].
+    pub async fn set_clock(&self, clock: Clock) {
+        *self.clock.lock().await = clock;
+        self.send(Message::ClockTick(clock.time(), clock.rate()));
+    }
+
+    /// Drives every idle train to a random free station, until the auto mode is stopped.
+    /// Replaces the auto mode running before.
+    pub async fn start_auto_mode(&self, auto_mode: AutoMode<TrainAddr>) {
+        *self.auto_mode.lock().await = Some(auto_mode);
+    }
+
+    /// Stops choosing new destinations. Trains driving to a station still stop there.
+    pub async fn stop_auto_mode(&self) {
+        *self.auto_mode.lock().await = None;
+    }
+
+    pub async fn auto_mode_running(&self) -> bool {
+        self.auto_mode.lock().await.is_some()
+    }
+
+    /// Starts the trains idle for the minimum dwell time driving to free stations,
+    /// while the auto mode runs.
+    async fn run_auto_mode(rail: &Arc<Self>) {
+        let mut auto_mode = rail.auto_mode.lock().await;
+        let auto_mode = match auto_mode.as_mut() {
+            Some(auto_mode) => auto_mode,
+            None => return,
+        };
+        let road = rail.road().await;
+        let sensor = |node: NodeIndex| match road.node_weight(node) {
+            Some(Node::Sensor(adr, _) | Node::Station(adr, _)) => Some(*adr),
+            _ => None,
+        };
+
+        // Stations are taken by the trains standing in or driving to them.
+        let mut taken = HashSet::new();
+        let mut idle = vec![];
+        for (adr, train) in rail.trains.iter() {
+            let train = train.lock().await;
+            let destination = train.route().and_then(|route| route.back());
+            taken.extend(sensor(train.position()));
+            taken.extend(destination.and_then(|(node, _)| sensor(*node)));
+
+            let stands = train.route().is_none() && train.stands();
+            if auto_mode.dwelled(*adr, stands && train.timetable_station().is_none()) {
+                idle.push(*adr);
+            }
+        }
+
+        let mut stations = vec![];
+        for node in road.node_indices() {
+            if let Some(Node::Station(adr, _)) = road.node_weight(node) {
+                if let Some(sensor) = rail.get_sensor_mutex(adr) {
+                    let sensor = sensor.lock().await;
+                    if sensor.train().is_some() || sensor.state().level == SLevel::Occupied {
+                        taken.insert(*adr);
+                    }
+                }
+                stations.push((node, *adr));
+            }
+        }
+
+        for adr in idle {
+            let mut free: Vec<NodeIndex> = stations
+                .iter()
+                .filter(|(node, station)| !taken.contains(station) && auto_mode.allows(adr, *node))
+                .map(|(node, _)| *node)
+                .collect();
+            auto_mode.shuffle(&mut free);
+
+            let mut train = rail.trains[&adr].lock().await;
+            for node in free {
+                if train.trigger_drive_to(node, rail.clone()).await {
+                    taken.extend(sensor(node));
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Starts calling [Train::update] for every train and [Signal::update] for every signal
+    /// each `tick`, so the trains drive their routes on their own.
+    /// Afterwards the [aspects](Railroad::update_aspects) of the signals are updated.
+    /// [Deadlocks](Railroad::deadlocks) between the trains are resolved, where possible.
+    /// Idle trains are driven to new destinations, while the [auto mode](Railroad::start_auto_mode)
+    /// runs.
+    /// Every model minute passed on the running fast clock is sent as [Message::ClockTick].
+    /// The scheduler runs until the returned handle is aborted.
+    pub fn start_scheduler(rail: Arc<Self>, tick: Duration) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(tick);
+            let mut minute = None;
+            loop {
+                interval.tick().await;
+                let clock = rail.clock().await;
+                let time = clock.time();
+                let now = Some(time.as_duration().as_secs() / 60);
+                if clock.is_running() && minute != now {
+                    rail.send(Message::ClockTick(time, clock.rate()));
+                }
+                minute = now;
+
+                for train in rail.trains.values() {
+                    train.lock().await.update(rail.clone()).await;
+                }
+                for signal in rail.signals.values() {
+                    signal.lock().await.update(rail.clone()).await;
+                }
+                rail.update_aspects().await;
+                Railroad::resolve_deadlocks(&rail).await;
+                Railroad::run_auto_mode(&rail).await;
+            }
+        })
+    }
+
+    /// Returns the aspect the signal shows, [Aspect::Stop] for unknown signals.
+    pub async fn aspect(&self, signal: &Address<SignalAddr>) -> Aspect<Spd> {
+        self.aspects
+            .lock()
+            .await
+            .get(signal)
+            .copied()
+            .unwrap_or(Aspect::Stop)
+    }
+
+    /// Derives the aspect of every signal from the block reservations
+    /// and sends the changed ones as [Message::UpdateSignal].
+    ///
+    /// A signal shows [Aspect::Stop], until its block is granted to a train and switched.
+    /// Routes over a curved switch are limited to the lowest maximum speed of their sensors.
+    /// Otherwise the signal announces the aspect of the next signal on the train's route,
+    /// so it shows [Aspect::ExpectStop] in front of a signal showing [Aspect::Stop].
+    pub async fn update_aspects(&self) {
+        let mut mains = HashMap::with_capacity(self.signals.len());
+        for signal in self.signals.values() {
+            let (address, train) = {
+                let signal = signal.lock().await;
+                let address = signal.address();
+                let trains = signal.state().trains;
+                let train = trains.into_iter().find(|train| signal.granted(*train));
+                (address, train)
+            };
+            let route: Option<Vec<NodeIndex>> = match train.and_then(|t| self.get_train(&t)) {
+                Some(train) => train
+                    .lock()
+                    .await
+                    .request_route(address, self)
+                    .await
+                    .map(|route| route.into_iter().copied().collect()),
+                None => None,
+            };
+            let main = match route {
+                Some(route) => self.route_aspect(address, &route).await,
+                None => (Aspect::Stop, None),
+            };
+            mains.insert(address, main);
+        }
+
+        let mut aspects = self.aspects.lock().await;
+        for (address, (aspect, next)) in &mains {
+            let next_stops = next
+                .and_then(|next| mains.get(&next))
+                .is_some_and(|(next, _)| *next == Aspect::Stop);
+            let aspect = match aspect {
+                Aspect::Proceed if next_stops => Aspect::ExpectStop,
+                aspect => *aspect,
+            };
+            if aspects.insert(*address, aspect) != Some(aspect) {
+                self.send(Message::UpdateSignal(*address, aspect));
+            }
+        }
+    }
+
+    /// Returns the main aspect of the signal for the route of the train through its block,
+    /// together with the next signal on the route.
+    async fn route_aspect(
+        &self,
+        signal: Address<SignalAddr>,
+        route: &[NodeIndex],
+    ) -> (Aspect<Spd>, Option<Address<SignalAddr>>) {
+        let mut diverging = false;
+        for step in route.windows(3) {
+            let dir = Switch::<SwitchAddr, TrainAddr>::path_dir(step[1], step[0], step[2], self);
+            diverging |= dir.await == Some(SwDir::Curved);
+        }
+
+        let (sensors, next) = {
+            let road = self.road().await;
+            let sensors: Vec<Address<SensorAddr>> = route
+                .iter()
+                .filter_map(|node| match road.node_weight(*node) {
+                    Some(Node::Sensor(adr, _) | Node::Station(adr, _)) => Some(*adr),
+                    _ => None,
+                })
+                .collect();
+            let next = match route.last().and_then(|node| road.node_weight(*node)) {
+                Some(Node::Signal(adr, ..)) if *adr != signal => Some(*adr),
+                _ => None,
+            };
+            (sensors, next)
+        };
+
+        let mut limit = None;
+        for sensor in sensors {
+            if let Some(sensor) = self.get_sensor_mutex(&sensor) {
+                let speed = sensor.lock().await.max_speed();
+                limit = Some(limit.map_or(speed, |limit: Speed<Spd>| limit.min(speed)));
+            }
+        }
+
+        let aspect = match limit {
+            Some(limit) if diverging => Aspect::ProceedLimited(limit),
+            _ => Aspect::Proceed,
+        };
+        (aspect, next)
+    }
+
+    /// Returns the trains waiting for each other in a circle, so none of them could ever
+    /// drive on. Every deadlock lists its trains with the signal each of them waits at.
+    ///
+    /// A train waits for the trains, the block behind the signal it requested is granted to,
+    /// or whose sensors are reserved or occupied in the block.
+    /// Trains with a granted block ahead still drive on, so they do not wait yet.
+    pub async fn deadlocks(&self) -> Vec<Vec<(Address<TrainAddr>, Address<SignalAddr>)>> {
+        let mut requests = vec![];
+        let mut granted = HashSet::new();
+        for signal in self.signals.values() {
+            let signal = signal.lock().await;
+            let state = signal.state();
+            granted.extend(state.trains.iter().copied());
+            if state.requesters.is_empty() {
+                continue;
+            }
+
+            let mut holders = state.trains;
+            for sensor in signal.block_sensors() {
+                if let Some(sensor) = self.get_sensor_mutex(sensor) {
+                    holders.extend(*sensor.lock().await.train());
+                }
+            }
+            requests.push((state.address, state.requesters, holders));
+        }
+
+        let mut waits = DiGraphMap::<Address<TrainAddr>, Address<SignalAddr>>::new();
+        for (signal, requesters, holders) in requests {
+            for requester in requesters.iter().filter(|train| !granted.contains(*train)) {
+                for holder in holders.iter().filter(|holder| *holder != requester) {
+                    waits.add_edge(*requester, *holder, signal);
+                }
+            }
+        }
+
+        tarjan_scc(&waits)
+            .into_iter()
+            .filter(|trains| trains.len() > 1)
+            .map(|trains| {
+                trains
+                    .iter()
+                    .filter_map(|train| {
+                        waits
+                            .edges(*train)
+                            .find(|(_, holder, _)| trains.contains(holder))
+                            .map(|(_, _, signal)| (*train, *signal))
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Resolves every deadlock by rerouting one of its trains around the signal it waits at.
+    /// The trains of deadlocks without any other route are reported once as
+    /// [Message::TrainDeadlocked].
+    async fn resolve_deadlocks(rail: &Arc<Self>) {
+        let deadlocks = rail.deadlocks().await;
+        let mut unresolved = vec![];
+        for deadlock in deadlocks {
+            let mut rerouted = false;
+            for (train, signal) in &deadlock {
+                if Railroad::reroute(rail, *train, *signal).await {
+                    rerouted = true;
+                    break;
+                }
+            }
+            if !rerouted {
+                unresolved.extend(deadlock);
+            }
+        }
+
+        let mut deadlocked = rail.deadlocked.lock().await;
+        deadlocked.retain(|train| unresolved.iter().any(|(t, _)| t == train));
+        for (train, signal) in unresolved {
+            if deadlocked.insert(train) {
+                rail.send(Message::TrainDeadlocked(train, signal));
+            }
+        }
+    }
+
+    /// Reroutes the train to its destination without passing the signal and withdraws its
+    /// request there. Returns `false`, if there is no such route.
+    async fn reroute(
+        rail: &Arc<Self>,
+        train: Address<TrainAddr>,
+        signal: Address<SignalAddr>,
+    ) -> bool {
+        let (Some(signal), Some(train_mutex)) =
+            (rail.get_signal_mutex(&signal), rail.get_train(&train))
+        else {
+            return false;
+        };
+        // Trains are locked before signals, as in Train::reset_position.
+        // The signal stays locked, so it does not grant the block to the new route.
+        let mut train_guard = train_mutex.lock().await;
+        let mut signal = signal.lock().await;
+        let node = signal.representing_node();
+        if !train_guard.reroute_avoiding(node, rail.clone()).await {
+            return false;
+        }
+        signal.withdraw(train);
+        true
+    }
+
     /// Sends a message to the railroads general message channel
     /// ignoring the possibility for now active subscribers receiving that message.
     pub fn send(&self, msg: Message<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr>) {
         let _ = self.channel.send(msg);
     }
+
+    /// Takes a snapshot of the live state of all sensors, signals, switches and trains.
+    /// Restore it with [Builder::restore_state] after loading the layout again.
+    pub async fn state(&self) -> RailroadState<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr> {
+        let mut sensors = Vec::with_capacity(self.sensors.len());
+        for (sensor, _nodes) in self.sensors.values() {
+            sensors.push(sensor.lock().await.state());
+        }
+        sensors.sort_by_key(|sensor| sensor.address);
+
+        let mut signals = Vec::with_capacity(self.signals.len());
+        for signal in self.signals.values() {
+            signals.push(signal.lock().await.state());
+        }
+        signals.sort_by_key(|signal| signal.address);
+
+        let mut switches = Vec::with_capacity(self.switches.len());
+        for (switch, _nodes) in self.switches.values() {
+            switches.push(switch.lock().await.state());
+        }
+        switches.sort_by_key(|switch| switch.address);
+
+        let mut trains = Vec::with_capacity(self.trains.len());
+        for train in self.trains.values() {
+            trains.push(train.lock().await.state());
+        }
+        trains.sort_by_key(|train| train.address);
+
+        RailroadState {
+            version: STATE_VERSION,
+            sensors,
+            signals,
+            switches,
+            trains,
+        }
+    }
+
+    /// Sends the direction of every switch, the aspect of every signal and the direction, speed
+    /// and active functions of every train, so the connected hardware matches a restored state.
+    pub async fn resync(&self) {
+        for (switch, _nodes) in self.switches.values() {
+            let state = switch.lock().await.state();
+            self.send(Message::Switch(state.address, state.dir));
+        }
+        for (signal, aspect) in self.aspects.lock().await.iter() {
+            self.send(Message::UpdateSignal(*signal, *aspect));
+        }
+        for train in self.trains.values() {
+            let train = train.lock().await;
+            let state = train.state();
+            self.send(Message::TrainDirection(state.address, state.direction));
+            self.send(Message::TrainSpeed(
+                state.address,
+                train.decoder_speed(state.speed),
+            ));
+            for function in state.functions {
+                self.send(Message::TrainFunction(state.address, function, true));
+            }
+        }
+    }
+}
+
+/// The costs of stopping a train and reversing its direction.
+const REVERSAL_COST: usize = 1000;
+
+/// Returns the node of the same sensor or station representing the opposite direction,
+/// if the sensor or station is bidirectional.
+pub(crate) fn reversed_node<
+    SensorAddr: AddressType,
+    SwitchAddr: AddressType,
+    SignalAddr: AddressType,
+    CrossingAddr: AddressType,
+>(
+    graph: &Graph<Node<SensorAddr, SwitchAddr, SignalAddr, CrossingAddr>, Vec<Rail>>,
+    node: NodeIndex,
+) -> Option<NodeIndex> {
+    let (adr, pos) = match graph.node_weight(node)? {
+        Node::Sensor(adr, pos) | Node::Station(adr, pos) => (adr, pos),
+        _ => return None,
+    };
+    graph.node_indices().find(|other| {
+        *other != node
+            && matches!(
+                graph.index(*other),
+                Node::Sensor(other_adr, other_pos) | Node::Station(other_adr, other_pos)
+                    if other_adr == adr && other_pos == pos
+            )
+    })
+}
+
+/// Checks if a train has to reverse at `node` to drive on to `next`.
+pub(crate) fn reverses<
+    SensorAddr: AddressType,
+    SwitchAddr: AddressType,
+    SignalAddr: AddressType,
+    CrossingAddr: AddressType,
+>(
+    graph: &Graph<Node<SensorAddr, SwitchAddr, SignalAddr, CrossingAddr>, Vec<Rail>>,
+    node: NodeIndex,
+    next: NodeIndex,
+) -> bool {
+    matches!(graph.node_weight(node), Some(Node::Buffer(..)))
+        || reversed_node(graph, node) == Some(next)
+}
+
+/// Returns all pairs of nodes a train could reverse between.
+fn reversals<
+    SensorAddr: AddressType,
+    SwitchAddr: AddressType,
+    SignalAddr: AddressType,
+    CrossingAddr: AddressType,
+>(
+    graph: &Graph<Node<SensorAddr, SwitchAddr, SignalAddr, CrossingAddr>, Vec<Rail>>,
+) -> Vec<(NodeIndex, NodeIndex)> {
+    graph
+        .node_indices()
+        .filter_map(|node| Some((node, reversed_node(graph, node)?)))
+        .collect()
 }
 
 fn estimate_costs<
@@ -265,8 +852,9 @@ fn node_cost<
             let sensor_mut = rail.get_sensor_mutex(sensor_adr)?;
             (*(sensor_mut.blocking_lock()).train())?
         };
-        let train_mut = rail.get_train(&train)?;
-        if train_mut.blocking_lock().stands() {
+        // A locked train could be the one planning this route, so it is not waited for.
+        let train_mut = rail.get_train(&train)?.try_lock().ok()?;
+        if train_mut.stands() {
             Some(100)
         } else {
             Some(27)
@@ -276,12 +864,15 @@ fn node_cost<
     match graph.index(node) {
         Node::Sensor(sensor_adr, ..) => train_cost(sensor_adr, rail).unwrap_or(2),
         Node::Station(..) => 500,
+        Node::Buffer(..) => REVERSAL_COST,
         _ => 2,
     }
 }
 
 type BuilderSensors<Spd, SensorAddr, TrainAddr> =
     HashMap<Address<SensorAddr>, (Sensor<Spd, SensorAddr, TrainAddr>, Vec<NodeIndex>)>;
+type BuilderSwitches<SwitchAddr, TrainAddr> =
+    HashMap<Address<SwitchAddr>, (Switch<SwitchAddr, TrainAddr>, Vec<NodeIndex>)>;
 
 pub struct Builder<
     Spd: SpeedType,
@@ -296,7 +887,7 @@ pub struct Builder<
     sensors: BuilderSensors<Spd, SensorAddr, TrainAddr>,
     signals: HashMap<Address<SignalAddr>, Signal<SignalAddr, TrainAddr, SensorAddr>>,
     crossings: HashMap<Address<CrossingAddr>, Cross<CrossingAddr>>,
-    switches: HashMap<Address<SwitchAddr>, (Switch<SwitchAddr>, Vec<NodeIndex>)>,
+    switches: BuilderSwitches<SwitchAddr, TrainAddr>,
     channel: Sender<Message<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr>>,
 }
 
@@ -375,7 +966,7 @@ impl<
             let mut map = HashMap::new();
             for i in input {
                 let old = i.1.lock().await;
-                map.insert(i.0.clone(), Train::new(old.address(), old.position()));
+                map.insert(i.0.clone(), Train::from_state(old.state()));
             }
             map
         }
@@ -573,6 +1164,12 @@ impl<
         Some((node1, node2))
     }
 
+    /// Adds a buffer stop at the end of a track.
+    /// Trains driving into it have to reverse to leave it over its outgoing rails.
+    pub fn add_buffer(&mut self, pos: Position) -> NodeIndex {
+        self.road.add_node(Node::Buffer(pos))
+    }
+
     pub fn add_switches(
         &mut self,
         switches: &[(Address<SwitchAddr>, Position, SwitchType)],
@@ -654,6 +1251,30 @@ impl<
         (index, index_reverse)
     }
 
+    /// Places a train on the given sensor or station node.
+    /// Returns None if the address is already in use or the sensor is blocked by another train.
+    pub fn add_train(
+        &mut self,
+        address: Address<TrainAddr>,
+        position: NodeIndex,
+    ) -> Option<&Train<Spd, TrainAddr>> {
+        if self.trains.contains_key(&address) {
+            return None;
+        }
+
+        let sensor = match self.road.node_weight(position)? {
+            Node::Sensor(adr, ..) | Node::Station(adr, ..) => &mut self.sensors.get_mut(adr)?.0,
+            _ => return None,
+        };
+
+        if !sensor.block(address) {
+            return None;
+        }
+
+        self.trains.insert(address, Train::new(address, position));
+        self.trains.get(&address)
+    }
+
     pub fn remove_train(&mut self, adr: &Address<TrainAddr>) {
         self.trains.remove(adr);
     }
@@ -790,6 +1411,310 @@ impl<
         }
     }
 
+    /// Checks that every node of the rail graph belongs to exactly the component registered for
+    /// it, that no node has more neighbours than allowed and that all trains stand on sensors.
+    pub fn validate(&self) -> Result<(), LayoutError> {
+        for node in self.road.node_indices() {
+            let registered = match self.road.index(node) {
+                Node::Sensor(adr, ..) | Node::Station(adr, ..) => self
+                    .sensors
+                    .get(adr)
+                    .is_some_and(|(_, nodes)| nodes.contains(&node)),
+                Node::Signal(adr, ..) => self
+                    .signals
+                    .get(adr)
+                    .is_some_and(|signal| signal.representing_node() == node),
+                Node::Switch(adr, ..) => self
+                    .switches
+                    .get(adr)
+                    .is_some_and(|(_, nodes)| nodes.contains(&node)),
+                Node::Cross(adr) => self
+                    .crossings
+                    .get(adr)
+                    .is_some_and(|cross| cross.nodes().0 == node || cross.nodes().1 == node),
+                Node::Buffer(..) => true,
+            };
+            if !registered {
+                return Err(LayoutError::UnregisteredNode(node));
+            }
+
+            let ins = self
+                .road
+                .neighbors_directed(node, Direction::Incoming)
+                .count();
+            let out = self
+                .road
+                .neighbors_directed(node, Direction::Outgoing)
+                .count();
+            let max = match self.road.index(node) {
+                Node::Switch(_, _, _, default, _) => {
+                    if default.is_some_and(|default| self.road.node_weight(default).is_none()) {
+                        return Err(LayoutError::InvalidSwitchDefault(node));
+                    }
+                    2
+                }
+                _ => 1,
+            };
+            if ins > max || out > max {
+                return Err(LayoutError::TooManyNeighbours(node));
+            }
+        }
+
+        for (adr, (_, nodes)) in &self.sensors {
+            for node in nodes {
+                self.check_node(*node, |weight| {
+                    matches!(weight, Node::Sensor(a, ..) | Node::Station(a, ..) if a == adr)
+                })?;
+            }
+        }
+        for (adr, signal) in &self.signals {
+            self.check_node(
+                signal.representing_node(),
+                |weight| matches!(weight, Node::Signal(a, ..) if a == adr),
+            )?;
+        }
+        for (adr, (_, nodes)) in &self.switches {
+            for node in nodes {
+                self.check_node(
+                    *node,
+                    |weight| matches!(weight, Node::Switch(a, ..) if a == adr),
+                )?;
+            }
+        }
+        for (adr, cross) in &self.crossings {
+            for node in [cross.nodes().0, cross.nodes().1] {
+                self.check_node(node, |weight| matches!(weight, Node::Cross(a) if a == adr))?;
+            }
+        }
+        for train in self.trains.values() {
+            self.check_node(train.position(), Node::is_driveable)
+                .map_err(|_| LayoutError::InvalidTrainPosition(train.position()))?;
+        }
+
+        Ok(())
+    }
+
+    /// Checks that the `node` exists and its weight `matches` the component referencing it.
+    fn check_node<F>(&self, node: NodeIndex, matches: F) -> Result<(), LayoutError>
+    where
+        F: Fn(&Node<SensorAddr, SwitchAddr, SignalAddr, CrossingAddr>) -> bool,
+    {
+        match self.road.node_weight(node) {
+            None => Err(LayoutError::MissingNode(node)),
+            Some(weight) if !matches(weight) => Err(LayoutError::NodeMismatch(node)),
+            Some(_) => Ok(()),
+        }
+    }
+
+    /// Returns a serializable description of the layout held by this builder.
+    pub fn to_layout(
+        &self,
+    ) -> Layout<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr, CrossingAddr> {
+        let mut sensors: Vec<_> = self
+            .sensors
+            .iter()
+            .map(|(adr, (sensor, nodes))| SensorLayout {
+                address: *adr,
+                max_speed: sensor.max_speed(),
+                nodes: nodes.clone(),
+            })
+            .collect();
+        sensors.sort_by_key(|sensor| sensor.address);
+
+        let mut signals: Vec<_> = self
+            .signals
+            .iter()
+            .map(|(adr, signal)| SignalLayout {
+                address: *adr,
+                signal_type: signal.signal_type(),
+                node: signal.representing_node(),
+            })
+            .collect();
+        signals.sort_by_key(|signal| signal.address);
+
+        let mut crossings: Vec<_> = self
+            .crossings
+            .iter()
+            .map(|(adr, cross)| CrossingLayout {
+                address: *adr,
+                position: cross.pos(),
+                nodes: cross.nodes(),
+            })
+            .collect();
+        crossings.sort_by_key(|cross| cross.address);
+
+        let mut switches: Vec<_> = self
+            .switches
+            .iter()
+            .map(|(adr, (_, nodes))| SwitchLayout {
+                address: *adr,
+                nodes: nodes.clone(),
+            })
+            .collect();
+        switches.sort_by_key(|switch| switch.address);
+
+        let mut trains: Vec<_> = self
+            .trains
+            .iter()
+            .map(|(adr, train)| TrainLayout {
+                address: *adr,
+                position: train.position(),
+                headlights: train.headlights(),
+                momentum: train.momentum(),
+                speed_table: train.speed_table().cloned(),
+                distance_per_speed_step: train.distance_per_speed_step(),
+                length: train.length(),
+            })
+            .collect();
+        trains.sort_by_key(|train| train.address);
+
+        Layout {
+            version: LAYOUT_VERSION,
+            road: self.road.clone(),
+            sensors,
+            signals,
+            crossings,
+            switches,
+            trains,
+        }
+    }
+
+    /// Creates a builder from a loaded layout.
+    /// The layout is validated the same way as by [Builder::try_build].
+    pub fn from_layout(
+        layout: Layout<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr, CrossingAddr>,
+    ) -> Result<Self, LayoutError> {
+        layout.check_version()?;
+
+        let mut builder = Builder::new();
+        builder.road = layout.road;
+
+        for sensor in layout.sensors {
+            let first = sensor.nodes.first().copied().ok_or(LayoutError::NoNodes)?;
+            if builder.sensors.contains_key(&sensor.address) {
+                return Err(LayoutError::DuplicateAddress(first));
+            }
+            builder.sensors.insert(
+                sensor.address,
+                (Sensor::new(sensor.address, sensor.max_speed), sensor.nodes),
+            );
+        }
+        for signal in layout.signals {
+            if builder.signals.contains_key(&signal.address) {
+                return Err(LayoutError::DuplicateAddress(signal.node));
+            }
+            builder.signals.insert(
+                signal.address,
+                Signal::new(signal.address, signal.signal_type, signal.node),
+            );
+        }
+        for cross in layout.crossings {
+            if builder.crossings.contains_key(&cross.address) {
+                return Err(LayoutError::DuplicateAddress(cross.nodes.0));
+            }
+            builder.crossings.insert(
+                cross.address,
+                Cross::new(cross.address, cross.position, cross.nodes),
+            );
+        }
+        for switch in layout.switches {
+            let first = switch.nodes.first().copied().ok_or(LayoutError::NoNodes)?;
+            if builder.switches.contains_key(&switch.address) {
+                return Err(LayoutError::DuplicateAddress(first));
+            }
+            builder
+                .switches
+                .insert(switch.address, (Switch::new(switch.address), switch.nodes));
+        }
+
+        builder.validate()?;
+
+        for train in layout.trains {
+            if builder.add_train(train.address, train.position).is_none() {
+                return Err(LayoutError::InvalidTrainPosition(train.position));
+            }
+            if let Some(added) = builder.trains.get_mut(&train.address) {
+                added.set_headlights(train.headlights);
+                added.set_momentum(train.momentum);
+                added.set_speed_table(train.speed_table);
+                added.set_distance_per_speed_step(train.distance_per_speed_step);
+                added.set_length(train.length);
+            }
+        }
+
+        Ok(builder)
+    }
+
+    /// Applies a state taken by [Railroad::state] to the components of this builder.
+    /// Trains of the state replace trains with the same address.
+    ///
+    /// Nothing is changed if the state references unknown components or places a train on a
+    /// node, that is no sensor or station.
+    pub fn restore_state(
+        &mut self,
+        state: RailroadState<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr>,
+    ) -> Result<(), StateError> {
+        state.check_version()?;
+
+        if state
+            .sensors
+            .iter()
+            .any(|sensor| !self.sensors.contains_key(&sensor.address))
+            || state
+                .signals
+                .iter()
+                .any(|signal| !self.signals.contains_key(&signal.address))
+            || state
+                .switches
+                .iter()
+                .any(|switch| !self.switches.contains_key(&switch.address))
+        {
+            return Err(StateError::UnknownComponent);
+        }
+
+        for train in &state.trains {
+            if !matches!(
+                self.road.node_weight(train.position),
+                Some(Node::Sensor(..) | Node::Station(..))
+            ) {
+                return Err(StateError::InvalidTrainPosition(train.position));
+            }
+        }
+
+        for sensor in &state.sensors {
+            if let Some((old, _nodes)) = self.sensors.get_mut(&sensor.address) {
+                old.restore_state(sensor);
+            }
+        }
+        for signal in &state.signals {
+            if let Some(old) = self.signals.get_mut(&signal.address) {
+                old.restore_state(signal);
+            }
+        }
+        for switch in &state.switches {
+            if let Some((old, _nodes)) = self.switches.get_mut(&switch.address) {
+                old.restore_state(switch);
+            }
+        }
+        for train in state.trains {
+            self.trains.insert(train.address, Train::from_state(train));
+        }
+
+        Ok(())
+    }
+
+    /// Validates this builder by calling [Builder::validate] and builds the railroad if the
+    /// layout is valid.
+    pub async fn try_build(
+        self,
+    ) -> Result<
+        Railroad<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr, CrossingAddr>,
+        LayoutError,
+    > {
+        self.validate()?;
+        Ok(self.build().await)
+    }
+
     /// Builds a railroad out of this reader.
     pub async fn build(
         mut self,
@@ -837,6 +1762,10 @@ impl<
             crossings,
             switches,
             channel: self.channel,
+            clock: Mutex::new(Clock::default()),
+            auto_mode: Mutex::new(None),
+            deadlocked: Mutex::new(HashSet::new()),
+            aspects: Mutex::new(HashMap::new()),
         };
 
         for signal in railroad.signals.values() {
@@ -1,9 +1,15 @@
+use crate::control::connectors::simulation_connector::{SimulationConfig, SimulationConnector};
+use crate::control::connectors::RailroadConnector;
 use crate::control::rail_system::components::{
     Address, Coord, Direction, Position, Rail, SignalType, Speed, SwitchType,
 };
 use crate::control::rail_system::railroad::{Builder, Railroad};
+use crate::general::{DefaultAddressType, DefaultSpeedType};
 use petgraph::graph::NodeIndex;
 use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinHandle;
 
 /// Sensors:
 ///
@@ -1177,6 +1183,340 @@ pub async fn create_test_railroad() -> (
     )
 }
 
+/// The interval the scheduler started by [start_simulation] runs in.
+pub const TICK: Duration = Duration::from_millis(50);
+
+/// Simulates the railroad and drives its trains by the scheduler.
+/// The returned tasks run until they are aborted.
+pub async fn start_simulation(railroad: &Arc<Railroad>) -> [JoinHandle<()>; 2] {
+    let mut connector = SimulationConnector::new(railroad.subscribe(), SimulationConfig::default());
+    connector.register_railroad(railroad.clone()).await;
+    [
+        tokio::spawn(async move { connector.start_connectors().await }),
+        Railroad::start_scheduler(railroad.clone(), TICK),
+    ]
+}
+
+/// Builder of the small layouts below.
+type TestBuilder = Builder<
+    DefaultSpeedType,
+    DefaultAddressType,
+    DefaultAddressType,
+    DefaultAddressType,
+    DefaultAddressType,
+    DefaultAddressType,
+>;
+
+/// Returns the position at `x` on track `y` of the small layouts below, facing east.
+fn position(x: usize, y: usize) -> Position {
+    Position::new(Coord(x, y, 0), Direction::East)
+}
+
+/// Returns the position of the curved branch of a switch at `x` on track `0`.
+fn branch(x: usize) -> Position {
+    Position::new(Coord(x, 4, 0), Direction::South)
+}
+
+/// Returns a connection of `length` rails starting at `x` on track `y`.
+fn rail(x: usize, y: usize, length: usize) -> Vec<Rail> {
+    vec![Rail::new(position(x, y), length, Direction::West)]
+}
+
+/// Connects the nodes one after another, starting at `x` on track `y`.
+/// Every connection is 4 rail units long, as are all connections of the layouts below,
+/// unless stated otherwise.
+fn line(builder: &mut TestBuilder, (x, y): (usize, usize), nodes: &[NodeIndex]) {
+    for (i, step) in nodes.windows(2).enumerate() {
+        builder
+            .connect(step[0], step[1], rail(x + 4 * i, y, 3))
+            .unwrap();
+    }
+}
+
+/// Connects `from` over switch 1 at `x` on track `0` to the nodes of its straight branch,
+/// which it leads to by default, and of its curved branch.
+fn fork(
+    builder: &mut TestBuilder,
+    from: NodeIndex,
+    x: usize,
+    straight: NodeIndex,
+    curved: NodeIndex,
+) {
+    let switch = builder.add_switch(Address::new(1), position(x, 0), SwitchType::StraightRight90);
+    line(builder, (x - 4, 0), &[from, switch, straight]);
+    line(builder, (x, 0), &[switch, curved]);
+    builder.set_switch_default_dir(switch, straight);
+}
+
+/// Three sensors in a row with a signal in front of the last one.
+///
+/// ```text
+/// 1 ---> 2 ---> signal 1 ---> 3
+/// ```
+pub async fn create_line_railroad() -> (Arc<Railroad>, [NodeIndex; 4]) {
+    let mut builder = Builder::new();
+    let first = builder.add_sensor(Address::new(1), Speed::Drive(128), position(0, 0));
+    let second = builder.add_sensor(Address::new(2), Speed::Drive(128), position(4, 0));
+    let signal = builder
+        .add_signal(Address::new(1), SignalType::Block, position(8, 0))
+        .unwrap();
+    let third = builder.add_sensor(Address::new(3), Speed::Drive(128), position(12, 0));
+    line(&mut builder, (0, 0), &[first, second, signal, third]);
+    builder.add_train(Address::new(1), first).unwrap();
+
+    (
+        Arc::new(builder.build().await),
+        [first, second, signal, third],
+    )
+}
+
+/// A sensor leading over a switch to two other sensors.
+///
+/// ```text
+/// 1 ---> switch 1 -+-> 2 (default)
+///                  +-> 3
+/// ```
+pub async fn create_switch_railroad() -> (Arc<Railroad>, [NodeIndex; 3]) {
+    let mut builder = Builder::new();
+    let first = builder.add_sensor(Address::new(1), Speed::Drive(128), position(0, 0));
+    let straight = builder.add_sensor(Address::new(2), Speed::Drive(128), position(8, 0));
+    let curved = builder.add_sensor(Address::new(3), Speed::Drive(128), branch(4));
+    fork(&mut builder, first, 4, straight, curved);
+    builder.add_train(Address::new(1), first).unwrap();
+
+    (Arc::new(builder.build().await), [first, straight, curved])
+}
+
+/// A terminus of two bidirectional sensors ending in a buffer stop.
+///
+/// ```text
+/// 1 <--> 2 <--> buffer
+/// ```
+pub async fn create_terminus_railroad() -> (Arc<Railroad>, [(NodeIndex, NodeIndex); 2], NodeIndex) {
+    let mut builder = Builder::new();
+    let first =
+        builder.add_bidirectional_sensor(Address::new(1), Speed::Drive(128), position(0, 0));
+    let second =
+        builder.add_bidirectional_sensor(Address::new(2), Speed::Drive(128), position(4, 0));
+    let buffer = builder.add_buffer(position(8, 0));
+
+    builder
+        .connect_bidirectional(first, second, rail(0, 0, 3))
+        .unwrap();
+    line(&mut builder, (4, 0), &[second.0, buffer]);
+    line(&mut builder, (4, 0), &[buffer, second.1]);
+    builder.add_train(Address::new(1), first.0).unwrap();
+
+    (Arc::new(builder.build().await), [first, second], buffer)
+}
+
+/// Three stations in a loop, the train standing in the first one.
+///
+/// ```text
+/// +-> 1 ---> 2 ---> 3 --+
+/// +---------------------+
+/// ```
+pub async fn create_station_loop_railroad() -> (Arc<Railroad>, [NodeIndex; 3]) {
+    let mut builder = Builder::new();
+    let stations = [0, 4, 8].map(|x| {
+        builder.add_station(
+            Address::new(x as u16 / 4 + 1),
+            Speed::Drive(128),
+            position(x, 0),
+        )
+    });
+    line(
+        &mut builder,
+        (0, 0),
+        &[stations[0], stations[1], stations[2], stations[0]],
+    );
+    builder.add_train(Address::new(1), stations[0]).unwrap();
+
+    (Arc::new(builder.build().await), stations)
+}
+
+/// A sensor leading over a switch to two stations, with the second train in the first station.
+///
+/// ```text
+/// 1 ---> switch 1 -+-> station 2 (default)
+///                  +-> station 3
+/// ```
+pub async fn create_station_fork_railroad() -> (Arc<Railroad>, [NodeIndex; 3]) {
+    let mut builder = Builder::new();
+    let first = builder.add_sensor(Address::new(1), Speed::Drive(128), position(0, 0));
+    let straight = builder.add_station(Address::new(2), Speed::Drive(128), position(8, 0));
+    let curved = builder.add_station(Address::new(3), Speed::Drive(128), branch(4));
+    fork(&mut builder, first, 4, straight, curved);
+    builder.add_train(Address::new(1), first).unwrap();
+    builder.add_train(Address::new(2), straight).unwrap();
+
+    (Arc::new(builder.build().await), [first, straight, curved])
+}
+
+/// A sensor leading over a signal and a switch to two other sensors.
+/// Trains pass the curved sensor with half speed.
+///
+/// ```text
+/// 1 ---> signal 1 ---> switch 1 -+-> 2 (default)
+///                                +-> 3
+/// ```
+pub async fn create_signal_switch_railroad() -> (Arc<Railroad>, [NodeIndex; 4]) {
+    let mut builder = Builder::new();
+    let first = builder.add_sensor(Address::new(1), Speed::Drive(128), position(0, 0));
+    let signal = builder
+        .add_signal(Address::new(1), SignalType::Block, position(4, 0))
+        .unwrap();
+    let straight = builder.add_sensor(Address::new(2), Speed::Drive(128), position(12, 0));
+    let curved = builder.add_sensor(Address::new(3), Speed::Drive(64), branch(8));
+    line(&mut builder, (0, 0), &[first, signal]);
+    fork(&mut builder, signal, 8, straight, curved);
+    builder.add_train(Address::new(1), first).unwrap();
+
+    (
+        Arc::new(builder.build().await),
+        [first, signal, straight, curved],
+    )
+}
+
+/// Three sensors in a row with a signal in front of the second and the third one.
+/// Train 1 stands on the first sensor, train 2 on the third one.
+///
+/// ```text
+/// 1 ---> signal 1 ---> 2 ---> signal 2 ---> 3
+/// ```
+pub async fn create_two_signals_railroad() -> (Arc<Railroad>, [NodeIndex; 3]) {
+    let mut builder = Builder::new();
+    let first = builder.add_sensor(Address::new(1), Speed::Drive(128), position(0, 0));
+    let entry = builder
+        .add_signal(Address::new(1), SignalType::Block, position(4, 0))
+        .unwrap();
+    let second = builder.add_sensor(Address::new(2), Speed::Drive(128), position(8, 0));
+    let exit = builder
+        .add_signal(Address::new(2), SignalType::Block, position(12, 0))
+        .unwrap();
+    let third = builder.add_sensor(Address::new(3), Speed::Drive(128), position(16, 0));
+    line(&mut builder, (0, 0), &[first, entry, second, exit, third]);
+    builder.add_train(Address::new(1), first).unwrap();
+    builder.add_train(Address::new(2), third).unwrap();
+
+    (Arc::new(builder.build().await), [first, second, third])
+}
+
+/// Two parallel tracks connected by a crossover from track A to track B,
+/// with a signal in front of the merging switch on track B.
+///
+/// ```text
+/// A1 ---> switch 1 ---------------------> A2
+///                  \
+/// B1 ---> signal 1 ---> switch 2 --------> B2
+/// ```
+pub async fn create_crossover_railroad() -> (Arc<Railroad>, [NodeIndex; 4]) {
+    let mut builder = Builder::new();
+    let a1 = builder.add_sensor(Address::new(1), Speed::Drive(128), position(0, 0));
+    let diverging =
+        builder.add_switch(Address::new(1), position(4, 0), SwitchType::StraightRight90);
+    let a2 = builder.add_sensor(Address::new(2), Speed::Drive(128), position(12, 0));
+    let b1 = builder.add_sensor(Address::new(3), Speed::Drive(128), position(0, 4));
+    let signal = builder
+        .add_signal(Address::new(1), SignalType::Block, position(4, 4))
+        .unwrap();
+    let merging = builder.add_switch(Address::new(2), position(8, 4), SwitchType::StraightLeft90);
+    let b2 = builder.add_sensor(Address::new(4), Speed::Drive(128), position(12, 4));
+
+    line(&mut builder, (0, 0), &[a1, diverging, a2]);
+    line(&mut builder, (4, 0), &[diverging, merging]);
+    line(&mut builder, (0, 4), &[b1, signal, merging, b2]);
+    builder.set_switch_default_dir(diverging, a2);
+    builder.set_switch_default_dir(merging, signal);
+    builder.add_train(Address::new(1), b1).unwrap();
+
+    (Arc::new(builder.build().await), [a1, a2, b1, b2])
+}
+
+/// A circle of two blocks, with train 1 on the first and train 2 on the second sensor.
+///
+/// ```text
+/// +-> 1 ---> signal 2 ---> 2 ---> signal 1 -+
+/// +-----------------------------------------+
+/// ```
+pub async fn create_ring_railroad() -> (Arc<Railroad>, [NodeIndex; 2]) {
+    let mut builder = Builder::new();
+    let first = builder.add_sensor(Address::new(1), Speed::Drive(128), position(0, 0));
+    let second_signal = builder
+        .add_signal(Address::new(2), SignalType::Block, position(4, 0))
+        .unwrap();
+    let second = builder.add_sensor(Address::new(2), Speed::Drive(128), position(8, 0));
+    let first_signal = builder
+        .add_signal(Address::new(1), SignalType::Block, position(12, 0))
+        .unwrap();
+    line(
+        &mut builder,
+        (0, 0),
+        &[first, second_signal, second, first_signal, first],
+    );
+    builder.add_train(Address::new(1), first).unwrap();
+    builder.add_train(Address::new(2), second).unwrap();
+
+    (Arc::new(builder.build().await), [first, second])
+}
+
+/// A circle with a passing loop, whose second track 3 is a long detour of 200 rail units.
+/// Train 1 stands on sensor 1, train 2 on sensor 4 and train 3 on sensor 2.
+///
+/// ```text
+/// +-> 1 ---> switch 1 ---> signal 2 ---> 2 ---> signal 4 ---> switch 2 ---> 4 ---> signal 1 -+
+/// |                  \                                      /                               |
+/// |                   +--> signal 3 ---> 3 ---> signal 5 -+                                 |
+/// +-------------------------------------------------------------------------------------------+
+/// ```
+pub async fn create_passing_ring_railroad() -> (Arc<Railroad>, [NodeIndex; 4]) {
+    let mut builder = Builder::new();
+    let first = builder.add_sensor(Address::new(1), Speed::Drive(128), position(0, 0));
+    let diverging =
+        builder.add_switch(Address::new(1), position(4, 0), SwitchType::StraightRight90);
+    let signals = builder.add_signals(&[
+        (Address::new(1), SignalType::Block, position(32, 0)),
+        (Address::new(2), SignalType::Block, position(8, 0)),
+        (Address::new(3), SignalType::Block, position(8, 4)),
+        (Address::new(4), SignalType::Block, position(16, 0)),
+        (Address::new(5), SignalType::Block, position(16, 4)),
+    ]);
+    let signal = |adr| signals[&Address::new(adr)];
+    let second = builder.add_sensor(Address::new(2), Speed::Drive(128), position(12, 0));
+    let third = builder.add_sensor(Address::new(3), Speed::Drive(128), position(12, 4));
+    let merging = builder.add_switch(Address::new(2), position(20, 0), SwitchType::StraightLeft90);
+    let fourth = builder.add_sensor(Address::new(4), Speed::Drive(128), position(24, 0));
+
+    line(
+        &mut builder,
+        (0, 0),
+        &[
+            first,
+            diverging,
+            signal(2),
+            second,
+            signal(4),
+            merging,
+            fourth,
+        ],
+    );
+    line(&mut builder, (24, 0), &[fourth, signal(1)]);
+    line(&mut builder, (32, 0), &[signal(1), first]);
+    line(&mut builder, (4, 4), &[diverging, signal(3)]);
+    builder.connect(signal(3), third, rail(8, 4, 200)).unwrap();
+    line(&mut builder, (12, 4), &[third, signal(5), merging]);
+    builder.set_switch_default_dir(diverging, signal(2));
+    builder.set_switch_default_dir(merging, signal(4));
+    builder.add_train(Address::new(1), first).unwrap();
+    builder.add_train(Address::new(2), fourth).unwrap();
+    builder.add_train(Address::new(3), second).unwrap();
+
+    (
+        Arc::new(builder.build().await),
+        [first, second, third, fourth],
+    )
+}
+
 #[tokio::test]
 pub async fn test_road() {
     use std::sync::Arc;
@@ -1226,3 +1566,338 @@ pub async fn test_road() {
 
     assert_eq!(calculated_road, expected_road);
 }
+
+#[tokio::test]
+pub async fn test_block_sensors() {
+    let (r, ..) = create_test_railroad().await;
+
+    // Blocks reach over switches and every sensor up to the next signals.
+    let signal = r.get_signal_mutex(&Address::new(82)).unwrap().lock().await;
+    assert_eq!(signal.block_sensors(), [Address::new(0)]);
+    let signal = r.get_signal_mutex(&Address::new(112)).unwrap().lock().await;
+    let mut sensors = signal.block_sensors().to_vec();
+    sensors.sort();
+    assert_eq!(sensors, [Address::new(16), Address::new(18)]);
+}
+
+#[tokio::test]
+pub async fn test_layout_round_trip() {
+    use crate::control::momentum::MomentumProfile;
+    use crate::control::rail_system::layout::LayoutError;
+    use std::sync::Arc;
+
+    let (r, _switches, _bi_dir_switches, sensors, _bi_dir_sensors, _signals) =
+        create_test_railroad().await;
+
+    let mut builder = Builder::from_railroad(&r).await;
+    assert!(builder.add_train(Address::new(1), sensors[2].0).is_some());
+    assert!(builder.add_train(Address::new(2), sensors[2].0).is_none());
+    let mut layout = builder.to_layout();
+    layout.trains[0].momentum = MomentumProfile::linear(100);
+    layout.trains[0].length = Some(12.0);
+
+    let loaded = Builder::from_layout(layout.clone())
+        .unwrap()
+        .try_build()
+        .await
+        .unwrap();
+    {
+        let train = loaded.get_train(&Address::new(1)).unwrap().lock().await;
+        assert_eq!(train.momentum(), MomentumProfile::linear(100));
+        assert_eq!(train.length(), Some(12.0));
+    }
+    assert_eq!(
+        Builder::from_railroad(&loaded).await.to_layout().trains,
+        layout.trains
+    );
+
+    let expected = Railroad::shortest_path(Arc::new(r), sensors[1].0, sensors[7].0).await;
+    let calculated = Railroad::shortest_path(Arc::new(loaded), sensors[1].0, sensors[7].0).await;
+    assert_eq!(calculated, expected);
+
+    let mut outdated = layout.clone();
+    outdated.version += 1;
+    assert!(Builder::from_layout(outdated).is_err());
+
+    let mut broken = layout.clone();
+    broken.sensors.pop();
+    assert!(Builder::from_layout(broken).is_err());
+
+    let mut nodeless = layout.clone();
+    nodeless.sensors.push(nodeless.sensors[0].clone());
+    nodeless.sensors.last_mut().unwrap().nodes.clear();
+    assert_eq!(
+        Builder::from_layout(nodeless).err(),
+        Some(LayoutError::NoNodes)
+    );
+
+    let mut nodeless = layout;
+    nodeless.switches[0].nodes.clear();
+    assert_eq!(
+        Builder::from_layout(nodeless).err(),
+        Some(LayoutError::NoNodes)
+    );
+}
+
+#[tokio::test]
+pub async fn test_state_round_trip() {
+    use crate::control::rail_system::state::StateError;
+    use std::collections::VecDeque;
+
+    let (r, switches, _bi_dir_switches, sensors, _bi_dir_sensors, _signals) =
+        create_test_railroad().await;
+
+    let mut builder = Builder::from_railroad(&r).await;
+    builder.add_train(Address::new(1), sensors[2].0);
+    let layout = builder.to_layout();
+    let running = builder.build().await;
+
+    let mut state = running.state().await;
+    assert_eq!(state.trains.len(), 1);
+    assert_eq!(state.trains[0].position, sensors[2].0);
+
+    state.switches[0].dir = !state.switches[0].dir;
+    state.switches[0].updated = true;
+    state.switches[0].locked = Some(Address::new(1));
+    state.switches[1].flank_locks = vec![(Address::new(1), state.switches[0].address)];
+    state.signals[0].requesters = VecDeque::from([Address::new(1)]);
+    state.signals[0].routes = vec![(Address::new(1), vec![sensors[1].0])];
+    state.signals[1].trains = vec![Address::new(1)];
+    state.signals[1].switching = vec![(Address::new(1), vec![switches[0].0])];
+    state.trains[0].speed = Speed::Drive(42);
+    state.trains[0].route = Some(VecDeque::from([(sensors[1].0, true)]));
+
+    let mut restored = Builder::from_layout(layout.clone()).unwrap();
+    restored.restore_state(state.clone()).unwrap();
+    let restored = restored.build().await;
+    assert_eq!(restored.state().await, state);
+    // The block is granted, after the switches of the route acknowledged it.
+    let signal = restored
+        .get_signal_mutex(&state.signals[1].address)
+        .unwrap();
+    assert!(!signal.lock().await.granted(Address::new(1)));
+
+    let copied = Builder::from_railroad(&restored).await.build().await;
+    assert_eq!(copied.state().await, state);
+
+    let mut unknown = state.clone();
+    unknown.sensors[0].address = Address::new(1000);
+    assert_eq!(
+        Builder::from_layout(layout.clone())
+            .unwrap()
+            .restore_state(unknown),
+        Err(StateError::UnknownComponent)
+    );
+
+    let mut misplaced = state;
+    misplaced.trains[0].position = switches[0].0;
+    assert_eq!(
+        Builder::from_layout(layout)
+            .unwrap()
+            .restore_state(misplaced),
+        Err(StateError::InvalidTrainPosition(switches[0].0))
+    );
+}
+
+#[tokio::test]
+pub async fn test_remove_train() {
+    use crate::control::messages::Message;
+
+    let (mut r, _switches, _bi_dir_switches, sensors, _bi_dir_sensors, _signals) =
+        create_test_railroad().await;
+    let mut messages = r.subscribe();
+
+    assert!(r
+        .create_train(Address::new(1), sensors[2].0)
+        .await
+        .is_some());
+    let sensor = r.get_sensor_mutex(&sensors[2].1).unwrap();
+    assert_eq!(sensor.lock().await.train(), &Some(Address::new(1)));
+
+    assert!(r.remove_train(&Address::new(1)).await.is_some());
+    assert!(r.get_train(&Address::new(1)).is_none());
+    let sensor = r.get_sensor_mutex(&sensors[2].1).unwrap();
+    assert_eq!(sensor.lock().await.train(), &None);
+    assert_eq!(
+        messages.try_recv().unwrap(),
+        Message::TrainRemoved(Address::new(1))
+    );
+
+    assert!(r.remove_train(&Address::new(1)).await.is_none());
+}
+
+#[tokio::test]
+pub async fn test_switch_acknowledged() {
+    use crate::control::messages::Message;
+    use crate::control::rail_system::components::SwDir;
+
+    let (r, switches, _bi_dir_switches, _sensors, _bi_dir_sensors, _signals) =
+        create_test_railroad().await;
+    let mut messages = r.subscribe();
+    let mut switch = r.get_switch_mutex(&switches[0].1).unwrap().lock().await;
+    let dir = match switch.state().dir {
+        SwDir::Straight => SwDir::Curved,
+        SwDir::Curved => SwDir::Straight,
+    };
+
+    // The requested direction is kept, so its acknowledgement marks the switch as updated.
+    switch.switch(dir, &r).await;
+    assert_eq!(switch.state().dir, dir);
+    assert!(!switch.state().updated);
+    assert_eq!(
+        messages.try_recv().unwrap(),
+        Message::Switch(switches[0].1, dir)
+    );
+
+    switch.ack_switch_state(dir, &r).await;
+    assert!(switch.state().updated);
+    assert!(messages.try_recv().is_err());
+}
+
+#[tokio::test]
+pub async fn test_locked_switch_refuses() {
+    use crate::control::messages::Message;
+    use crate::control::rail_system::components::{Node, SwDir};
+
+    let (r, [first, straight, curved]) = create_switch_railroad().await;
+    let node = {
+        let road = r.road().await;
+        road.node_indices()
+            .find(|node| matches!(road.node_weight(*node), Some(Node::Switch(..))))
+            .unwrap()
+    };
+    let mut messages = r.subscribe();
+    let mut switch = r.get_switch_mutex(&Address::new(1)).unwrap().lock().await;
+    assert!(switch.lock(Address::new(1), SwDir::Straight));
+
+    // Only the train locking the switch moves it.
+    assert!(!switch.switch(SwDir::Curved, &r).await);
+    assert!(
+        !switch
+            .request_path(Address::new(2), node, first, curved, &r)
+            .await
+    );
+    assert!(messages.try_recv().is_err());
+
+    assert!(
+        switch
+            .request_path(Address::new(1), node, first, straight, &r)
+            .await
+    );
+    assert_eq!(
+        messages.try_recv().unwrap(),
+        Message::Switch(Address::new(1), SwDir::Straight)
+    );
+}
+
+#[tokio::test]
+pub async fn test_train_functions() {
+    use crate::control::messages::Message;
+    use crate::control::rail_system::components::TrainDirection;
+    use crate::control::train::{Headlights, MAX_FUNCTION};
+
+    let (mut r, _switches, _bi_dir_switches, sensors, _bi_dir_sensors, _signals) =
+        create_test_railroad().await;
+    r.create_train(Address::new(1), sensors[2].0).await.unwrap();
+    let mut messages = r.subscribe();
+    let mut train = r.get_train(&Address::new(1)).unwrap().lock().await;
+
+    assert!(train.set_function(2, true, &r));
+    assert!(train.function(2));
+    assert!(!train.set_function(MAX_FUNCTION + 1, true, &r));
+    assert_eq!(
+        messages.try_recv().unwrap(),
+        Message::TrainFunction(Address::new(1), 2, true)
+    );
+    assert!(messages.try_recv().is_err());
+
+    // Separate headlights for every direction are switched, when the train reverses.
+    train.set_headlights(Headlights::Directional {
+        forward: 0,
+        backward: 1,
+    });
+    train.set_lights(true, &r);
+    assert!(train.set_direction(TrainDirection::Backward, &r));
+    assert!(train.lights());
+    assert_eq!((train.function(0), train.function(1)), (false, true));
+    let sent: Vec<_> = std::iter::from_fn(|| messages.try_recv().ok()).collect();
+    assert_eq!(
+        sent,
+        vec![
+            Message::TrainFunction(Address::new(1), 0, true),
+            Message::TrainDirection(Address::new(1), TrainDirection::Backward),
+            Message::TrainFunction(Address::new(1), 0, false),
+            Message::TrainFunction(Address::new(1), 1, true),
+        ]
+    );
+
+    let state = train.state();
+    assert_eq!(state.functions.into_iter().collect::<Vec<_>>(), vec![1, 2]);
+}
+
+#[tokio::test(start_paused = true)]
+pub async fn test_long_train_occupancy() {
+    use crate::control::messages::Message;
+    use crate::control::rail_system::components::{SLevel, Status};
+    use std::time::Duration;
+
+    let (r, [_first, second, _signal, third]) = create_line_railroad().await;
+    let sensor = |adr| r.get_sensor_mutex(&Address::new(adr)).unwrap();
+    let train = r.get_train(&Address::new(1)).unwrap();
+    {
+        let mut train = train.lock().await;
+        train.set_length(Some(10.0));
+        assert!(train.trigger_drive_to(third, r.clone()).await);
+        train.sensor_entered(second, r.clone()).await;
+    }
+
+    // The head left the first sensor 4 units ago, so the tail is still on it.
+    Railroad::handle_feedback(
+        r.clone(),
+        Message::UpdateSensor(Address::new(1), SLevel::Free),
+    )
+    .await;
+    tokio::time::sleep(Duration::from_secs(3)).await;
+    assert_eq!(sensor(1).lock().await.train(), &Some(Address::new(1)));
+    assert_eq!(sensor(2).lock().await.train(), &Some(Address::new(1)));
+
+    train.lock().await.sensor_entered(third, r.clone()).await;
+    assert_eq!(sensor(1).lock().await.train(), &None);
+    assert_eq!(sensor(1).lock().await.status(), Status::Free);
+    assert_eq!(sensor(2).lock().await.train(), &Some(Address::new(1)));
+    assert_eq!(
+        train.lock().await.occupied().collect::<Vec<_>>(),
+        vec![second, third]
+    );
+
+    // Without a length the train relies on the sensors reporting to be free.
+    train.lock().await.set_length(None);
+    Railroad::handle_feedback(
+        r.clone(),
+        Message::UpdateSensor(Address::new(2), SLevel::Free),
+    )
+    .await;
+    tokio::time::sleep(Duration::from_secs(3)).await;
+    assert_eq!(sensor(2).lock().await.train(), &None);
+    assert_eq!(
+        train.lock().await.occupied().collect::<Vec<_>>(),
+        vec![third]
+    );
+}
+
+#[cfg(all(feature = "json", feature = "ron"))]
+#[tokio::test]
+pub async fn test_layout_formats() {
+    use crate::control::rail_system::layout::Layout;
+
+    let (r, ..) = create_test_railroad().await;
+    let layout = Builder::from_railroad(&r).await.to_layout();
+
+    let json: Layout<u8, u16, u16, u16, u16, u16> =
+        Layout::from_json(&layout.to_json().unwrap()).unwrap();
+    assert!(Builder::from_layout(json).is_ok());
+
+    let ron: Layout<u8, u16, u16, u16, u16, u16> =
+        Layout::from_ron(&layout.to_ron().unwrap()).unwrap();
+    assert!(Builder::from_layout(ron).is_ok());
+}
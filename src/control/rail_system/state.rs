@@ -0,0 +1,175 @@
+use crate::control::momentum::{MomentumProfile, SpeedTable};
+use crate::control::rail_system::components::{
+    Address, SLevel, Speed, Status, SwDir, TrainDirection,
+};
+use crate::control::train::Headlights;
+use crate::general::{AddressType, SpeedType};
+use petgraph::graph::NodeIndex;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, VecDeque};
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+
+/// The version of the state format written by this crate.
+/// States of other versions are rejected on loading.
+pub const STATE_VERSION: u32 = 3;
+
+/// A serializable snapshot of the live state of a running railroad.
+///
+/// Take one by calling [Railroad::state](crate::control::rail_system::railroad::Railroad::state)
+/// and apply it to a freshly loaded layout with
+/// [Builder::restore_state](crate::control::rail_system::railroad::Builder::restore_state).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(bound = "")]
+pub struct RailroadState<
+    Spd: SpeedType,
+    TrainAddr: AddressType,
+    SensorAddr: AddressType,
+    SwitchAddr: AddressType,
+    SignalAddr: AddressType,
+> {
+    /// The format version this state was written with
+    pub version: u32,
+    pub sensors: Vec<SensorState<SensorAddr, TrainAddr>>,
+    pub signals: Vec<SignalState<SignalAddr, TrainAddr>>,
+    pub switches: Vec<SwitchState<SwitchAddr, TrainAddr>>,
+    pub trains: Vec<TrainState<Spd, TrainAddr>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(bound = "")]
+pub struct SensorState<SensorAddr: AddressType, TrainAddr: AddressType> {
+    pub address: Address<SensorAddr>,
+    pub status: Status,
+    pub level: SLevel,
+    /// The train this sensor is reserved for
+    pub train: Option<Address<TrainAddr>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(bound = "")]
+pub struct SignalState<SignalAddr: AddressType, TrainAddr: AddressType> {
+    pub address: Address<SignalAddr>,
+    pub status: Status,
+    /// The trains the block behind this signal is granted to
+    pub trains: Vec<Address<TrainAddr>>,
+    /// The trains waiting for the block behind this signal
+    pub requesters: VecDeque<Address<TrainAddr>>,
+    /// The routes through the block, the waiting trains asked for
+    pub routes: Vec<(Address<TrainAddr>, Vec<NodeIndex>)>,
+    /// The trains the block is granted to, together with their route through it,
+    /// whose switches did not acknowledge the route yet
+    pub switching: Vec<(Address<TrainAddr>, Vec<NodeIndex>)>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(bound = "")]
+pub struct SwitchState<SwitchAddr: AddressType, TrainAddr: AddressType> {
+    pub address: Address<SwitchAddr>,
+    pub dir: SwDir,
+    /// If the switch acknowledged its direction
+    pub updated: bool,
+    /// The train, whose route locks this switch
+    pub locked: Option<Address<TrainAddr>>,
+    /// The trains, whose routes over the given switches are protected by this switch
+    pub flank_locks: Vec<(Address<TrainAddr>, Address<SwitchAddr>)>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(bound = "")]
+pub struct TrainState<Spd: SpeedType, TrainAddr: AddressType> {
+    pub address: Address<TrainAddr>,
+    pub position: NodeIndex,
+    pub route: Option<VecDeque<(NodeIndex, bool)>>,
+    pub speed: Speed<Spd>,
+    pub direction: TrainDirection,
+    /// The functions switched on
+    pub functions: BTreeSet<u8>,
+    pub headlights: Headlights,
+    pub momentum: MomentumProfile,
+    pub speed_table: Option<SpeedTable<Spd>>,
+    pub distance_per_speed_step: Option<f64>,
+    /// The length of the train, measured in rail units
+    pub length: Option<f64>,
+    /// The sensors the train occupies from its tail to its head, with the distance driven,
+    /// when its head passed them
+    pub occupied: VecDeque<(NodeIndex, f64)>,
+}
+
+/// The reasons a state could not be restored.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StateError {
+    /// The state was written with an unsupported format version.
+    UnsupportedVersion(u32),
+    /// The state references a sensor, signal or switch that is not part of the layout.
+    UnknownComponent,
+    /// A train is not placed on a sensor or station.
+    InvalidTrainPosition(NodeIndex),
+    /// The state could not be read or written in the requested format.
+    Format(String),
+}
+
+impl Display for StateError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StateError::UnsupportedVersion(version) => {
+                write!(f, "unsupported state version {}", version)
+            }
+            StateError::UnknownComponent => write!(f, "the state references an unknown component"),
+            StateError::InvalidTrainPosition(node) => {
+                write!(f, "a train can not be placed on node {}", node.index())
+            }
+            StateError::Format(err) => write!(f, "malformed state: {}", err),
+        }
+    }
+}
+
+impl Error for StateError {}
+
+impl<
+        Spd: SpeedType,
+        TrainAddr: AddressType,
+        SensorAddr: AddressType,
+        SwitchAddr: AddressType,
+        SignalAddr: AddressType,
+    > RailroadState<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr>
+{
+    /// Checks if this state could be read by this version of the crate.
+    pub fn check_version(&self) -> Result<(), StateError> {
+        if self.version == STATE_VERSION {
+            Ok(())
+        } else {
+            Err(StateError::UnsupportedVersion(self.version))
+        }
+    }
+
+    /// Writes this state as pretty printed JSON.
+    #[cfg(feature = "json")]
+    pub fn to_json(&self) -> Result<String, StateError> {
+        serde_json::to_string_pretty(self).map_err(|err| StateError::Format(err.to_string()))
+    }
+
+    /// Reads a state from JSON and checks its version.
+    #[cfg(feature = "json")]
+    pub fn from_json(json: &str) -> Result<Self, StateError> {
+        let state: Self =
+            serde_json::from_str(json).map_err(|err| StateError::Format(err.to_string()))?;
+        state.check_version()?;
+        Ok(state)
+    }
+
+    /// Writes this state as pretty printed RON.
+    #[cfg(feature = "ron")]
+    pub fn to_ron(&self) -> Result<String, StateError> {
+        ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())
+            .map_err(|err| StateError::Format(err.to_string()))
+    }
+
+    /// Reads a state from RON and checks its version.
+    #[cfg(feature = "ron")]
+    pub fn from_ron(ron: &str) -> Result<Self, StateError> {
+        let state: Self = ron::from_str(ron).map_err(|err| StateError::Format(err.to_string()))?;
+        state.check_version()?;
+        Ok(state)
+    }
+}
@@ -1,18 +1,43 @@
+use crate::control::clock::ModelTime;
 use crate::control::messages::Message;
-use crate::control::rail_system::components::{Address, Node, Position, Speed};
-use crate::control::rail_system::railroad::Railroad;
+use crate::control::momentum::{MomentumProfile, SpeedTable};
+use crate::control::rail_system::components::{
+    Address, Node, Position, Rail, SLevel, Speed, TrainDirection,
+};
+use crate::control::rail_system::railroad::{reversed_node, reverses, Railroad};
+use crate::control::rail_system::state::TrainState;
 use crate::general::{AddressType, SpeedType};
-use petgraph::graph::NodeIndex;
-use std::collections::VecDeque;
+use petgraph::graph::{DiGraph, NodeIndex};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashSet, VecDeque};
 use std::hash::Hash;
 use std::sync::Arc;
 use std::time::Duration;
 use tokio::select;
-use tokio::sync::Notify;
+use tokio::sync::{Mutex, Notify};
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
 
-#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
-pub struct Clock {
-    _time: Duration,
+/// The highest function a train's decoder could switch.
+pub const MAX_FUNCTION: u8 = 28;
+
+/// The number of blocks ahead a train reserves, while driving its route.
+pub const RESERVED_BLOCKS: usize = 2;
+
+/// The decoder functions switching the headlights of a train.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub enum Headlights {
+    /// One function, the decoder switches between the front and rear lights by itself.
+    Function(u8),
+    /// One function for the lights at the front of every direction,
+    /// switched by the train, when it reverses.
+    Directional { forward: u8, backward: u8 },
+}
+
+impl Default for Headlights {
+    fn default() -> Self {
+        Headlights::Function(0)
+    }
 }
 
 #[derive(Debug)]
@@ -22,6 +47,37 @@ pub struct Train<Spd: SpeedType, TrainAddr: AddressType> {
     address: Address<TrainAddr>,
     /// The speed the train should read
     speed: Speed<Spd>,
+    /// The direction the train drives in
+    direction: TrainDirection,
+    /// The decoder functions switched on
+    functions: BTreeSet<u8>,
+    headlights: Headlights,
+    /// How fast the speed is changed
+    momentum: MomentumProfile,
+    /// Maps the speeds of this train to the speed steps of its decoder
+    speed_table: Option<SpeedTable<Spd>>,
+    /// The distance the train drives per second and speed step, measured in rail units
+    distance_per_speed_step: Option<f64>,
+    /// Starts braking for the next stopping point
+    brake_timer: Option<JoinHandle<()>>,
+    /// The time the train passed the sensor at its position or started again behind it
+    position_passed: Option<Instant>,
+    /// The distance from the position to the point the train started again at
+    start_offset: f64,
+    /// The stopping point the train stopped for and its distance from the position
+    stopped_at: Option<(NodeIndex, f64)>,
+    /// The last signal the train passed, guarding the block it drives in
+    signal_passed: Option<NodeIndex>,
+    /// The speed limit the last passed signal showed
+    aspect_limit: Option<Speed<Spd>>,
+    /// The length of the train, measured in rail units
+    length: Option<f64>,
+    /// The sensors the train occupies from its tail to its head, together with the distance
+    /// the train had driven, when its head passed them
+    occupied: VecDeque<(NodeIndex, f64)>,
+    /// The switches the train drove over and still locks, together with the distance the
+    /// train had driven, when its head passed them
+    switches_passed: VecDeque<(NodeIndex, f64)>,
     end_speed_adjusting: Arc<Notify>,
     speed_updater: Option<tokio::task::JoinHandle<Speed<Spd>>>,
     /// The train's position
@@ -29,7 +85,15 @@ pub struct Train<Spd: SpeedType, TrainAddr: AddressType> {
     /// Controls the driving process
     route: Option<VecDeque<(NodeIndex, bool)>>,
     /// Controls the driving table
-    timetable: Vec<Station<Spd, TrainAddr>>,
+    timetable: Vec<Station<TrainAddr>>,
+    /// Starts the timetable again, after its last station was left
+    repeat_timetable: bool,
+    /// The index of the station in the timetable, the train drives to or waits in
+    timetable_index: usize,
+    timetable_stage: Option<TimetableStage>,
+    /// The model time the train started waiting at in the current stage of the timetable,
+    /// taken from the fast clock, when the stage is run first
+    timetable_model_since: Option<ModelTime>,
 }
 
 impl<Spd: SpeedType, Ix: AddressType> PartialEq for Train<Spd, Ix> {
@@ -52,16 +116,131 @@ impl<Spd: SpeedType, TrainAddr: AddressType> Train<Spd, TrainAddr> {
         Train {
             address,
             speed: Speed::<Spd>::Stop,
+            direction: TrainDirection::Forward,
+            functions: BTreeSet::new(),
+            headlights: Headlights::default(),
+            momentum: MomentumProfile::default(),
+            speed_table: None,
+            distance_per_speed_step: None,
+            brake_timer: None,
+            position_passed: None,
+            start_offset: 0.0,
+            stopped_at: None,
+            signal_passed: None,
+            aspect_limit: None,
+            length: None,
+            occupied: VecDeque::from([(position, 0.0)]),
+            switches_passed: VecDeque::new(),
             end_speed_adjusting: Arc::new(Notify::new()),
             speed_updater: None,
             position,
             route: None,
             timetable: Vec::new(),
+            repeat_timetable: false,
+            timetable_index: 0,
+            timetable_stage: None,
+            timetable_model_since: None,
+        }
+    }
+
+    /// Recreates a train from a saved state.
+    pub(crate) fn from_state(state: TrainState<Spd, TrainAddr>) -> Train<Spd, TrainAddr> {
+        let mut train = Train::new(state.address, state.position);
+        train.speed = state.speed;
+        train.direction = state.direction;
+        train.functions = state.functions;
+        train.headlights = state.headlights;
+        train.momentum = state.momentum;
+        train.speed_table = state.speed_table;
+        train.distance_per_speed_step = state.distance_per_speed_step;
+        train.length = state.length;
+        if !state.occupied.is_empty() {
+            train.occupied = state.occupied;
+        }
+        train.route = state.route;
+        train
+    }
+
+    /// Returns the position, route and speed of this train.
+    pub fn state(&self) -> TrainState<Spd, TrainAddr> {
+        TrainState {
+            address: self.address,
+            position: self.position,
+            route: self.route.clone(),
+            speed: self.speed,
+            direction: self.direction,
+            functions: self.functions.clone(),
+            headlights: self.headlights,
+            momentum: self.momentum,
+            speed_table: self.speed_table.clone(),
+            distance_per_speed_step: self.distance_per_speed_step,
+            length: self.length,
+            occupied: self.occupied.clone(),
+        }
+    }
+
+    /// Returns the speed the train drives or accelerates to.
+    pub fn speed(&self) -> Speed<Spd> {
+        self.speed
+    }
+
+    pub fn momentum(&self) -> MomentumProfile {
+        self.momentum
+    }
+
+    /// Sets how fast the train accelerates and brakes.
+    /// Takes effect with the next call of [Train::set_speed].
+    pub fn set_momentum(&mut self, momentum: MomentumProfile) {
+        self.momentum = momentum;
+    }
+
+    pub fn speed_table(&self) -> Option<&SpeedTable<Spd>> {
+        self.speed_table.as_ref()
+    }
+
+    /// Sets the table mapping the speeds of this train to the speed steps of its decoder.
+    /// Without a table the speeds are sent unchanged.
+    pub fn set_speed_table(&mut self, speed_table: Option<SpeedTable<Spd>>) {
+        self.speed_table = speed_table;
+    }
+
+    pub fn distance_per_speed_step(&self) -> Option<f64> {
+        self.distance_per_speed_step
+    }
+
+    /// Sets the distance the train drives per second and speed step, measured in rail units.
+    /// Without it the braking distance is unknown, so the train starts braking, when it passes
+    /// the last sensor in front of its stopping point.
+    pub fn set_distance_per_speed_step(&mut self, distance_per_speed_step: Option<f64>) {
+        self.distance_per_speed_step = distance_per_speed_step;
+    }
+
+    pub fn length(&self) -> Option<f64> {
+        self.length
+    }
+
+    /// Sets the length of the train, measured in rail units.
+    /// Without it the train releases the sensors behind it, when they report to be free.
+    pub fn set_length(&mut self, length: Option<f64>) {
+        self.length = length;
+    }
+
+    /// Returns the sensors the train occupies, from its tail to its head.
+    pub fn occupied(&self) -> impl Iterator<Item = NodeIndex> + '_ {
+        self.occupied.iter().map(|(node, _driven)| *node)
+    }
+
+    /// Returns the speed sent to the decoder of this train for the given speed.
+    pub fn decoder_speed(&self, speed: Speed<Spd>) -> Speed<Spd> {
+        match &self.speed_table {
+            Some(table) => table.decoder_speed(speed),
+            None => speed,
         }
     }
 
     /// Sets the speed of this train to the given speed.
-    /// The train will accelerate or decelerate to the given speed, by controlled messages.
+    /// The train will accelerate or decelerate to the given speed along its [MomentumProfile],
+    /// by controlled messages.
     /// An emergency stop will be executed immediately without any deceleration delay.
     pub async fn set_speed<
         SensorAddr: AddressType,
@@ -89,9 +268,19 @@ impl<Spd: SpeedType, TrainAddr: AddressType> Train<Spd, TrainAddr> {
 
         let self_address = self.address;
         let interrupter = self.end_speed_adjusting.clone();
+        let momentum = self.momentum;
+        let speed_table = self.speed_table.clone();
 
         self.speed_updater = Some(tokio::spawn(async move {
-            Train::speed_accelerator(self_address, actual_speed, speed, interrupter, railroad).await
+            Train::speed_accelerator(
+                self_address,
+                actual_speed,
+                speed,
+                (momentum, speed_table),
+                interrupter,
+                railroad,
+            )
+            .await
         }));
     }
 
@@ -104,46 +293,231 @@ impl<Spd: SpeedType, TrainAddr: AddressType> Train<Spd, TrainAddr> {
         address: Address<TrainAddr>,
         mut actual_speed: Speed<Spd>,
         speed: Speed<Spd>,
+        (momentum, speed_table): (MomentumProfile, Option<SpeedTable<Spd>>),
         interrupter: Arc<Notify>,
         railroad: Arc<Railroad<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr, CrossingAddr>>,
     ) -> Speed<Spd> {
-        let duration = Duration::from_millis(10);
-        loop {
+        let decoder_speed = |speed| match &speed_table {
+            Some(table) => table.decoder_speed(speed),
+            None => speed,
+        };
+
+        let mut sent = None;
+        for step in momentum.ramp(actual_speed, speed) {
             select! {
                 _ = interrupter.notified() => {
                     return actual_speed;
                 },
-                _ = tokio::time::sleep(duration) => {
+                _ = tokio::time::sleep(momentum.tick) => {
 
                 }
             }
 
-            if actual_speed < speed {
-                actual_speed = actual_speed + Speed::default_acceleration();
-                if actual_speed > speed {
-                    break;
-                }
-            } else {
-                actual_speed = actual_speed - Speed::default_acceleration();
-                if actual_speed < speed {
-                    break;
-                }
+            actual_speed = step;
+            let step = decoder_speed(step);
+            if sent != Some(step) {
+                railroad.send(Message::TrainSpeed(address, step));
+                sent = Some(step);
             }
-
-            railroad.send(Message::TrainSpeed(address, actual_speed));
         }
-        railroad.send(Message::TrainSpeed(address, speed));
         speed
     }
 
+    pub fn direction(&self) -> TrainDirection {
+        self.direction
+    }
+
+    /// Changes the direction the train drives in.
+    /// The direction could only be changed, while the train stands.
+    /// Returns `false`, if the train is driving.
+    pub fn set_direction<
+        SensorAddr: AddressType,
+        SwitchAddr: AddressType,
+        SignalAddr: AddressType,
+        CrossingAddr: AddressType,
+    >(
+        &mut self,
+        direction: TrainDirection,
+        railroad: &Railroad<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr, CrossingAddr>,
+    ) -> bool {
+        if !self.stands()
+            || self
+                .speed_updater
+                .as_ref()
+                .is_some_and(|u| !u.is_finished())
+        {
+            return false;
+        }
+
+        let lights = self.lights();
+        self.direction = direction;
+        railroad.send(Message::TrainDirection(self.address, direction));
+        if let Headlights::Directional { forward, backward } = self.headlights {
+            let (front, rear) = match direction {
+                TrainDirection::Forward => (forward, backward),
+                TrainDirection::Backward => (backward, forward),
+            };
+            if lights {
+                self.set_function(rear, false, railroad);
+                self.set_function(front, true, railroad);
+            }
+        }
+        true
+    }
+
+    /// Checks if a function of the train's decoder is switched on.
+    pub fn function(&self, function: u8) -> bool {
+        self.functions.contains(&function)
+    }
+
+    /// Switches a function of the train's decoder, e.g. `F2` sounding the horn.
+    /// Returns `false`, if the function is higher than [MAX_FUNCTION].
+    pub fn set_function<
+        SensorAddr: AddressType,
+        SwitchAddr: AddressType,
+        SignalAddr: AddressType,
+        CrossingAddr: AddressType,
+    >(
+        &mut self,
+        function: u8,
+        on: bool,
+        railroad: &Railroad<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr, CrossingAddr>,
+    ) -> bool {
+        if function > MAX_FUNCTION {
+            return false;
+        }
+
+        if on {
+            self.functions.insert(function);
+        } else {
+            self.functions.remove(&function);
+        }
+        railroad.send(Message::TrainFunction(self.address, function, on));
+        true
+    }
+
+    pub fn headlights(&self) -> Headlights {
+        self.headlights
+    }
+
+    /// Sets the functions switching the headlights of this train.
+    pub fn set_headlights(&mut self, headlights: Headlights) {
+        self.headlights = headlights;
+    }
+
+    /// Checks if the headlights in the direction of travel are switched on.
+    pub fn lights(&self) -> bool {
+        self.function(self.front_lights())
+    }
+
+    /// Switches the headlights in the direction of travel on or off.
+    pub fn set_lights<
+        SensorAddr: AddressType,
+        SwitchAddr: AddressType,
+        SignalAddr: AddressType,
+        CrossingAddr: AddressType,
+    >(
+        &mut self,
+        on: bool,
+        railroad: &Railroad<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr, CrossingAddr>,
+    ) {
+        self.set_function(self.front_lights(), on, railroad);
+    }
+
+    /// Returns the function switching the headlights in the direction of travel.
+    fn front_lights(&self) -> u8 {
+        match (self.headlights, self.direction) {
+            (Headlights::Function(function), _) => function,
+            (Headlights::Directional { forward, .. }, TrainDirection::Forward) => forward,
+            (Headlights::Directional { backward, .. }, TrainDirection::Backward) => backward,
+        }
+    }
+
     pub fn stands(&self) -> bool {
         self.speed == Speed::Stop || self.speed == Speed::EmergencyStop
     }
 
-    pub fn timetable(&self) -> &Vec<Station<Spd, TrainAddr>> {
+    pub fn timetable(&self) -> &Vec<Station<TrainAddr>> {
         &self.timetable
     }
 
+    /// Sets the stations the train drives to one after another, starting with the first one.
+    /// With `repeat` the train starts again with the first station, after it left the last one.
+    ///
+    /// The timetable is run by [Train::update]. An empty timetable stops running the current one.
+    pub fn set_timetable(&mut self, timetable: Vec<Station<TrainAddr>>, repeat: bool) {
+        self.enter_timetable_stage(
+            (!timetable.is_empty()).then(|| TimetableStage::Arriving(Instant::now())),
+        );
+        self.timetable = timetable;
+        self.repeat_timetable = repeat;
+        self.timetable_index = 0;
+    }
+
+    /// Stops running the timetable. A train driving to a station of the timetable still stops
+    /// there, but does not depart again.
+    pub fn stop_timetable(&mut self) {
+        self.set_timetable(Vec::new(), false);
+    }
+
+    /// Shuttles the train between its position and `other`, until the timetable is stopped.
+    /// The train dwells `dwell` in both stations and reverses there, before it departs.
+    ///
+    /// Both stations need to be bidirectional sensors or stations, given in the direction the
+    /// train arrives in. Returns `false`, if one of them is not bidirectional.
+    ///
+    /// # Usage
+    ///
+    /// ```
+    /// # use std::time::Duration;
+    /// # use locologic::control::rail_system::components::Address;
+    /// # use locologic::control::rail_system::railroad::Railroad;
+    /// # use locologic::control::rail_system::railroad_test;
+    /// # tokio_test::block_on(async {
+    /// let (railroad, [_first, second], _buffer) = railroad_test::create_terminus_railroad().await;
+    /// let mut train = railroad.get_train(&Address::new(1)).unwrap().lock().await;
+    /// assert!(train.start_shuttle(second.0, Duration::from_secs(30), railroad.clone()).await);
+    /// assert_eq!(train.timetable().len(), 2);
+    ///
+    /// train.stop_timetable();
+    /// assert_eq!(train.timetable_station(), None);
+    /// # });
+    /// ```
+    pub async fn start_shuttle<
+        SensorAddr: AddressType,
+        SwitchAddr: AddressType,
+        SignalAddr: AddressType,
+        CrossingAddr: AddressType,
+    >(
+        &mut self,
+        other: NodeIndex,
+        dwell: Duration,
+        railroad: Arc<Railroad<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr, CrossingAddr>>,
+    ) -> bool {
+        let road = railroad.road().await;
+        let start = match (
+            reversed_node(&road, self.position),
+            reversed_node(&road, other),
+        ) {
+            (Some(start), Some(_)) => start,
+            _ => return false,
+        };
+
+        let station = |destination| {
+            Station::new(destination)
+                .depart_when(WaitingReasons::Time(dwell))
+                .reverse()
+        };
+        self.set_timetable(vec![station(other), station(start)], true);
+        true
+    }
+
+    /// Returns the index of the station in the timetable, the train drives to or waits in,
+    /// or `None`, if the timetable is finished.
+    pub fn timetable_station(&self) -> Option<usize> {
+        self.timetable_stage.map(|_| self.timetable_index)
+    }
+
     pub fn position(&self) -> NodeIndex {
         self.position
     }
@@ -168,40 +542,421 @@ impl<Spd: SpeedType, TrainAddr: AddressType> Train<Spd, TrainAddr> {
         }
     }
 
-    pub async fn sensor_entered<
+    /// Moves the train to the first node of its route ahead, that belongs to the given sensor.
+    /// Returns `false`, if the sensor is not on the route ahead of the train.
+    pub async fn sensor_reached<
+        SensorAddr: AddressType,
+        SwitchAddr: AddressType,
+        SignalAddr: AddressType,
+        CrossingAddr: AddressType,
+    >(
+        &mut self,
+        sensor: Address<SensorAddr>,
+        railroad: Arc<Railroad<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr, CrossingAddr>>,
+    ) -> bool {
+        let road = railroad.road().await;
+        let node = self
+            .route
+            .iter()
+            .flatten()
+            .map(|(node, _)| *node)
+            .find(|node| *node != self.position && sensor_address(&road, *node) == Some(sensor));
+
+        match node {
+            Some(node) => {
+                self.sensor_entered(node, railroad).await;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Moves the train to a sensor node of its route.
+    /// Sensors could be missed, so the train accepts every sensor ahead of it.
+    pub async fn sensor_entered<
+        SensorAddr: AddressType,
+        SwitchAddr: AddressType,
+        SignalAddr: AddressType,
+        CrossingAddr: AddressType,
+    >(
+        &mut self,
+        sensor: NodeIndex,
+        railroad: Arc<Railroad<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr, CrossingAddr>>,
+    ) {
+        let road = railroad.road().await;
+        let route = match self.route.as_mut() {
+            Some(route) => route,
+            None => return,
+        };
+        // Routes start at the train's position, which is not ahead of it.
+        if sensor == self.position
+            || sensor_address(&road, sensor).is_none()
+            || !route.iter().any(|(node, _)| *node == sensor)
+        {
+            return;
+        }
+
+        let mut previous = self.position;
+        let mut driven = self.occupied.back().map_or(0.0, |(_, driven)| *driven);
+        let mut signals = vec![];
+        while let Some((node, _granted)) = route.pop_front() {
+            driven += rail_length(&road, previous, node);
+            match road.node_weight(node) {
+                Some(Node::Signal(..)) => signals.push(node),
+                Some(Node::Switch(..)) => self.switches_passed.push_back((node, driven)),
+                _ => {}
+            }
+            previous = node;
+            if node == sensor {
+                break;
+            }
+        }
+        let arrived = route.is_empty();
+        if let Some(Node::Signal(adr, ..)) = signals.last().and_then(|n| road.node_weight(*n)) {
+            self.aspect_limit = railroad.aspect(adr).await.speed_limit();
+        }
+
+        self.position = sensor;
+        self.position_passed = Some(Instant::now());
+        self.start_offset = 0.0;
+        self.stopped_at = None;
+        self.occupied.push_back((sensor, driven));
+        if let Some(adr) = sensor_address(&road, sensor) {
+            if let Some(sensor) = railroad.get_sensor_mutex(&adr) {
+                sensor.lock().await.block(self.address);
+            }
+        }
+        self.release_cleared_sensors(&road, &railroad).await;
+        self.unlock_cleared_switches(&road, &railroad).await;
+        for signal in signals {
+            self.pass_signal(signal, railroad.clone());
+        }
+
+        if arrived {
+            self.route = None;
+            if !self.stands() {
+                self.set_speed(Speed::Stop, railroad).await;
+            }
+            return;
+        }
+        self.request_next_block(railroad.clone()).await;
+        self.schedule_braking(railroad).await;
+    }
+
+    /// Gives the block behind a passed signal back and releases the sensors of the block
+    /// the train left, except those it still occupies.
+    fn pass_signal<
+        SensorAddr: AddressType,
+        SwitchAddr: AddressType,
+        SignalAddr: AddressType,
+        CrossingAddr: AddressType,
+    >(
+        &mut self,
+        signal: NodeIndex,
+        railroad: Arc<Railroad<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr, CrossingAddr>>,
+    ) {
+        let left = self.signal_passed.replace(signal);
+        let address = self.address;
+
+        // The train does not wait, until the signal calculated who gets the block.
+        tokio::spawn(async move {
+            if let Some(signal) = railroad.get_signal_mutex_by_index(signal).await {
+                signal.lock().await.passed(address, railroad.clone()).await;
+            }
+
+            let sensors = match left {
+                Some(left) => match railroad.get_signal_mutex_by_index(left).await {
+                    Some(signal) => signal.lock().await.block_sensors().to_vec(),
+                    None => return,
+                },
+                None => return,
+            };
+            if let Some(train) = railroad.get_train(&address) {
+                let train = train.lock().await;
+                let road = railroad.road().await;
+                for sensor in sensors {
+                    train.release(sensor, &road, &railroad).await;
+                }
+            }
+        });
+    }
+
+    /// Handles a sensor reporting, that no train is on it anymore.
+    ///
+    /// Without a length the train releases the sensor together with all sensors behind it.
+    /// Otherwise the sensor is released, when the train drove its length beyond it.
+    /// Returns `false`, if the train does not occupy the sensor.
+    pub(crate) async fn sensor_left<
+        SensorAddr: AddressType,
+        SwitchAddr: AddressType,
+        SignalAddr: AddressType,
+        CrossingAddr: AddressType,
+    >(
+        &mut self,
+        sensor: Address<SensorAddr>,
+        railroad: &Railroad<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr, CrossingAddr>,
+    ) -> bool {
+        let road = railroad.road().await;
+        let index = match self
+            .occupied
+            .iter()
+            .position(|(node, _)| sensor_address(&road, *node) == Some(sensor))
+        {
+            Some(index) => index,
+            None => return false,
+        };
+
+        if self.length.is_none() {
+            let left: Vec<_> = self.occupied.drain(..=index).collect();
+            for (node, _driven) in left {
+                if let Some(adr) = sensor_address(&road, node) {
+                    self.release(adr, &road, railroad).await;
+                }
+            }
+            self.unlock_cleared_switches(&road, railroad).await;
+        }
+        true
+    }
+
+    /// Releases the sensors the tail of the train has passed.
+    async fn release_cleared_sensors<
+        SensorAddr: AddressType,
+        SwitchAddr: AddressType,
+        SignalAddr: AddressType,
+        CrossingAddr: AddressType,
+    >(
+        &mut self,
+        road: &Road<SensorAddr, SwitchAddr, SignalAddr, CrossingAddr>,
+        railroad: &Railroad<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr, CrossingAddr>,
+    ) {
+        let length = match self.length {
+            Some(length) => length,
+            None => return,
+        };
+        let head = self.occupied.back().map_or(0.0, |(_, driven)| *driven);
+
+        while self.occupied.len() > 1 && head - self.occupied[0].1 > length {
+            let left = self.occupied.pop_front().map(|(node, _)| node);
+            if let Some(adr) = left.and_then(|node| sensor_address(road, node)) {
+                self.release(adr, road, railroad).await;
+            }
+        }
+    }
+
+    /// Unlocks the switches the tail of the train has passed.
+    ///
+    /// Without a length the tail passed a switch, when the train left all sensors in front of it.
+    async fn unlock_cleared_switches<
+        SensorAddr: AddressType,
+        SwitchAddr: AddressType,
+        SignalAddr: AddressType,
+        CrossingAddr: AddressType,
+    >(
+        &mut self,
+        road: &Road<SensorAddr, SwitchAddr, SignalAddr, CrossingAddr>,
+        railroad: &Railroad<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr, CrossingAddr>,
+    ) {
+        let head = self.occupied.back().map_or(0.0, |(_, driven)| *driven);
+        let tail = self.occupied.front().map(|(_, driven)| *driven);
+
+        while let Some((node, driven)) = self.switches_passed.front().copied() {
+            let cleared = match (self.length, tail) {
+                (Some(length), _) => head - driven > length,
+                (None, Some(tail)) => tail > driven,
+                (None, None) => true,
+            };
+            if !cleared {
+                break;
+            }
+            self.switches_passed.pop_front();
+            if let Some(Node::Switch(adr, ..)) = road.node_weight(node) {
+                railroad.unlock_switch(adr, self.address).await;
+            }
+        }
+    }
+
+    /// Frees a sensor the train left, unless the train still occupies it.
+    async fn release<
+        SensorAddr: AddressType,
+        SwitchAddr: AddressType,
+        SignalAddr: AddressType,
+        CrossingAddr: AddressType,
+    >(
+        &self,
+        adr: Address<SensorAddr>,
+        road: &Road<SensorAddr, SwitchAddr, SignalAddr, CrossingAddr>,
+        railroad: &Railroad<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr, CrossingAddr>,
+    ) {
+        if self
+            .occupied
+            .iter()
+            .any(|(node, _)| sensor_address(road, *node) == Some(adr))
+        {
+            return;
+        }
+        if let Some(sensor) = railroad.get_sensor_mutex(&adr) {
+            sensor.lock().await.free(self.address, railroad);
+        }
+    }
+
+    /// Returns the next node of the route the train has to stop at and the distance from the
+    /// train's position to it, measured in rail units.
+    ///
+    /// Trains stop in front of signals, whose block is not granted to them, at nodes they
+    /// reverse at and at the end of their route.
+    pub async fn stopping_point<
+        SensorAddr: AddressType,
+        SwitchAddr: AddressType,
+        SignalAddr: AddressType,
+        CrossingAddr: AddressType,
+    >(
+        &self,
+        railroad: &Railroad<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr, CrossingAddr>,
+    ) -> Option<(NodeIndex, f64)> {
+        let route = self.route.as_ref()?;
+        let road = railroad.road().await;
+
+        let mut distance = 0.0;
+        let mut previous = self.position;
+        for (index, (node, granted)) in route.iter().enumerate() {
+            if reverses(&road, previous, *node) {
+                return Some((previous, distance));
+            }
+            distance += rail_length(&road, previous, *node);
+
+            let red_signal = !granted && matches!(road.node_weight(*node), Some(Node::Signal(..)));
+            if red_signal || index == route.len() - 1 {
+                return Some((*node, distance));
+            }
+            previous = *node;
+        }
+        None
+    }
+
+    /// Plans when to start braking, so the train stops at its [stopping point](Train::stopping_point).
+    ///
+    /// The braking distance is calculated from the train's [MomentumProfile] and its current speed.
+    /// Between two sensors the train's position is estimated from the time it passed the last one.
+    /// Should be called again, whenever the route or the speed of the train changed.
+    pub async fn schedule_braking<
+        SensorAddr: AddressType,
+        SwitchAddr: AddressType,
+        SignalAddr: AddressType,
+        CrossingAddr: AddressType,
+    >(
+        &mut self,
+        railroad: Arc<Railroad<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr, CrossingAddr>>,
+    ) {
+        if let Some(timer) = self.brake_timer.take() {
+            timer.abort();
+        }
+        if self.stands() {
+            return;
+        }
+        let (stop, distance, delay) = match self.braking_delay(self.speed, &railroad).await {
+            Some(braking) => braking,
+            None => return,
+        };
+
+        if delay.is_zero() {
+            self.stop_for(stop, distance, railroad).await;
+            return;
+        }
+
+        let address = self.address;
+        self.brake_timer = Some(tokio::spawn(async move {
+            tokio::time::sleep(delay).await;
+            if let Some(train) = railroad.get_train(&address) {
+                let mut train = train.lock().await;
+                train.brake_timer = None;
+                train.stop_for(stop, distance, railroad.clone()).await;
+            }
+        }));
+    }
+
+    /// Returns the stopping point, its distance and the time until the train has to start
+    /// braking for it, when driving with `speed`.
+    /// Returns `None`, if the train does not know yet, when to brake.
+    async fn braking_delay<
+        SensorAddr: AddressType,
+        SwitchAddr: AddressType,
+        SignalAddr: AddressType,
+        CrossingAddr: AddressType,
+    >(
+        &self,
+        speed: Speed<Spd>,
+        railroad: &Railroad<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr, CrossingAddr>,
+    ) -> Option<(NodeIndex, f64, Duration)> {
+        let (stop, distance) = self.stopping_point(railroad).await?;
+
+        let delay = match self.distance_per_speed_step {
+            Some(distance_per_speed_step) => {
+                let velocity = match speed {
+                    Speed::Drive(spd) => spd.to_f64().unwrap_or_default(),
+                    _ => 0.0,
+                } * distance_per_speed_step;
+                if velocity <= 0.0 {
+                    return None;
+                }
+                let driven = self.start_offset
+                    + self
+                        .position_passed
+                        .map_or(0.0, |passed| passed.elapsed().as_secs_f64() * velocity);
+                let braking = self
+                    .momentum
+                    .braking_distance(speed, distance_per_speed_step);
+                Duration::from_secs_f64(((distance - driven - braking) / velocity).max(0.0))
+            }
+            // Without a braking distance the train brakes at the last sensor in front of the stop,
+            // or at the stop itself, if it is a sensor.
+            None => {
+                let road = railroad.road().await;
+                let is_sensor = |node| road.node_weight(node).is_some_and(Node::is_driveable);
+                let sensor_ahead = stop != self.position
+                    && (is_sensor(stop)
+                        || self
+                            .route
+                            .iter()
+                            .flatten()
+                            .map(|(node, _)| *node)
+                            .skip_while(|node| *node == self.position)
+                            .take_while(|node| *node != stop)
+                            .any(is_sensor));
+                if sensor_ahead {
+                    return None;
+                }
+                Duration::ZERO
+            }
+        };
+        Some((stop, distance, delay))
+    }
+
+    /// Stops the train for the given stopping point.
+    /// It waits there, until the stopping point moves on.
+    async fn stop_for<
         SensorAddr: AddressType,
         SwitchAddr: AddressType,
         SignalAddr: AddressType,
         CrossingAddr: AddressType,
     >(
         &mut self,
-        sensor: NodeIndex,
+        stop: NodeIndex,
+        distance: f64,
         railroad: Arc<Railroad<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr, CrossingAddr>>,
     ) {
-        if self.route.is_none() {
-            return;
-        }
-        let route = self.route.as_mut().unwrap();
-        let road = railroad.road().await;
-        if let Some((next_sensor, _b)) = route.clone().iter().find(|(i, _b)| {
-            matches!(
-                road.node_weight(*i),
-                Some(Node::Sensor(..) | Node::Station(..))
-            )
-        }) {
-            if *next_sensor == sensor {
-                while let Some((i, _b)) = route.pop_front() {
-                    if i == sensor {
-                        break;
-                    }
-                }
-                self.position = *next_sensor;
-                self.request_next_block(railroad).await;
-            }
-        }
+        // Without a braking distance the train stops right behind the sensor it braked at.
+        let distance = match self.distance_per_speed_step {
+            Some(_) => distance,
+            None => 0.0,
+        };
+        self.stopped_at = Some((stop, distance));
+        self.set_speed(Speed::Stop, railroad).await;
     }
 
-    /// Checks if the next signal block should be requested and requests it.
+    /// Requests the block behind the next signal of the route,
+    /// unless [RESERVED_BLOCKS] blocks ahead are already granted to the train.
+    ///
+    /// The request is handled in the background, [Train::update] applies its grant to the route.
     pub async fn request_next_block<
         SensorAddr: AddressType,
         SwitchAddr: AddressType,
@@ -233,26 +988,29 @@ impl<Spd: SpeedType, TrainAddr: AddressType> Train<Spd, TrainAddr> {
                 if *b {
                     blocks_count += 1;
                 } else {
-                    next_signal = Some(adr);
+                    next_signal = Some(*adr);
                     break;
                 }
             }
         }
 
-        if blocks_count < 1
-        /* TODO: Replace with config value */
-        {
+        if blocks_count >= RESERVED_BLOCKS {
             return;
         }
 
         if let Some(next_signal) = next_signal {
-            if let Some(signal) = rail.get_signal_mutex(next_signal) {
-                signal
-                    .lock()
-                    .await
-                    .request_block(self.address(), rail.clone())
-                    .await;
-            }
+            let address = self.address;
+            let route = self.route_through(next_signal, &rail).await;
+            // The train does not wait, until the signal calculated who gets the block.
+            tokio::spawn(async move {
+                if let Some(signal) = rail.get_signal_mutex(&next_signal) {
+                    signal
+                        .lock()
+                        .await
+                        .request_block(address, route, rail.clone())
+                        .await;
+                }
+            });
         }
     }
 
@@ -274,7 +1032,10 @@ impl<Spd: SpeedType, TrainAddr: AddressType> Train<Spd, TrainAddr> {
         if let Some(signal_node) = rail.get_signal_of_block(position).await {
             let signal = rail.get_signal_mutex_by_index(signal_node).await.unwrap();
             let mut signal = signal.lock().await;
-            signal.request_block(self.address, rail.clone()).await;
+            let route = self.route_through(signal.address(), &rail).await;
+            signal
+                .request_block(self.address, route, rail.clone())
+                .await;
             signal_adr = Some(signal.address());
         }
 
@@ -353,9 +1114,45 @@ impl<Spd: SpeedType, TrainAddr: AddressType> Train<Spd, TrainAddr> {
         &mut self,
         destination: NodeIndex,
         railroad: Arc<Railroad<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr, CrossingAddr>>,
+    ) -> bool {
+        self.drive_to_avoiding(destination, &[], railroad).await
+    }
+
+    /// Replaces the route of the train by a route to the same destination,
+    /// which does not pass the `avoided` node.
+    /// Returns `false` and keeps the route, if there is no such route.
+    pub async fn reroute_avoiding<
+        SensorAddr: AddressType,
+        SwitchAddr: AddressType,
+        SignalAddr: AddressType,
+        CrossingAddr: AddressType,
+    >(
+        &mut self,
+        avoided: NodeIndex,
+        railroad: Arc<Railroad<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr, CrossingAddr>>,
+    ) -> bool {
+        let destination = match self.route.as_ref().and_then(|route| route.back()) {
+            Some((destination, _)) => *destination,
+            None => return false,
+        };
+        self.drive_to_avoiding(destination, &[avoided], railroad)
+            .await
+    }
+
+    async fn drive_to_avoiding<
+        SensorAddr: AddressType,
+        SwitchAddr: AddressType,
+        SignalAddr: AddressType,
+        CrossingAddr: AddressType,
+    >(
+        &mut self,
+        destination: NodeIndex,
+        avoided: &[NodeIndex],
+        railroad: Arc<Railroad<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr, CrossingAddr>>,
     ) -> bool {
         let graph = railroad.road().await;
-        let route = Railroad::shortest_path(railroad, self.position, destination).await;
+        let route =
+            Railroad::shortest_path_avoiding(railroad, self.position, destination, avoided).await;
 
         // Short route to the last sensor as destination
 
@@ -419,6 +1216,35 @@ impl<Spd: SpeedType, TrainAddr: AddressType> Train<Spd, TrainAddr> {
         Some(vec)
     }
 
+    /// Returns the part of the route through the block behind the given signal.
+    /// Without a route through it, the train only needs the block's sensors.
+    async fn route_through<
+        SensorAddr: AddressType,
+        SwitchAddr: AddressType,
+        SignalAddr: AddressType,
+        CrossingAddr: AddressType,
+    >(
+        &self,
+        signal: Address<SignalAddr>,
+        railroad: &Railroad<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr, CrossingAddr>,
+    ) -> Vec<NodeIndex> {
+        self.request_route(signal, railroad)
+            .await
+            .map(|route| route.into_iter().copied().collect())
+            .unwrap_or_default()
+    }
+
+    /// Drives the train along its route.
+    /// Called every tick by [Railroad::start_scheduler].
+    ///
+    /// The train requests the blocks ahead and sets the switches of the route granted to it.
+    /// A standing train starts, when all of these switches are acknowledged.
+    /// The speed obeys the [maximum speed] of the last sensor and the [speed limit] of nearby signals.
+    /// The train reverses, where its route requires it, and stops at the route's end.
+    /// Without a route, it drives to the next station of its [timetable](Train::set_timetable).
+    ///
+    /// [maximum speed]: crate::control::rail_system::components::Sensor::max_speed
+    /// [speed limit]: crate::control::rail_system::components::Aspect::speed_limit
     pub async fn update<
         SensorAddr: AddressType,
         SwitchAddr: AddressType,
@@ -426,67 +1252,622 @@ impl<Spd: SpeedType, TrainAddr: AddressType> Train<Spd, TrainAddr> {
         CrossingAddr: AddressType,
     >(
         &mut self,
-        _tick: Duration,
-        _railroad: &Railroad<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr, CrossingAddr>,
+        railroad: Arc<Railroad<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr, CrossingAddr>>,
+    ) {
+        let road = railroad.road().await;
+        let granted = self.update_grants(&road, &railroad);
+        self.request_next_block(railroad.clone()).await;
+        self.run_timetable(&road, &railroad).await;
+        if self.route.is_none() {
+            return;
+        }
+
+        let switched = self.set_switches(&road, &railroad).await;
+        let speed = match self.max_speed(&road, &railroad).await {
+            Some(speed) => speed,
+            None => return,
+        };
+
+        if !self.stands() {
+            if speed != self.speed {
+                self.set_speed(speed, railroad.clone()).await;
+                self.schedule_braking(railroad).await;
+            } else if granted {
+                self.schedule_braking(railroad).await;
+            }
+            return;
+        }
+
+        if self.reverse(&road, &railroad) || !switched {
+            return;
+        }
+        let stop = self.stopping_point(&railroad).await.map(|(stop, _)| stop);
+        if self.stopped_at.is_some_and(|(node, _)| Some(node) == stop) {
+            return;
+        }
+
+        self.start_offset = self.stopped_at.map_or(0.0, |(_, distance)| distance);
+        self.position_passed = Some(Instant::now());
+        if self
+            .braking_delay(speed, &railroad)
+            .await
+            .is_some_and(|(_, _, delay)| delay.is_zero())
+        {
+            return;
+        }
+
+        self.stopped_at = None;
+        self.set_speed(speed, railroad.clone()).await;
+        self.schedule_braking(railroad).await;
+    }
+
+    /// Drives the train to the stations of its timetable and waits for their conditions.
+    async fn run_timetable<
+        SensorAddr: AddressType,
+        SwitchAddr: AddressType,
+        SignalAddr: AddressType,
+        CrossingAddr: AddressType,
+    >(
+        &mut self,
+        road: &Road<SensorAddr, SwitchAddr, SignalAddr, CrossingAddr>,
+        railroad: &Arc<Railroad<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr, CrossingAddr>>,
     ) {
-        todo!("Implement");
+        let (stage, station) = match (
+            self.timetable_stage,
+            self.timetable.get(self.timetable_index),
+        ) {
+            (Some(stage), Some(station)) => (stage, station),
+            _ => return,
+        };
+        let (destination, reverse) = (station.destination, station.reverse);
+        let model_since = match self.timetable_model_since {
+            Some(model_since) => model_since,
+            None => *self
+                .timetable_model_since
+                .insert(railroad.clock().await.time()),
+        };
+
+        match stage {
+            TimetableStage::Arriving(since) => {
+                let state = self
+                    .waiting_state(&station.arrive, (since, model_since), road, railroad)
+                    .await;
+                if self.route.is_some() || !station.could_arrive(&state) {
+                    return;
+                }
+                // A route from the destination to itself is never left, so it is not driven.
+                if self.position == destination
+                    || self.trigger_drive_to(destination, railroad.clone()).await
+                {
+                    self.enter_timetable_stage(Some(TimetableStage::Driving));
+                }
+            }
+            TimetableStage::Driving => {
+                if self.route.is_some() {
+                    return;
+                }
+                if self.position != destination {
+                    // The route was replaced or reset, so the train drives to the station again.
+                    self.trigger_drive_to(destination, railroad.clone()).await;
+                } else if self.stands() {
+                    self.enter_timetable_stage(Some(TimetableStage::Departing(Instant::now())));
+                }
+            }
+            TimetableStage::Departing(since) => {
+                let state = self
+                    .waiting_state(&station.depart, (since, model_since), road, railroad)
+                    .await;
+                if !station.could_depart(&state) {
+                    return;
+                }
+                // Trains still changing their speed are turned with the next update.
+                if let Some(turned) = reversed_node(road, self.position).filter(|_| reverse) {
+                    if !self.turn_at(turned, railroad) {
+                        return;
+                    }
+                }
+                self.timetable_index += 1;
+                if self.timetable_index == self.timetable.len() && self.repeat_timetable {
+                    self.timetable_index = 0;
+                }
+                self.enter_timetable_stage(
+                    (self.timetable_index < self.timetable.len())
+                        .then(|| TimetableStage::Arriving(Instant::now())),
+                );
+            }
+        }
+    }
+
+    fn enter_timetable_stage(&mut self, stage: Option<TimetableStage>) {
+        self.timetable_stage = stage;
+        self.timetable_model_since = None;
+    }
+
+    /// Collects the state of the fast clock, trains and sensors the waiting reasons refer to,
+    /// for a train waiting since the given real and model time.
+    /// Trains being busy are checked again with the next update.
+    async fn waiting_state<
+        SensorAddr: AddressType,
+        SwitchAddr: AddressType,
+        SignalAddr: AddressType,
+        CrossingAddr: AddressType,
+    >(
+        &self,
+        waiting: &WaitingNode<TrainAddr>,
+        (since, model_since): (Instant, ModelTime),
+        road: &Road<SensorAddr, SwitchAddr, SignalAddr, CrossingAddr>,
+        railroad: &Railroad<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr, CrossingAddr>,
+    ) -> WaitingState<TrainAddr> {
+        let mut state = WaitingState::new(since.elapsed());
+        state.model_since = model_since;
+        state.model_time = railroad.clock().await.time();
+        for reason in waiting.reasons() {
+            let (address, node) = match reason {
+                WaitingReasons::Time(_) | WaitingReasons::ModelTime(_) => continue,
+                WaitingReasons::TrainOnSensor(address, node)
+                | WaitingReasons::TrainHoldInStation(address, node) => (*address, *node),
+            };
+            let other = if address == self.address {
+                None
+            } else {
+                match railroad.get_train(&address).map(Mutex::try_lock) {
+                    Some(Ok(train)) => Some(train),
+                    _ => continue,
+                }
+            };
+            let train = other.as_deref().unwrap_or(self);
+
+            if train.position == node && train.stands() {
+                state.trains_holding.insert((address, node));
+            }
+            let occupied = match sensor_address(road, node) {
+                Some(sensor) => match railroad.get_sensor_mutex(&sensor) {
+                    Some(sensor) => sensor.lock().await.state().level == SLevel::Occupied,
+                    None => false,
+                },
+                None => true,
+            };
+            if occupied && train.occupied().any(|sensor| sensor == node) {
+                state.trains_on_sensors.insert((address, node));
+            }
+        }
+        state
+    }
+
+    /// Marks the signals of the route, whose blocks were granted to the train.
+    /// Returns `true`, if a block was granted since the last call.
+    fn update_grants<
+        SensorAddr: AddressType,
+        SwitchAddr: AddressType,
+        SignalAddr: AddressType,
+        CrossingAddr: AddressType,
+    >(
+        &mut self,
+        road: &Road<SensorAddr, SwitchAddr, SignalAddr, CrossingAddr>,
+        railroad: &Railroad<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr, CrossingAddr>,
+    ) -> bool {
+        let address = self.address;
+        let mut granted = false;
+        for (node, b) in self.route.iter_mut().flatten().filter(|(_, b)| !*b) {
+            if let Some(Node::Signal(adr, _)) = road.node_weight(*node) {
+                // Signals being busy are checked again with the next update.
+                let signal = railroad.get_signal_mutex(adr).map(Mutex::try_lock);
+                if let Some(Ok(signal)) = signal {
+                    if signal.granted(address) {
+                        *b = true;
+                        granted = true;
+                    }
+                }
+            }
+        }
+        granted
+    }
+
+    /// Locks and sets the switches on the route up to the first signal not granted to the train.
+    /// Switches locked for the route of another train are not moved.
+    /// Returns `true`, if all of them are locked for the train and acknowledged the route.
+    async fn set_switches<
+        SensorAddr: AddressType,
+        SwitchAddr: AddressType,
+        SignalAddr: AddressType,
+        CrossingAddr: AddressType,
+    >(
+        &self,
+        road: &Road<SensorAddr, SwitchAddr, SignalAddr, CrossingAddr>,
+        railroad: &Railroad<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr, CrossingAddr>,
+    ) -> bool {
+        let mut nodes = vec![self.position];
+        for (node, granted) in self.route.iter().flatten() {
+            nodes.push(*node);
+            if !granted && matches!(road.node_weight(*node), Some(Node::Signal(..))) {
+                break;
+            }
+        }
+
+        let mut switched = true;
+        for step in nodes.windows(3) {
+            if let Some(Node::Switch(adr, ..)) = road.node_weight(step[1]) {
+                if let Some(switch) = railroad.get_switch_mutex(adr) {
+                    let mut switch = switch.lock().await;
+                    if !switch
+                        .request_path(self.address, step[1], step[0], step[2], railroad)
+                        .await
+                    {
+                        switched = false;
+                        continue;
+                    }
+                    switched &= switch
+                        .switch_in_correct_state(step[1], step[0], step[2], railroad)
+                        .await;
+                }
+            }
+        }
+        switched
+    }
+
+    /// Returns the maximum speed of the sensor the train passed last,
+    /// or of the next sensor, if the train is not at a sensor.
+    /// The speed is limited by the aspect of the signal passed last and of the next signal,
+    /// so the train already passes the next signal with its limited speed.
+    async fn max_speed<
+        SensorAddr: AddressType,
+        SwitchAddr: AddressType,
+        SignalAddr: AddressType,
+        CrossingAddr: AddressType,
+    >(
+        &self,
+        road: &Road<SensorAddr, SwitchAddr, SignalAddr, CrossingAddr>,
+        railroad: &Railroad<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr, CrossingAddr>,
+    ) -> Option<Speed<Spd>> {
+        let sensor = std::iter::once(self.position)
+            .chain(self.route.iter().flatten().map(|(node, _)| *node))
+            .find_map(|node| sensor_address(road, node))?;
+        let mut speed = railroad.get_sensor_mutex(&sensor)?.lock().await.max_speed();
+
+        let next_signal =
+            self.route
+                .iter()
+                .flatten()
+                .find_map(|(node, _)| match road.node_weight(*node) {
+                    Some(Node::Signal(adr, ..)) => Some(*adr),
+                    _ => None,
+                });
+        let next_limit = match next_signal {
+            Some(signal) => railroad.aspect(&signal).await.speed_limit(),
+            None => None,
+        };
+        for limit in self.aspect_limit.iter().chain(next_limit.iter()) {
+            speed = speed.min(*limit);
+        }
+        Some(speed)
+    }
+
+    /// Reverses the standing train, if it stopped where its route reverses.
+    /// Returns `true`, if the train reversed.
+    fn reverse<
+        SensorAddr: AddressType,
+        SwitchAddr: AddressType,
+        SignalAddr: AddressType,
+        CrossingAddr: AddressType,
+    >(
+        &mut self,
+        road: &Road<SensorAddr, SwitchAddr, SignalAddr, CrossingAddr>,
+        railroad: &Railroad<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr, CrossingAddr>,
+    ) -> bool {
+        let route = match &self.route {
+            Some(route) => route,
+            None => return false,
+        };
+
+        // The index of the first node driven to after reversing, if no red signal comes first.
+        let mut previous = self.position;
+        let mut index = None;
+        for (i, (node, granted)) in route.iter().enumerate() {
+            if reverses(road, previous, *node) {
+                index = Some(i);
+                break;
+            }
+            if !granted && matches!(road.node_weight(*node), Some(Node::Signal(..))) {
+                break;
+            }
+            previous = *node;
+        }
+        let index = match index {
+            Some(index) => index,
+            None => return false,
+        };
+
+        let stopped =
+            previous == self.position || self.stopped_at.is_some_and(|(stop, _)| stop == previous);
+        if !stopped || !self.turn_at(route[index].0, railroad) {
+            return false;
+        }
+
+        self.route.as_mut().unwrap().drain(..=index);
+        true
+    }
+
+    /// Changes the direction of the standing train, which continues from `position`.
+    /// Returns `false`, if the direction could not be changed.
+    fn turn_at<
+        SensorAddr: AddressType,
+        SwitchAddr: AddressType,
+        SignalAddr: AddressType,
+        CrossingAddr: AddressType,
+    >(
+        &mut self,
+        position: NodeIndex,
+        railroad: &Railroad<Spd, TrainAddr, SensorAddr, SwitchAddr, SignalAddr, CrossingAddr>,
+    ) -> bool {
+        if !self.set_direction(!self.direction, railroad) {
+            return false;
+        }
+
+        self.position = position;
+        self.stopped_at = None;
+        // The former tail leads now, so no sensor counts as cleared, before the train drove again.
+        let head = self.occupied.back().map_or(0.0, |(_, driven)| *driven);
+        for (_, driven) in self
+            .occupied
+            .iter_mut()
+            .chain(self.switches_passed.iter_mut())
+        {
+            *driven = head;
+        }
+        true
+    }
+}
+
+type Road<SensorAddr, SwitchAddr, SignalAddr, CrossingAddr> =
+    DiGraph<Node<SensorAddr, SwitchAddr, SignalAddr, CrossingAddr>, Vec<Rail>>;
+
+/// The length of the rails connecting two nodes, measured in rail units.
+fn rail_length<
+    SensorAddr: AddressType,
+    SwitchAddr: AddressType,
+    SignalAddr: AddressType,
+    CrossingAddr: AddressType,
+>(
+    road: &Road<SensorAddr, SwitchAddr, SignalAddr, CrossingAddr>,
+    from: NodeIndex,
+    to: NodeIndex,
+) -> f64 {
+    road.find_edge(from, to)
+        .and_then(|edge| road.edge_weight(edge))
+        .map_or(0, |rails| rails.iter().map(Rail::manhattan_distance).sum()) as f64
+}
+
+fn sensor_address<
+    SensorAddr: AddressType,
+    SwitchAddr: AddressType,
+    SignalAddr: AddressType,
+    CrossingAddr: AddressType,
+>(
+    road: &Road<SensorAddr, SwitchAddr, SignalAddr, CrossingAddr>,
+    node: NodeIndex,
+) -> Option<Address<SensorAddr>> {
+    match road.node_weight(node)? {
+        Node::Sensor(adr, _) | Node::Station(adr, _) => Some(*adr),
+        _ => None,
     }
 }
 
-#[derive(Debug, Eq, PartialEq, Hash)]
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
 /// One station for a train to drive to
-pub struct Station<Spd: SpeedType, TrainAddr: AddressType> {
-    arrive: Box<WaitingNode<Spd, TrainAddr>>,
-    depart: Box<WaitingNode<Spd, TrainAddr>>,
+///
+/// The train drives to the station's destination, once the arrive conditions are fulfilled,
+/// and leaves it for the next station, once the depart conditions are fulfilled.
+/// Without conditions the train arrives and departs immediately.
+pub struct Station<TrainAddr: AddressType> {
+    arrive: Box<WaitingNode<TrainAddr>>,
+    depart: Box<WaitingNode<TrainAddr>>,
     destination: NodeIndex,
+    /// Reverses the train, before it departs
+    reverse: bool,
 }
 
-impl<Spd: SpeedType, TrainAddr: AddressType> Station<Spd, TrainAddr> {
-    pub fn could_arrive(&self) -> bool {
-        self.arrive.fulfills()
+impl<TrainAddr: AddressType> Station<TrainAddr> {
+    pub fn new(destination: NodeIndex) -> Self {
+        Station {
+            arrive: Box::default(),
+            depart: Box::default(),
+            destination,
+            reverse: false,
+        }
+    }
+
+    /// Sets the conditions to fulfill, before the train starts driving to this station.
+    /// The time waited is measured from the departure at the previous station.
+    pub fn arrive_when(mut self, arrive: impl Into<WaitingNode<TrainAddr>>) -> Self {
+        self.arrive = Box::new(arrive.into());
+        self
+    }
+
+    /// Sets the conditions to fulfill, before the train leaves this station.
+    /// The time waited is measured from the arrival at this station.
+    pub fn depart_when(mut self, depart: impl Into<WaitingNode<TrainAddr>>) -> Self {
+        self.depart = Box::new(depart.into());
+        self
+    }
+
+    /// Reverses the train in this station, before it departs.
+    /// Only trains at bidirectional sensors or stations are reversed.
+    pub fn reverse(mut self) -> Self {
+        self.reverse = true;
+        self
+    }
+
+    pub fn destination(&self) -> NodeIndex {
+        self.destination
     }
 
-    pub fn could_depart(&self) -> bool {
-        self.depart.fulfills()
+    pub fn reverses(&self) -> bool {
+        self.reverse
+    }
+
+    pub fn could_arrive(&self, state: &WaitingState<TrainAddr>) -> bool {
+        self.arrive.fulfills(state)
+    }
+
+    pub fn could_depart(&self, state: &WaitingState<TrainAddr>) -> bool {
+        self.depart.fulfills(state)
+    }
+}
+
+/// The progress of a train through its timetable.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum TimetableStage {
+    /// Waits for the arrive conditions of the station since the given time
+    Arriving(Instant),
+    /// Drives to the station
+    Driving,
+    /// Waits in the station for its depart conditions since the given time
+    Departing(Instant),
+}
+
+/// The live state of the railroad, the waiting reasons of a station are checked against.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct WaitingState<TrainAddr: AddressType> {
+    /// The time the train waits already
+    pub waited: Duration,
+    /// The trains occupying the sensors at the given nodes
+    pub trains_on_sensors: HashSet<(Address<TrainAddr>, NodeIndex)>,
+    /// The trains standing at the given nodes
+    pub trains_holding: HashSet<(Address<TrainAddr>, NodeIndex)>,
+    /// The model time the train started waiting at
+    pub model_since: ModelTime,
+    /// The current model time of the fast clock
+    pub model_time: ModelTime,
+}
+
+impl<TrainAddr: AddressType> WaitingState<TrainAddr> {
+    pub fn new(waited: Duration) -> Self {
+        WaitingState {
+            waited,
+            trains_on_sensors: HashSet::new(),
+            trains_holding: HashSet::new(),
+            model_since: ModelTime::default(),
+            model_time: ModelTime::default(),
+        }
     }
 }
 
-trait Fulfiller {
-    fn fulfills(&self) -> bool;
+trait Fulfiller<TrainAddr: AddressType> {
+    fn fulfills(&self, state: &WaitingState<TrainAddr>) -> bool;
 }
 
-#[derive(Debug, Eq, PartialEq, Hash)]
-struct WaitingNode<Spd: SpeedType, TrainAddr: AddressType> {
+/// Connects waiting reasons and further nodes with an operator.
+///
+/// # Usage
+///
+/// ```
+/// # use std::time::Duration;
+/// # use petgraph::graph::NodeIndex;
+/// # use locologic::control::rail_system::components::Address;
+/// # use locologic::control::train::{Station, WaitingNode, WaitingReasons, WaitingState};
+/// // Departs after a minute, or as soon as train 2 holds at node 4.
+/// let depart = WaitingNode::any(vec![WaitingReasons::Time(Duration::from_secs(60))])
+///     .waiter(WaitingReasons::TrainHoldInStation(Address::new(2), NodeIndex::new(4)));
+/// let station = Station::<u16>::new(NodeIndex::new(3)).depart_when(depart);
+///
+/// let mut state = WaitingState::new(Duration::from_secs(10));
+/// assert!(!station.could_depart(&state));
+/// state.trains_holding.insert((Address::new(2), NodeIndex::new(4)));
+/// assert!(station.could_depart(&state));
+/// ```
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct WaitingNode<TrainAddr: AddressType> {
     connector: WaitingReasonOperator,
-    waiters: Vec<WaitingReasons<Spd, TrainAddr>>,
-    childs: Vec<WaitingNode<Spd, TrainAddr>>,
+    waiters: Vec<WaitingReasons<TrainAddr>>,
+    childs: Vec<WaitingNode<TrainAddr>>,
+}
+
+impl<TrainAddr: AddressType> Default for WaitingNode<TrainAddr> {
+    /// A node without conditions, that is always fulfilled.
+    fn default() -> Self {
+        WaitingNode::new(WaitingReasonOperator::AND)
+    }
+}
+
+impl<TrainAddr: AddressType> From<WaitingReasons<TrainAddr>> for WaitingNode<TrainAddr> {
+    fn from(reason: WaitingReasons<TrainAddr>) -> Self {
+        WaitingNode::all(vec![reason])
+    }
 }
 
-impl<Spd: SpeedType, TrainAddr: AddressType> Fulfiller for WaitingNode<Spd, TrainAddr> {
-    fn fulfills(&self) -> bool {
-        let result = self.check(self.waiters.iter());
+impl<TrainAddr: AddressType> Fulfiller<TrainAddr> for WaitingNode<TrainAddr> {
+    fn fulfills(&self, state: &WaitingState<TrainAddr>) -> bool {
+        let result = self.check(self.waiters.iter(), state);
 
         match self.connector {
-            WaitingReasonOperator::AND => result && self.check(self.childs.iter()),
-            WaitingReasonOperator::OR => result || self.check(self.childs.iter()),
-            WaitingReasonOperator::XOR => result ^ self.check(self.childs.iter()),
-            WaitingReasonOperator::XNOR => result == self.check(self.childs.iter()),
+            WaitingReasonOperator::AND => result && self.check(self.childs.iter(), state),
+            WaitingReasonOperator::OR => result || self.check(self.childs.iter(), state),
+            WaitingReasonOperator::XOR => result ^ self.check(self.childs.iter(), state),
+            WaitingReasonOperator::XNOR => result == self.check(self.childs.iter(), state),
         }
     }
 }
 
-impl<Spd: SpeedType, TrainAddr: AddressType> WaitingNode<Spd, TrainAddr> {
-    fn check<'t, I, F>(&self, mut fulfilled: I) -> bool
+impl<TrainAddr: AddressType> WaitingNode<TrainAddr> {
+    pub fn new(connector: WaitingReasonOperator) -> Self {
+        WaitingNode {
+            connector,
+            waiters: Vec::new(),
+            childs: Vec::new(),
+        }
+    }
+
+    /// Waits until all of the given reasons are fulfilled.
+    pub fn all(waiters: Vec<WaitingReasons<TrainAddr>>) -> Self {
+        WaitingNode {
+            waiters,
+            ..WaitingNode::new(WaitingReasonOperator::AND)
+        }
+    }
+
+    /// Waits until any of the given reasons is fulfilled.
+    pub fn any(waiters: Vec<WaitingReasons<TrainAddr>>) -> Self {
+        WaitingNode {
+            waiters,
+            ..WaitingNode::new(WaitingReasonOperator::OR)
+        }
+    }
+
+    pub fn waiter(mut self, waiter: WaitingReasons<TrainAddr>) -> Self {
+        self.waiters.push(waiter);
+        self
+    }
+
+    pub fn child(mut self, child: WaitingNode<TrainAddr>) -> Self {
+        self.childs.push(child);
+        self
+    }
+
+    pub fn connector(&self) -> WaitingReasonOperator {
+        self.connector
+    }
+
+    /// Returns the waiting reasons of this node and all of its children.
+    pub fn reasons(&self) -> Vec<&WaitingReasons<TrainAddr>> {
+        self.waiters
+            .iter()
+            .chain(self.childs.iter().flat_map(|child| child.reasons()))
+            .collect()
+    }
+
+    fn check<'t, I, F>(&self, mut fulfilled: I, state: &WaitingState<TrainAddr>) -> bool
     where
         I: Iterator<Item = &'t F>,
-        F: Fulfiller + 't,
+        F: Fulfiller<TrainAddr> + 't,
     {
         match self.connector {
-            WaitingReasonOperator::AND => fulfilled.all(|next| next.fulfills()),
-            WaitingReasonOperator::OR => fulfilled.any(|next| next.fulfills()),
-            WaitingReasonOperator::XOR => fulfilled.fold(false, |res, next| res ^ next.fulfills()),
+            WaitingReasonOperator::AND => fulfilled.all(|next| next.fulfills(state)),
+            WaitingReasonOperator::OR => fulfilled.any(|next| next.fulfills(state)),
+            WaitingReasonOperator::XOR => {
+                fulfilled.fold(false, |res, next| res ^ next.fulfills(state))
+            }
             WaitingReasonOperator::XNOR => fulfilled
-                .map(|f| f.fulfills())
+                .map(|f| f.fulfills(state))
                 .reduce(|acc, item| acc == item)
                 .unwrap_or(true),
         }
@@ -502,22 +1883,30 @@ pub enum WaitingReasonOperator {
 }
 
 /// For what the train should wait
-#[derive(Debug, Eq, PartialEq, Hash)]
-pub enum WaitingReasons<Spd: SpeedType, TrainAddr: AddressType> {
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum WaitingReasons<TrainAddr: AddressType> {
     /// Waits a specific time
     Time(Duration),
-    /// Waits until another train is on a specified sensor
-    TrainOnSensor(Train<Spd, TrainAddr>, Address),
-    /// Waits until another train holds on a specified sensor
-    TrainHoldInStation(Train<Spd, TrainAddr>, Address),
+    /// Waits until the fast clock shows the given time of day,
+    /// for the first time since the train started waiting
+    ModelTime(ModelTime),
+    /// Waits until a train occupies the sensor at the specified node
+    TrainOnSensor(Address<TrainAddr>, NodeIndex),
+    /// Waits until a train holds at the specified node
+    TrainHoldInStation(Address<TrainAddr>, NodeIndex),
 }
 
-impl<Spd: SpeedType, TrainAddr: AddressType> Fulfiller for WaitingReasons<Spd, TrainAddr> {
-    fn fulfills(&self) -> bool {
+impl<TrainAddr: AddressType> Fulfiller<TrainAddr> for WaitingReasons<TrainAddr> {
+    fn fulfills(&self, state: &WaitingState<TrainAddr>) -> bool {
         match self {
-            WaitingReasons::Time(duration) => duration.is_zero(),
-            WaitingReasons::TrainOnSensor(_train, _adr) => true,
-            WaitingReasons::TrainHoldInStation(_train, _adr) => true,
+            WaitingReasons::Time(duration) => state.waited >= *duration,
+            WaitingReasons::ModelTime(time) => state.model_since.next(*time) <= state.model_time,
+            WaitingReasons::TrainOnSensor(train, node) => {
+                state.trains_on_sensors.contains(&(*train, *node))
+            }
+            WaitingReasons::TrainHoldInStation(train, node) => {
+                state.trains_holding.contains(&(*train, *node))
+            }
         }
     }
 }
@@ -0,0 +1,485 @@
+use crate::control::messages::Message;
+use crate::control::rail_system::components::{
+    Address, Aspect, SLevel, Speed, Status, SwDir, TrainDirection,
+};
+use crate::control::rail_system::railroad::Railroad;
+use crate::control::rail_system::railroad_test::{
+    create_crossover_railroad, create_line_railroad, create_passing_ring_railroad,
+    create_ring_railroad, create_signal_switch_railroad, create_switch_railroad,
+    create_terminus_railroad, create_two_signals_railroad, start_simulation, TICK,
+};
+use crate::control::train::{
+    Station, WaitingNode, WaitingReasonOperator, WaitingReasons, WaitingState,
+};
+use petgraph::graph::NodeIndex;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+
+#[tokio::test]
+pub async fn test_train_waits_for_switches() {
+    let (r, [_first, _straight, curved]) = create_switch_railroad().await;
+    let mut messages = r.subscribe();
+    let mut train = r.get_train(&Address::new(1)).unwrap().lock().await;
+    assert!(train.trigger_drive_to(curved, r.clone()).await);
+
+    train.update(r.clone()).await;
+    assert!(train.stands());
+    assert_eq!(
+        messages.try_recv().unwrap(),
+        Message::Switch(Address::new(1), SwDir::Curved)
+    );
+
+    Railroad::handle_feedback(
+        r.clone(),
+        Message::SwitchAck(Address::new(1), SwDir::Curved),
+    )
+    .await;
+    train.update(r.clone()).await;
+    assert_eq!(train.speed(), Speed::Drive(128));
+}
+
+#[tokio::test(start_paused = true)]
+pub async fn test_signal_locks_route_switches() {
+    let (r, [_first, _signal, _straight, curved]) = create_signal_switch_railroad().await;
+    let mut messages = r.subscribe();
+    let train = r.get_train(&Address::new(1)).unwrap();
+    {
+        let mut train = train.lock().await;
+        assert!(train.trigger_drive_to(curved, r.clone()).await);
+        train.update(r.clone()).await;
+    }
+    tokio::time::sleep(TICK).await;
+
+    // The block is not granted, before the switch acknowledged the route.
+    let signal = r.get_signal_mutex(&Address::new(1)).unwrap();
+    let switch = r.get_switch_mutex(&Address::new(1)).unwrap();
+    assert!(!signal.lock().await.granted(Address::new(1)));
+    assert_eq!(switch.lock().await.locked_by(), Some(Address::new(1)));
+    let sent: Vec<_> = std::iter::from_fn(|| messages.try_recv().ok()).collect();
+    assert!(sent.contains(&Message::Switch(Address::new(1), SwDir::Curved)));
+    assert!(!sent
+        .iter()
+        .any(|message| matches!(message, Message::TrainGranted(..))));
+
+    Railroad::handle_feedback(
+        r.clone(),
+        Message::SwitchAck(Address::new(1), SwDir::Curved),
+    )
+    .await;
+    signal.lock().await.update(r.clone()).await;
+    assert!(signal.lock().await.granted(Address::new(1)));
+    let sent: Vec<_> = std::iter::from_fn(|| messages.try_recv().ok()).collect();
+    assert!(sent.contains(&Message::TrainGranted(Address::new(1), Address::new(1))));
+
+    // Other routes could not move the locked switch, until the train passed it.
+    assert!(!switch.lock().await.lock(Address::new(2), SwDir::Curved));
+    let tasks = start_simulation(&r).await;
+    tokio::time::sleep(Duration::from_secs(5)).await;
+    assert_eq!(train.lock().await.position(), curved);
+    assert_eq!(switch.lock().await.locked_by(), None);
+
+    tasks.iter().for_each(JoinHandle::abort);
+}
+
+#[tokio::test(start_paused = true)]
+pub async fn test_reset_position_requests_block() {
+    let (r, [_first, _second, _signal, third]) = create_line_railroad().await;
+    let mut train = r.get_train(&Address::new(1)).unwrap().lock().await;
+
+    // The train stays locked, while the signal grants it the block.
+    let reset = train.reset_position(third, r.clone());
+    assert!(tokio::time::timeout(Duration::from_secs(5), reset)
+        .await
+        .unwrap());
+    let signal = r.get_signal_mutex(&Address::new(1)).unwrap();
+    assert!(signal.lock().await.granted(Address::new(1)));
+}
+
+#[tokio::test(start_paused = true)]
+pub async fn test_reset_position_switches_route() {
+    let (r, [_first, _signal, _straight, curved]) = create_signal_switch_railroad().await;
+    let tasks = start_simulation(&r).await;
+    let mut train = r.get_train(&Address::new(1)).unwrap().lock().await;
+    assert!(train.trigger_drive_to(curved, r.clone()).await);
+
+    // The scheduler waits for the train, so the switch acknowledgement grants the block.
+    let reset = train.reset_position(curved, r.clone());
+    assert!(tokio::time::timeout(Duration::from_secs(5), reset)
+        .await
+        .unwrap());
+    let switch = r.get_switch_mutex(&Address::new(1)).unwrap();
+    assert_eq!(switch.lock().await.state().dir, SwDir::Curved);
+    assert_eq!(switch.lock().await.locked_by(), Some(Address::new(1)));
+
+    tasks.iter().for_each(JoinHandle::abort);
+}
+
+#[tokio::test(start_paused = true)]
+pub async fn test_signal_aspects() {
+    let (r, [_first, _signal, _straight, curved]) = create_signal_switch_railroad().await;
+    let mut messages = r.subscribe();
+    let train = r.get_train(&Address::new(1)).unwrap();
+    {
+        let mut train = train.lock().await;
+        assert!(train.trigger_drive_to(curved, r.clone()).await);
+        train.update(r.clone()).await;
+    }
+    tokio::time::sleep(TICK).await;
+    r.update_aspects().await;
+    assert_eq!(r.aspect(&Address::new(1)).await, Aspect::Stop);
+
+    // The route leads over the curved switch, so the signal limits the speed.
+    Railroad::handle_feedback(
+        r.clone(),
+        Message::SwitchAck(Address::new(1), SwDir::Curved),
+    )
+    .await;
+    let signal = r.get_signal_mutex(&Address::new(1)).unwrap();
+    signal.lock().await.update(r.clone()).await;
+    r.update_aspects().await;
+    let limited = Aspect::ProceedLimited(Speed::Drive(64));
+    assert_eq!(r.aspect(&Address::new(1)).await, limited);
+    let sent: Vec<_> = std::iter::from_fn(|| messages.try_recv().ok()).collect();
+    assert!(sent.contains(&Message::UpdateSignal(Address::new(1), limited)));
+
+    let tasks = start_simulation(&r).await;
+    let mut fastest = Speed::Stop;
+    for _ in 0..100 {
+        tokio::time::sleep(TICK).await;
+        fastest = fastest.max(train.lock().await.speed());
+    }
+    assert_eq!(fastest, Speed::Drive(64));
+    assert_eq!(train.lock().await.position(), curved);
+    assert_eq!(r.aspect(&Address::new(1)).await, Aspect::Stop);
+
+    tasks.iter().for_each(JoinHandle::abort);
+}
+
+#[tokio::test(start_paused = true)]
+pub async fn test_signal_expects_stop() {
+    let (r, [_first, second, third]) = create_two_signals_railroad().await;
+    let train = r.get_train(&Address::new(1)).unwrap();
+    assert!(train.lock().await.trigger_drive_to(third, r.clone()).await);
+    let tasks = start_simulation(&r).await;
+    tokio::time::sleep(TICK * 4).await;
+
+    // The second block is occupied, so the first signal announces the stop.
+    assert_eq!(r.aspect(&Address::new(1)).await, Aspect::ExpectStop);
+    assert_eq!(r.aspect(&Address::new(2)).await, Aspect::Stop);
+
+    tokio::time::sleep(Duration::from_secs(5)).await;
+    assert_eq!(train.lock().await.position(), second);
+    assert_eq!(r.aspect(&Address::new(2)).await, Aspect::Stop);
+
+    tasks.iter().for_each(JoinHandle::abort);
+}
+
+#[tokio::test(start_paused = true)]
+pub async fn test_route_flank_protection() {
+    let (r, [_a1, _a2, _b1, b2]) = create_crossover_railroad().await;
+    let tasks = start_simulation(&r).await;
+    let train = r.get_train(&Address::new(1)).unwrap();
+    assert!(train.lock().await.trigger_drive_to(b2, r.clone()).await);
+    tokio::time::sleep(TICK * 2).await;
+
+    // The crossover is closed, so no train on track A could run onto the route.
+    let merging = r.get_switch_mutex(&Address::new(2)).unwrap();
+    let diverging = r.get_switch_mutex(&Address::new(1)).unwrap();
+    assert_eq!(merging.lock().await.locked_by(), Some(Address::new(1)));
+    {
+        let mut diverging = diverging.lock().await;
+        assert!(diverging.protects(Address::new(1), Address::new(2)));
+        assert_eq!(diverging.state().dir, SwDir::Straight);
+        assert!(!diverging.lock(Address::new(2), SwDir::Curved));
+        // Trains staying on track A could still pass the switch.
+        assert!(diverging.lock(Address::new(2), SwDir::Straight));
+        diverging.unlock(Address::new(2));
+    }
+
+    tokio::time::sleep(Duration::from_secs(5)).await;
+    assert_eq!(train.lock().await.position(), b2);
+    assert_eq!(merging.lock().await.locked_by(), None);
+    assert!(!diverging
+        .lock()
+        .await
+        .protects(Address::new(1), Address::new(2)));
+
+    tasks.iter().for_each(JoinHandle::abort);
+}
+
+#[tokio::test(start_paused = true)]
+pub async fn test_deadlock_reported() {
+    let (r, [first, second]) = create_ring_railroad().await;
+    let mut messages = r.subscribe();
+    for (train, destination) in [(1, second), (2, first)] {
+        let train = r.get_train(&Address::new(train)).unwrap();
+        assert!(
+            train
+                .lock()
+                .await
+                .trigger_drive_to(destination, r.clone())
+                .await
+        );
+    }
+    let tasks = start_simulation(&r).await;
+    tokio::time::sleep(Duration::from_secs(1)).await;
+
+    // Each train waits for the block the other one stands in.
+    let mut deadlocks = r.deadlocks().await;
+    deadlocks.iter_mut().for_each(|deadlock| deadlock.sort());
+    assert_eq!(
+        deadlocks,
+        vec![vec![
+            (Address::new(1), Address::new(2)),
+            (Address::new(2), Address::new(1))
+        ]]
+    );
+    let reported: Vec<_> = std::iter::from_fn(|| messages.try_recv().ok())
+        .filter(|message| matches!(message, Message::TrainDeadlocked(..)))
+        .collect();
+    assert_eq!(reported.len(), 2);
+    assert!(reported.contains(&Message::TrainDeadlocked(Address::new(1), Address::new(2))));
+    assert!(reported.contains(&Message::TrainDeadlocked(Address::new(2), Address::new(1))));
+    assert_eq!(
+        r.get_train(&Address::new(1))
+            .unwrap()
+            .lock()
+            .await
+            .position(),
+        first
+    );
+
+    tasks.iter().for_each(JoinHandle::abort);
+}
+
+#[tokio::test(start_paused = true)]
+pub async fn test_deadlock_rerouted() {
+    let (r, [first, second, third, fourth]) = create_passing_ring_railroad().await;
+    for (train, destination) in [(1, fourth), (2, second), (3, first)] {
+        let train = r.get_train(&Address::new(train)).unwrap();
+        assert!(
+            train
+                .lock()
+                .await
+                .trigger_drive_to(destination, r.clone())
+                .await
+        );
+    }
+    let train = r.get_train(&Address::new(1)).unwrap();
+    let passes_third = || async {
+        let train = train.lock().await;
+        train
+            .route()
+            .unwrap()
+            .iter()
+            .any(|(node, _)| *node == third)
+    };
+    assert!(!passes_third().await);
+    let tasks = start_simulation(&r).await;
+    tokio::time::sleep(Duration::from_secs(1)).await;
+
+    // Train 1 takes the detour, so train 2 could leave the block train 1 waits for.
+    assert!(passes_third().await);
+    let signal = r.get_signal_mutex(&Address::new(2)).unwrap();
+    assert!(!signal
+        .lock()
+        .await
+        .state()
+        .requesters
+        .contains(&Address::new(1)));
+
+    tokio::time::sleep(Duration::from_secs(60)).await;
+    assert!(r.deadlocks().await.is_empty());
+    for (train, position) in [(1, fourth), (2, second), (3, first)] {
+        let train = r.get_train(&Address::new(train)).unwrap();
+        assert_eq!(train.lock().await.position(), position);
+    }
+
+    tasks.iter().for_each(JoinHandle::abort);
+}
+
+#[tokio::test(start_paused = true)]
+pub async fn test_train_drives_route() {
+    let (r, [_first, _second, _signal, third]) = create_line_railroad().await;
+    let tasks = start_simulation(&r).await;
+
+    let train = r.get_train(&Address::new(1)).unwrap();
+    assert!(train.lock().await.trigger_drive_to(third, r.clone()).await);
+    tokio::time::sleep(Duration::from_secs(5)).await;
+
+    {
+        let train = train.lock().await;
+        assert_eq!(train.position(), third);
+        assert_eq!(train.route(), None);
+        assert!(train.stands());
+        assert_eq!(train.occupied().collect::<Vec<_>>(), vec![third]);
+    }
+
+    // The train passed the signal and left the first sensors behind.
+    let signal = r.get_signal_mutex(&Address::new(1)).unwrap().lock().await;
+    assert_eq!(signal.status(), Status::Free);
+    assert!(!signal.granted(Address::new(1)));
+    for sensor in [1, 2] {
+        let sensor = r.get_sensor_mutex(&Address::new(sensor)).unwrap();
+        assert_eq!(sensor.lock().await.train(), &None);
+    }
+    let sensor = r.get_sensor_mutex(&Address::new(3)).unwrap().lock().await;
+    assert_eq!(sensor.train(), &Some(Address::new(1)));
+    assert_eq!(sensor.state().level, SLevel::Occupied);
+
+    tasks.iter().for_each(JoinHandle::abort);
+}
+
+#[tokio::test(start_paused = true)]
+pub async fn test_train_reverses_on_route() {
+    let (r, [first, second], _buffer) = create_terminus_railroad().await;
+    let tasks = start_simulation(&r).await;
+
+    // The train drives to the second sensor and reverses there.
+    let train = r.get_train(&Address::new(1)).unwrap();
+    {
+        let mut train = train.lock().await;
+        assert!(train.trigger_drive_to(second.1, r.clone()).await);
+        assert!(train.route().unwrap().contains(&(second.0, false)));
+    }
+    tokio::time::sleep(Duration::from_secs(3)).await;
+    {
+        let train = train.lock().await;
+        assert_eq!(train.position(), second.1);
+        assert_eq!(train.direction(), TrainDirection::Backward);
+        assert_eq!(train.route(), None);
+    }
+
+    assert!(
+        train
+            .lock()
+            .await
+            .trigger_drive_to(first.1, r.clone())
+            .await
+    );
+    tokio::time::sleep(Duration::from_secs(3)).await;
+    let train = train.lock().await;
+    assert_eq!(train.position(), first.1);
+    assert_eq!(train.direction(), TrainDirection::Backward);
+    assert_eq!(train.route(), None);
+    assert!(train.stands());
+
+    tasks.iter().for_each(JoinHandle::abort);
+}
+
+#[test]
+pub fn test_waiting_reasons() {
+    let node = NodeIndex::new(1);
+    let on_sensor = WaitingReasons::TrainOnSensor(Address::new(2), node);
+    let holding = WaitingReasons::TrainHoldInStation(Address::new(2), node);
+    let station = Station::<u16>::new(node)
+        .arrive_when(on_sensor)
+        .depart_when(
+            WaitingNode::new(WaitingReasonOperator::XOR)
+                .waiter(WaitingReasons::Time(Duration::from_secs(10)))
+                .child(WaitingNode::all(vec![holding])),
+        );
+
+    let mut state = WaitingState::new(Duration::from_secs(5));
+    assert!(Station::<u16>::new(node).could_arrive(&state));
+    assert!(!station.could_arrive(&state));
+    assert!(!station.could_depart(&state));
+
+    state.trains_on_sensors.insert((Address::new(2), node));
+    state.trains_holding.insert((Address::new(2), node));
+    assert!(station.could_arrive(&state));
+    assert!(station.could_depart(&state));
+
+    // Either the time passed or the train holds, but not both.
+    state.waited = Duration::from_secs(10);
+    assert!(!station.could_depart(&state));
+}
+
+#[tokio::test(start_paused = true)]
+pub async fn test_train_runs_timetable() {
+    let (r, [first, second], _buffer) = create_terminus_railroad().await;
+    let tasks = start_simulation(&r).await;
+
+    // The train waits five seconds in the second station, before it returns.
+    let train = r.get_train(&Address::new(1)).unwrap();
+    train.lock().await.set_timetable(
+        vec![
+            Station::new(second.1).depart_when(WaitingNode::all(vec![
+                WaitingReasons::TrainHoldInStation(Address::new(1), second.1),
+                WaitingReasons::Time(Duration::from_secs(5)),
+            ])),
+            Station::new(first.1),
+        ],
+        false,
+    );
+    tokio::time::sleep(Duration::from_secs(3)).await;
+    {
+        let train = train.lock().await;
+        assert_eq!(train.position(), second.1);
+        assert!(train.stands());
+        assert_eq!(train.timetable_station(), Some(0));
+    }
+
+    tokio::time::sleep(Duration::from_secs(6)).await;
+    let train = train.lock().await;
+    assert_eq!(train.position(), first.1);
+    assert!(train.stands());
+    assert_eq!(train.timetable_station(), None);
+
+    tasks.iter().for_each(JoinHandle::abort);
+}
+
+#[tokio::test(start_paused = true)]
+pub async fn test_train_shuttles() {
+    let (r, [first, second], buffer) = create_terminus_railroad().await;
+    let tasks = start_simulation(&r).await;
+
+    let train = r.get_train(&Address::new(1)).unwrap();
+    assert!(
+        !train
+            .lock()
+            .await
+            .start_shuttle(buffer, Duration::from_secs(1), r.clone())
+            .await
+    );
+    assert!(
+        train
+            .lock()
+            .await
+            .start_shuttle(second.0, Duration::from_secs(1), r.clone())
+            .await
+    );
+
+    // The train drives to the second sensor, turns there, and returns to the first one.
+    let mut visited = vec![(first.0, TrainDirection::Forward)];
+    for _ in 0..100 {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let train = train.lock().await;
+        let stop = (train.position(), train.direction());
+        if visited.last() != Some(&stop) {
+            visited.push(stop);
+        }
+    }
+    assert_eq!(
+        visited[..6],
+        [
+            (first.0, TrainDirection::Forward),
+            (second.0, TrainDirection::Forward),
+            (second.1, TrainDirection::Backward),
+            (first.1, TrainDirection::Backward),
+            (first.0, TrainDirection::Forward),
+            (second.0, TrainDirection::Forward),
+        ]
+    );
+
+    // After stopping the shuttle, the train stays where it arrives next.
+    train.lock().await.stop_timetable();
+    tokio::time::sleep(Duration::from_secs(5)).await;
+    let position = train.lock().await.position();
+    tokio::time::sleep(Duration::from_secs(5)).await;
+    let train = train.lock().await;
+    assert_eq!(train.position(), position);
+    assert!(train.stands());
+
+    tasks.iter().for_each(JoinHandle::abort);
+}
@@ -1,12 +1,27 @@
 use crate::control::rail_system::components::Speed;
-use num_traits::{CheckedAdd, CheckedSub};
+use num_traits::{CheckedAdd, CheckedSub, NumCast, ToPrimitive};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
 use std::hash::Hash;
 
 pub type DefaultAddressType = u16;
 pub type DefaultSpeedType = u8;
 
 pub trait SpeedType:
-    Copy + Clone + Eq + Hash + Ord + Send + Sync + CheckedAdd + CheckedSub + 'static
+    Copy
+    + Clone
+    + Eq
+    + Hash
+    + Ord
+    + Send
+    + Sync
+    + CheckedAdd
+    + CheckedSub
+    + ToPrimitive
+    + NumCast
+    + Serialize
+    + DeserializeOwned
+    + 'static
 {
     fn sub_to_speed(&self, other: &Self) -> Speed<Self> {
         self.checked_sub(other)
@@ -16,7 +31,10 @@ pub trait SpeedType:
     fn default_acceleration() -> Self;
 }
 
-pub trait AddressType: Copy + Clone + Eq + Hash + Send + Sync + Ord + 'static {}
+pub trait AddressType:
+    Copy + Clone + Eq + Hash + Send + Sync + Ord + Serialize + DeserializeOwned + 'static
+{
+}
 
 impl SpeedType for u8 {
     fn default_acceleration() -> Self {